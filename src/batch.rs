@@ -0,0 +1,292 @@
+// The `cat-file --batch` / `--batch-check` protocol for reading many objects in one process
+use std::io::{self, BufRead, Read, Write};
+
+use anyhow::anyhow;
+use serde_json::json;
+
+use crate::{error::GitrsError, object::GitrsObject, repository::Repository};
+
+/// Format used when `--batch`/`--batch-check` are given without one.
+pub const DEFAULT_FORMAT: &str = "%(objectname) %(objecttype) %(objectsize)";
+
+/// Options to control what a batch run reads and prints.
+pub struct BatchOptions {
+    /// Format of the header line printed for each object
+    pub format: String,
+    /// Print each object's content after its header (`--batch`)
+    pub contents: bool,
+    /// Report every object in the repository instead of reading names from the input
+    pub all_objects: bool,
+    /// Write each record as a line of JSON instead, ignoring the format
+    pub json: bool,
+}
+
+/// A piece of a `--batch-check=<format>` string.
+enum Atom {
+    Literal(String),
+    ObjectName,
+    ObjectType,
+    ObjectSize,
+    /// The text after the object name on the input line
+    Rest,
+}
+
+/// Reads object names from `input`, one per line, and writes a record for each to `output`.
+///
+/// Each record is the header line expanded from the format, followed for `--batch` by the raw
+/// content and a newline. Names that don't resolve produce `<name> missing`, and abbreviations
+/// matching several objects `<name> ambiguous`; I/O errors and corrupt objects end the run.
+/// Output is flushed after every record so callers can interleave requests and responses over
+/// a pipe.
+///
+/// With `json`, each record is instead a single line holding a JSON object with the name, hash,
+/// type, size and (for `--batch`) content, or `"missing": true`/`"ambiguous": true`.
+///
+/// `all_objects` fails if the repository has packfiles, as gitrs can't read them.
+pub fn run<R: BufRead, W: Write>(
+    repository: &Repository,
+    options: &BatchOptions,
+    input: &mut R,
+    output: &mut W,
+) -> anyhow::Result<()> {
+    let format = parse_format(&options.format)?;
+
+    if options.all_objects {
+        for sha in GitrsObject::list_all(repository)? {
+            write_record(repository, &format, options, &sha, "", output)?;
+        }
+        return Ok(());
+    }
+
+    // Like git, only split off the rest of the line when the format asks for it, so names
+    // containing spaces (e.g. `HEAD:some file`) still work otherwise
+    let wants_rest = format.iter().any(|atom| matches!(atom, Atom::Rest));
+
+    for line in input.lines() {
+        let line = line?;
+        let (name, rest) = match line.split_once(char::is_whitespace) {
+            Some((name, rest)) if wants_rest => (name, rest.trim_start()),
+            _ => (line.as_str(), ""),
+        };
+        write_record(repository, &format, options, name, rest, output)?;
+    }
+
+    Ok(())
+}
+
+fn write_record<W: Write>(
+    repository: &Repository,
+    format: &[Atom],
+    options: &BatchOptions,
+    name: &str,
+    rest: &str,
+    output: &mut W,
+) -> anyhow::Result<()> {
+    let sha = match GitrsObject::find(repository, name, None) {
+        Ok(sha) => sha,
+        Err(error) => {
+            let status = match GitrsError::find(&error) {
+                Some(GitrsError::AmbiguousName { .. }) => "ambiguous",
+                Some(GitrsError::CorruptObject { .. }) => return Err(error),
+                _ if error.chain().any(|cause| cause.is::<io::Error>()) => return Err(error),
+                _ => "missing",
+            };
+            if options.json {
+                writeln!(output, "{}", json!({ "name": name, status: true }))?;
+            } else {
+                writeln!(output, "{} {}", name, status)?;
+            }
+            output.flush()?;
+            return Ok(());
+        }
+    };
+    let mut reader = GitrsObject::open(repository, &sha)?;
+
+    if options.json {
+        let mut record = json!({
+            "name": name,
+            "hash": sha,
+            "type": reader.object_type.to_string(),
+            "size": reader.size,
+        });
+        if options.contents {
+            let mut content = Vec::new();
+            reader.read_to_end(&mut content)?;
+            record["content"] = json!(String::from_utf8_lossy(&content));
+        }
+        writeln!(output, "{}", record)?;
+    } else {
+        for atom in format {
+            match atom {
+                Atom::Literal(text) => write!(output, "{}", text)?,
+                Atom::ObjectName => write!(output, "{}", sha)?,
+                Atom::ObjectType => write!(output, "{}", reader.object_type)?,
+                Atom::ObjectSize => write!(output, "{}", reader.size)?,
+                Atom::Rest => write!(output, "{}", rest)?,
+            }
+        }
+        writeln!(output)?;
+
+        if options.contents {
+            io::copy(&mut reader, output)?;
+            writeln!(output)?;
+        }
+    }
+
+    output.flush()?;
+    Ok(())
+}
+
+/// Splits a format string into literals and `%(atom)` placeholders.
+fn parse_format(format: &str) -> anyhow::Result<Vec<Atom>> {
+    let mut atoms = Vec::new();
+    let mut rest = format;
+
+    while let Some(start) = rest.find("%(") {
+        if start > 0 {
+            atoms.push(Atom::Literal(rest[..start].to_string()));
+        }
+
+        let end = rest[start..]
+            .find(')')
+            .map(|i| i + start)
+            .ok_or_else(|| anyhow!("Unterminated format atom in '{}'", format))?;
+
+        atoms.push(match &rest[start + 2..end] {
+            "objectname" => Atom::ObjectName,
+            "objecttype" => Atom::ObjectType,
+            "objectsize" => Atom::ObjectSize,
+            "rest" => Atom::Rest,
+            other => return Err(anyhow!("Unknown format element: {}", other)),
+        });
+        rest = &rest[end + 1..];
+    }
+
+    if !rest.is_empty() {
+        atoms.push(Atom::Literal(rest.to_string()));
+    }
+
+    Ok(atoms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{object::ObjectType, testing::TempRepo};
+    use std::fs;
+
+    fn check(repository: &Repository, json: bool, input: &str) -> anyhow::Result<String> {
+        let options = BatchOptions {
+            format: DEFAULT_FORMAT.to_string(),
+            contents: false,
+            all_objects: false,
+            json,
+        };
+        batch(repository, &options, input)
+    }
+
+    fn check_format(repository: &Repository, format: &str, input: &str) -> anyhow::Result<String> {
+        let options = BatchOptions {
+            format: format.to_string(),
+            contents: false,
+            all_objects: false,
+            json: false,
+        };
+        batch(repository, &options, input)
+    }
+
+    fn batch(
+        repository: &Repository,
+        options: &BatchOptions,
+        input: &str,
+    ) -> anyhow::Result<String> {
+        let mut output = Vec::new();
+        run(repository, options, &mut input.as_bytes(), &mut output)?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn reports_missing_and_ambiguous_names() {
+        let repo = TempRepo::new();
+        // Both hash to 6bb2...
+        let first = GitrsObject::hash_raw(b"195\n", &ObjectType::Blob, Some(&repo)).unwrap();
+        let second = GitrsObject::hash_raw(b"389\n", &ObjectType::Blob, Some(&repo)).unwrap();
+        assert_eq!(first[..4], second[..4]);
+
+        let output = check(&repo, false, &format!("{}\n6bb2\nnosuch\n", first)).unwrap();
+        assert_eq!(
+            output,
+            format!("{} blob 4\n6bb2 ambiguous\nnosuch missing\n", first)
+        );
+
+        let output = check(&repo, true, "6bb2\nnosuch\n").unwrap();
+        assert_eq!(
+            output,
+            "{\"ambiguous\":true,\"name\":\"6bb2\"}\n{\"missing\":true,\"name\":\"nosuch\"}\n"
+        );
+    }
+
+    #[test]
+    fn corrupt_objects_end_the_run() {
+        let repo = TempRepo::new();
+        let sha = "0123456789abcdef0123456789abcdef01234567";
+        let dir = repo.gitdir.join("objects").join(&sha[..2]);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(&sha[2..]), b"not zlib").unwrap();
+
+        assert!(check(&repo, false, &format!("{}\n", sha)).is_err());
+    }
+
+    #[test]
+    fn formats_with_rest_and_literals() {
+        let repo = TempRepo::new();
+        let sha = GitrsObject::hash_raw(b"", &ObjectType::Blob, Some(&repo)).unwrap();
+
+        let output = check_format(
+            &repo,
+            "[%(objecttype)] %(rest)|%(objectsize)",
+            &format!("{sha}   keep  this \n{sha}\n"),
+        )
+        .unwrap();
+        assert_eq!(output, "[blob] keep  this |0\n[blob] |0\n");
+
+        // Without %(rest) the whole line is the name
+        let output = check_format(&repo, "%(objectname)", &format!("{sha} extra\n")).unwrap();
+        assert_eq!(output, format!("{sha} extra missing\n"));
+    }
+
+    #[test]
+    fn bad_formats_are_rejected() {
+        let repo = TempRepo::new();
+        for format in ["%(objectname) %(deltabase)", "%(objectname", "x %("] {
+            assert!(check_format(&repo, format, "").is_err(), "{format:?}");
+        }
+        // A `%` not followed by `(` is literal text
+        assert_eq!(check_format(&repo, "100% %s", "").unwrap(), "");
+    }
+
+    #[test]
+    fn all_objects_fails_instead_of_skipping_packs() {
+        let repo = TempRepo::new();
+        let first = GitrsObject::hash_raw(b"b\n", &ObjectType::Blob, Some(&repo)).unwrap();
+        let second = GitrsObject::hash_raw(b"a\n", &ObjectType::Blob, Some(&repo)).unwrap();
+        let options = BatchOptions {
+            format: "%(objectname)".to_string(),
+            contents: false,
+            all_objects: true,
+            json: false,
+        };
+
+        let mut expected = [first, second];
+        expected.sort();
+        assert_eq!(
+            batch(&repo, &options, "").unwrap(),
+            format!("{}\n{}\n", expected[0], expected[1])
+        );
+
+        let pack = repo.gitdir.join("objects/pack");
+        fs::create_dir_all(&pack).unwrap();
+        fs::write(pack.join("pack-1234.pack"), b"PACK").unwrap();
+        assert!(batch(&repo, &options, "").is_err());
+    }
+}
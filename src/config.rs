@@ -0,0 +1,1030 @@
+// Git-style INI configuration files: parsing, layered lookup and comment-preserving edits
+use std::{
+    env, fmt, fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, anyhow, bail, ensure};
+use log::debug;
+
+use crate::{
+    ignore::wildmatch,
+    repository::{self, Repository},
+};
+
+/// Maximum depth of nested `include.path` directives.
+const MAX_INCLUDE_DEPTH: usize = 10;
+
+/// Which configuration file a value was read from, in increasing order of precedence.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ConfigScope {
+    System,
+    Global,
+    Local,
+    /// A file given explicitly (e.g. `config --file`)
+    File,
+}
+
+/// A single `key = value` assignment.
+#[derive(Clone, Debug)]
+pub struct ConfigEntry {
+    /// Section name, lowercased
+    pub section: String,
+    /// Subsection name, case-sensitive
+    pub subsection: Option<String>,
+    /// Key name, lowercased
+    pub key: String,
+    /// `None` for a bare key (which counts as boolean `true`)
+    pub value: Option<String>,
+    /// File the entry was read from
+    pub origin: PathBuf,
+    pub scope: ConfigScope,
+}
+
+/// Merged view of all configuration files, later entries taking precedence.
+#[derive(Default)]
+pub struct Config {
+    entries: Vec<ConfigEntry>,
+}
+
+/// A single configuration file, kept as raw lines so edits preserve comments and formatting.
+pub struct ConfigFile {
+    path: PathBuf,
+    lines: Vec<String>,
+    sections: Vec<SectionSpan>,
+    entries: Vec<EntrySpan>,
+}
+
+/// Location of a section header within a `ConfigFile`.
+struct SectionSpan {
+    section: String,
+    subsection: Option<String>,
+    /// Last line belonging to the section (its header or its last entry)
+    last_line: usize,
+}
+
+/// Location of an entry within a `ConfigFile`.
+struct EntrySpan {
+    section: String,
+    subsection: Option<String>,
+    key: String,
+    value: Option<String>,
+    start_line: usize,
+    end_line: usize,
+    /// Text preceding the key on its first line (indentation, or a section header)
+    prefix: String,
+}
+
+/// A parsed `section[.subsection].key` variable name.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ConfigName {
+    pub section: String,
+    pub subsection: Option<String>,
+    pub key: String,
+}
+
+impl ConfigEntry {
+    /// Returns the canonical `section[.subsection].key` name of the entry.
+    pub fn name(&self) -> String {
+        match &self.subsection {
+            Some(subsection) => format!("{}.{}.{}", self.section, subsection, self.key),
+            None => format!("{}.{}", self.section, self.key),
+        }
+    }
+
+    fn is(&self, name: &ConfigName) -> bool {
+        self.section == name.section && self.subsection == name.subsection && self.key == name.key
+    }
+}
+
+impl ConfigName {
+    /// Parses a variable name. Section and key are case-insensitive, the subsection is not.
+    pub fn parse(name: &str) -> anyhow::Result<Self> {
+        let (section, rest) = name
+            .split_once('.')
+            .ok_or_else(|| anyhow!("Key does not contain a section: {}", name))?;
+        let (subsection, key) = match rest.rsplit_once('.') {
+            Some((subsection, key)) => (Some(subsection.to_string()), key),
+            None => (None, rest),
+        };
+
+        ensure!(
+            !section.is_empty()
+                && section
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-'),
+            "Invalid section name: {}",
+            name
+        );
+        ensure!(
+            key.starts_with(|c: char| c.is_ascii_alphabetic())
+                && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'),
+            "Invalid key name: {}",
+            name
+        );
+
+        Ok(Self {
+            section: section.to_ascii_lowercase(),
+            subsection,
+            key: key.to_ascii_lowercase(),
+        })
+    }
+}
+
+impl fmt::Display for ConfigScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ConfigScope::System => "system",
+            ConfigScope::Global => "global",
+            ConfigScope::Local => "local",
+            ConfigScope::File => "command",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/////////////////////////////////////
+// Layered lookup
+/////////////////////////////////////
+
+impl Config {
+    /// Reads the system, global and (if given) repository-local configuration, in that order of
+    /// increasing precedence. Missing files are skipped.
+    pub fn read(repository: Option<&Repository>) -> anyhow::Result<Self> {
+        let mut config = Self::default();
+        let gitdir = repository.map(|repository| repository.gitdir.as_path());
+
+        for scope in [ConfigScope::System, ConfigScope::Global, ConfigScope::Local] {
+            for path in Self::scope_paths(scope, repository) {
+                if path.is_file() {
+                    config.load(&path, scope, gitdir, 0)?;
+                }
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Reads a single configuration file (and the files it includes).
+    pub fn read_file(
+        path: &Path,
+        scope: ConfigScope,
+        repository: Option<&Repository>,
+    ) -> anyhow::Result<Self> {
+        let mut config = Self::default();
+        if path.is_file() {
+            config.load(
+                path,
+                scope,
+                repository.map(|repository| repository.gitdir.as_path()),
+                0,
+            )?;
+        }
+        Ok(config)
+    }
+
+    /// Returns the files read for a scope, in the order they are read.
+    ///
+    /// `GIT_CONFIG_SYSTEM`/`GIT_CONFIG_GLOBAL` override the system and global locations and
+    /// `GIT_CONFIG_NOSYSTEM` skips the system file, which keeps tests hermetic.
+    pub fn scope_paths(scope: ConfigScope, repository: Option<&Repository>) -> Vec<PathBuf> {
+        match scope {
+            ConfigScope::System if env::var_os("GIT_CONFIG_NOSYSTEM").is_some() => vec![],
+            ConfigScope::System => vec![
+                env::var_os("GIT_CONFIG_SYSTEM")
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|| PathBuf::from("/etc/gitconfig")),
+            ],
+            ConfigScope::Global => match env::var_os("GIT_CONFIG_GLOBAL") {
+                Some(path) => vec![PathBuf::from(path)],
+                None => {
+                    let xdg = xdg_config_home().map(|dir| dir.join("git").join("config"));
+                    let home =
+                        env::var_os("HOME").map(|home| PathBuf::from(home).join(".gitconfig"));
+                    xdg.into_iter().chain(home).collect()
+                }
+            },
+            ConfigScope::Local => repository
+                .map(|repository| vec![repository.gitdir.join("config")])
+                .unwrap_or_default(),
+            ConfigScope::File => vec![],
+        }
+    }
+
+    /// Returns the file that writes to a scope go to.
+    pub fn scope_file(scope: ConfigScope, repository: Option<&Repository>) -> Option<PathBuf> {
+        match scope {
+            ConfigScope::Global if env::var_os("GIT_CONFIG_GLOBAL").is_none() => {
+                env::var_os("HOME").map(|home| PathBuf::from(home).join(".gitconfig"))
+            }
+            _ => Self::scope_paths(scope, repository).pop(),
+        }
+    }
+
+    /// Returns every entry in order of increasing precedence.
+    pub fn entries(&self) -> &[ConfigEntry] {
+        &self.entries
+    }
+
+    /// Returns the effective (last) value of a variable. Bare keys yield an empty string.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        let name = ConfigName::parse(name).ok()?;
+        self.entries
+            .iter()
+            .rev()
+            .find(|entry| entry.is(&name))
+            .map(|entry| entry.value.as_deref().unwrap_or(""))
+    }
+
+    /// Returns every value of a multi-valued variable, in order, with `None` for bare keys.
+    pub fn get_all(&self, name: &str) -> Vec<Option<&str>> {
+        let Ok(name) = ConfigName::parse(name) else {
+            return vec![];
+        };
+        self.entries
+            .iter()
+            .filter(|entry| entry.is(&name))
+            .map(|entry| entry.value.as_deref())
+            .collect()
+    }
+
+    /// Returns the effective value of a variable interpreted as a boolean.
+    pub fn get_bool(&self, name: &str) -> anyhow::Result<Option<bool>> {
+        let Ok(parsed) = ConfigName::parse(name) else {
+            return Ok(None);
+        };
+        self.entries
+            .iter()
+            .rev()
+            .find(|entry| entry.is(&parsed))
+            .map(|entry| match &entry.value {
+                None => Ok(true),
+                Some(value) => parse_bool(value)
+                    .ok_or_else(|| anyhow!("Bad boolean config value '{}' for '{}'", value, name)),
+            })
+            .transpose()
+    }
+
+    /// Returns the effective value of a variable interpreted as an integer, honouring `k`, `m`
+    /// and `g` suffixes.
+    pub fn get_int(&self, name: &str) -> anyhow::Result<Option<i64>> {
+        self.get(name)
+            .map(|value| {
+                parse_int(value)
+                    .ok_or_else(|| anyhow!("Bad numeric config value '{}' for '{}'", value, name))
+            })
+            .transpose()
+    }
+
+    /// Returns the effective value of a variable interpreted as a path (expanding `~/`).
+    pub fn get_path(&self, name: &str) -> Option<PathBuf> {
+        self.get(name).map(expand_path)
+    }
+
+    /// Parses a file and appends its entries, expanding includes in place.
+    fn load(
+        &mut self,
+        path: &Path,
+        scope: ConfigScope,
+        gitdir: Option<&Path>,
+        depth: usize,
+    ) -> anyhow::Result<()> {
+        ensure!(
+            depth <= MAX_INCLUDE_DEPTH,
+            "Exceeded maximum include depth ({}) while including {}",
+            MAX_INCLUDE_DEPTH,
+            path.display()
+        );
+        debug!("Reading config file {:?}", path.display());
+
+        let file = ConfigFile::read(path)?;
+        let base = path.parent().unwrap_or(Path::new("."));
+
+        for span in file.entries {
+            let include = match (&span.section[..], &span.subsection, &span.key[..]) {
+                ("include", None, "path") => span.value.clone(),
+                ("includeif", Some(condition), "path")
+                    if include_condition_holds(condition, base, gitdir) =>
+                {
+                    span.value.clone()
+                }
+                _ => None,
+            };
+
+            self.entries.push(ConfigEntry {
+                section: span.section,
+                subsection: span.subsection,
+                key: span.key,
+                value: span.value,
+                origin: path.to_path_buf(),
+                scope,
+            });
+
+            if let Some(include) = include {
+                let target = base.join(expand_path(&include));
+                if target.is_file() {
+                    self.load(&target, scope, gitdir, depth + 1)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Evaluates an `includeIf` condition. Only `gitdir:` and `gitdir/i:` are supported.
+fn include_condition_holds(condition: &str, base: &Path, gitdir: Option<&Path>) -> bool {
+    let Some(gitdir) = gitdir else {
+        return false;
+    };
+
+    let (pattern, case_insensitive) = if let Some(pattern) = condition.strip_prefix("gitdir:") {
+        (pattern, false)
+    } else if let Some(pattern) = condition.strip_prefix("gitdir/i:") {
+        (pattern, true)
+    } else {
+        return false;
+    };
+
+    let mut pattern = if let Some(rest) = pattern.strip_prefix("./") {
+        base.join(rest).to_string_lossy().into_owned()
+    } else if pattern.starts_with('/') || pattern.starts_with("~/") {
+        expand_path(pattern).to_string_lossy().into_owned()
+    } else {
+        format!("**/{}", pattern)
+    };
+    if pattern.ends_with('/') {
+        pattern.push_str("**");
+    }
+
+    let mut gitdir = gitdir.to_string_lossy().into_owned();
+    if case_insensitive {
+        pattern = pattern.to_lowercase();
+        gitdir = gitdir.to_lowercase();
+    }
+
+    // Patterns may name either the gitdir itself or a directory containing it
+    wildmatch(pattern.as_bytes(), gitdir.as_bytes())
+        || wildmatch(pattern.as_bytes(), format!("{}/", gitdir).as_bytes())
+}
+
+/////////////////////////////////////
+// Single file parsing and editing
+/////////////////////////////////////
+
+impl ConfigFile {
+    /// Reads and parses a file. A missing file is treated as empty.
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", path.display()));
+            }
+        };
+        Self::parse(path, &text)
+    }
+
+    /// Parses configuration text, attributing errors to `path`.
+    pub fn parse(path: &Path, text: &str) -> anyhow::Result<Self> {
+        let mut file = Self {
+            path: path.to_path_buf(),
+            lines: text.lines().map(str::to_string).collect(),
+            sections: Vec::new(),
+            entries: Vec::new(),
+        };
+
+        Parser::new(text)
+            .run(&mut file)
+            .with_context(|| format!("Bad config file {}", path.display()))?;
+
+        Ok(file)
+    }
+
+    /// Writes the file back to disk, through `<file>.lock` like git.
+    pub fn save(&self) -> anyhow::Result<()> {
+        let mut text = self.lines.join("\n");
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        repository::write_locked(&self.path, |file| file.write_all(text.as_bytes()))
+    }
+
+    /// Sets a variable. With `add`, a new value is appended even if the variable exists;
+    /// otherwise a single existing value is replaced in place.
+    pub fn set(&mut self, name: &str, value: &str, add: bool) -> anyhow::Result<()> {
+        let parsed = ConfigName::parse(name)?;
+        let matching: Vec<usize> = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, span)| span.is(&parsed))
+            .map(|(i, _)| i)
+            .collect();
+
+        let key = name.rsplit('.').next().unwrap_or(name);
+        match (add, matching.as_slice()) {
+            (false, [i]) => {
+                let span = &self.entries[*i];
+                let indent = if span.prefix.trim().is_empty() {
+                    span.prefix.clone()
+                } else {
+                    format!("{} ", span.prefix.trim_end())
+                };
+                let line = format!("{}{} = {}", indent, key, format_value(value));
+                self.lines.splice(span.start_line..=span.end_line, [line]);
+            }
+            (false, [_, _, ..]) => bail!("Cannot overwrite multiple values with a single value"),
+            _ => {
+                let line = format!("\t{} = {}", key, format_value(value));
+                let after = matching
+                    .last()
+                    .map(|&i| self.entries[i].end_line)
+                    .or_else(|| {
+                        self.sections
+                            .iter()
+                            .rev()
+                            .find(|section| {
+                                section.section == parsed.section
+                                    && section.subsection == parsed.subsection
+                            })
+                            .map(|section| section.last_line)
+                    });
+
+                match after {
+                    Some(after) => self.lines.insert(after + 1, line),
+                    None => {
+                        self.lines.push(format_section_header(&parsed));
+                        self.lines.push(line);
+                    }
+                }
+            }
+        }
+
+        self.reparse()
+    }
+
+    /// Removes a variable. Fails if it has multiple values unless `all` is set. Returns the
+    /// number of values removed.
+    pub fn unset(&mut self, name: &str, all: bool) -> anyhow::Result<usize> {
+        let parsed = ConfigName::parse(name)?;
+        let matching: Vec<(usize, usize, String)> = self
+            .entries
+            .iter()
+            .filter(|span| span.is(&parsed))
+            .map(|span| (span.start_line, span.end_line, span.prefix.clone()))
+            .collect();
+
+        if matching.len() > 1 && !all {
+            bail!("Key {} has multiple values", name);
+        }
+
+        // Remove from the bottom up so earlier line numbers stay valid
+        for (start, end, prefix) in matching.iter().rev() {
+            if prefix.trim().is_empty() {
+                self.lines.drain(*start..=*end);
+            } else {
+                // The entry shares its line with a section header, keep the header
+                self.lines
+                    .splice(*start..=*end, [prefix.trim_end().to_string()]);
+            }
+        }
+
+        self.reparse()?;
+        Ok(matching.len())
+    }
+
+    fn reparse(&mut self) -> anyhow::Result<()> {
+        *self = Self::parse(&self.path, &self.lines.join("\n"))?;
+        Ok(())
+    }
+}
+
+impl EntrySpan {
+    fn is(&self, name: &ConfigName) -> bool {
+        self.section == name.section && self.subsection == name.subsection && self.key == name.key
+    }
+}
+
+/// Character-level parser over the text of a config file.
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+    line_start: usize,
+}
+
+impl Parser {
+    fn new(text: &str) -> Self {
+        Self {
+            chars: text.chars().collect(),
+            pos: 0,
+            line: 0,
+            line_start: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+            self.line_start = self.pos;
+        }
+        Some(c)
+    }
+
+    fn skip_line(&mut self) {
+        while let Some(c) = self.bump() {
+            if c == '\n' {
+                break;
+            }
+        }
+    }
+
+    fn skip_blanks(&mut self) {
+        while matches!(self.peek(), Some(' ') | Some('\t') | Some('\r')) {
+            self.bump();
+        }
+    }
+
+    fn run(mut self, file: &mut ConfigFile) -> anyhow::Result<()> {
+        let mut current: Option<(String, Option<String>)> = None;
+
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' | '\t' | '\r' | '\n' => {
+                    self.bump();
+                }
+                '#' | ';' => self.skip_line(),
+                '[' => {
+                    let header_line = self.line;
+                    let (section, subsection) = self.section_header()?;
+                    file.sections.push(SectionSpan {
+                        section: section.clone(),
+                        subsection: subsection.clone(),
+                        last_line: header_line,
+                    });
+                    current = Some((section, subsection));
+                }
+                c if c.is_ascii_alphabetic() => {
+                    let (section, subsection) = current.clone().ok_or_else(|| {
+                        anyhow!("line {}: key outside of a section", self.line + 1)
+                    })?;
+                    let start_line = self.line;
+                    let prefix: String = self.chars[self.line_start..self.pos].iter().collect();
+                    let (key, value) = self.assignment()?;
+
+                    // The assignment either consumed its terminating newline or hit the end
+                    let end_line = if self.chars[..self.pos].last() == Some(&'\n') {
+                        self.line - 1
+                    } else {
+                        self.line
+                    };
+
+                    if let Some(last) = file.sections.last_mut() {
+                        last.last_line = end_line;
+                    }
+                    file.entries.push(EntrySpan {
+                        section,
+                        subsection,
+                        key,
+                        value,
+                        start_line,
+                        end_line,
+                        prefix,
+                    });
+                }
+                other => bail!("line {}: unexpected character '{}'", self.line + 1, other),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Parses `[section]`, `[section "subsection"]` or the legacy `[section.subsection]`.
+    fn section_header(&mut self) -> anyhow::Result<(String, Option<String>)> {
+        let line = self.line + 1;
+        self.bump();
+
+        let mut name = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+                name.push(c);
+                self.bump();
+            } else {
+                break;
+            }
+        }
+        ensure!(!name.is_empty(), "line {}: empty section name", line);
+
+        self.skip_blanks();
+        let subsection = match self.bump() {
+            Some(']') => match name.split_once('.') {
+                Some((section, subsection)) => {
+                    let subsection = subsection.to_ascii_lowercase();
+                    name = section.to_string();
+                    Some(subsection)
+                }
+                None => None,
+            },
+            Some('"') => {
+                let mut subsection = String::new();
+                loop {
+                    match self.bump() {
+                        Some('"') => break,
+                        Some('\\') => match self.bump() {
+                            Some('\n') | None => bail!("line {}: bad section header", line),
+                            Some(c) => subsection.push(c),
+                        },
+                        Some('\n') | None => bail!("line {}: unterminated subsection", line),
+                        Some(c) => subsection.push(c),
+                    }
+                }
+                ensure!(
+                    self.bump() == Some(']'),
+                    "line {}: bad section header",
+                    line
+                );
+                Some(subsection)
+            }
+            _ => bail!("line {}: bad section header", line),
+        };
+
+        Ok((name.to_ascii_lowercase(), subsection))
+    }
+
+    /// Parses `key [= value]` up to and including the end of its (last) line.
+    fn assignment(&mut self) -> anyhow::Result<(String, Option<String>)> {
+        let line = self.line + 1;
+        let mut key = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                key.push(c);
+                self.bump();
+            } else {
+                break;
+            }
+        }
+
+        self.skip_blanks();
+        match self.peek() {
+            None | Some('\n') => {
+                self.bump();
+                return Ok((key.to_ascii_lowercase(), None));
+            }
+            Some('#') | Some(';') => {
+                self.skip_line();
+                return Ok((key.to_ascii_lowercase(), None));
+            }
+            Some('=') => {
+                self.bump();
+            }
+            Some(c) => bail!("line {}: unexpected character '{}' after key", line, c),
+        }
+
+        self.skip_blanks();
+        let mut value = String::new();
+        let mut pending_space = String::new();
+        let mut quoted = false;
+
+        loop {
+            let Some(c) = self.bump() else {
+                ensure!(!quoted, "line {}: unterminated quoted value", line);
+                break;
+            };
+
+            match c {
+                '\n' if quoted => bail!("line {}: unterminated quoted value", line),
+                '\n' => break,
+                '#' | ';' if !quoted => {
+                    self.skip_line();
+                    break;
+                }
+                '"' => {
+                    value.push_str(&pending_space);
+                    pending_space.clear();
+                    quoted = !quoted;
+                }
+                '\\' => {
+                    let escaped = match self.bump() {
+                        // Line continuation
+                        Some('\n') => continue,
+                        Some('\r') if self.peek() == Some('\n') => {
+                            self.bump();
+                            continue;
+                        }
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('b') => '\u{8}',
+                        Some('\\') => '\\',
+                        Some('"') => '"',
+                        _ => bail!("line {}: bad escape sequence in value", line),
+                    };
+                    value.push_str(&pending_space);
+                    pending_space.clear();
+                    value.push(escaped);
+                }
+                ' ' | '\t' | '\r' if !quoted => {
+                    if !value.is_empty() {
+                        pending_space.push(' ');
+                    }
+                }
+                c => {
+                    value.push_str(&pending_space);
+                    pending_space.clear();
+                    value.push(c);
+                }
+            }
+        }
+
+        Ok((key.to_ascii_lowercase(), Some(value)))
+    }
+}
+
+/////////////////////////////////////
+// Value helpers
+/////////////////////////////////////
+
+/// Parses a git boolean (`true`/`yes`/`on`/`1` or `false`/`no`/`off`/`0`/empty).
+pub fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" => Some(true),
+        "false" | "no" | "off" | "" => Some(false),
+        other => parse_int(other).map(|n| n != 0),
+    }
+}
+
+/// Parses a git integer with an optional `k`, `m` or `g` (binary) suffix.
+pub fn parse_int(value: &str) -> Option<i64> {
+    let value = value.trim();
+    let (digits, multiplier) = match value.chars().last()?.to_ascii_lowercase() {
+        'k' => (&value[..value.len() - 1], 1 << 10),
+        'm' => (&value[..value.len() - 1], 1 << 20),
+        'g' => (&value[..value.len() - 1], 1 << 30),
+        _ => (value, 1),
+    };
+    digits.parse::<i64>().ok()?.checked_mul(multiplier)
+}
+
+/// Expands a leading `~/` to the user's home directory.
+pub fn expand_path(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Returns `$XDG_CONFIG_HOME`, falling back to `~/.config`.
+pub fn xdg_config_home() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+}
+
+/// Formats a value for writing, quoting and escaping it where needed.
+fn format_value(value: &str) -> String {
+    let needs_quotes = value.starts_with([' ', '\t'])
+        || value.ends_with([' ', '\t'])
+        || value.contains(['#', ';']);
+
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t");
+
+    if needs_quotes {
+        format!("\"{}\"", escaped)
+    } else {
+        escaped
+    }
+}
+
+fn format_section_header(name: &ConfigName) -> String {
+    match &name.subsection {
+        Some(subsection) => format!(
+            "[{} \"{}\"]",
+            name.section,
+            subsection.replace('\\', "\\\\").replace('"', "\\\"")
+        ),
+        None => format!("[{}]", name.section),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::GitrsError, testing::TempRepo};
+
+    /// Reads `text` as a configuration file.
+    fn config(repo: &TempRepo, text: &str) -> Config {
+        let path = repo.write("test.cfg", text);
+        Config::read_file(&path, ConfigScope::File, None).unwrap()
+    }
+
+    /// Applies `edit` to a file holding `text` and returns the saved text.
+    fn edit(repo: &TempRepo, text: &str, edit: impl FnOnce(&mut ConfigFile)) -> String {
+        let path = repo.write("test.cfg", text);
+        let mut file = ConfigFile::read(&path).unwrap();
+        edit(&mut file);
+        file.save().unwrap();
+        fs::read_to_string(&path).unwrap()
+    }
+
+    #[test]
+    fn parses_sections_and_values() {
+        let repo = TempRepo::new();
+        let config = config(
+            &repo,
+            concat!(
+                "# a comment\n",
+                "[Core]\n",
+                "\tEditor = vim   ; trailing comment\n",
+                "\tpager = \"less  -R # not a comment\"\n",
+                "[remote \"Origin\"]\n",
+                "\turl = a\\\n",
+                "  b\n",
+                "[branch.Main]\n",
+                "\tremote = origin\n",
+                "[escapes]\n",
+                "\tvalue = x\\ty\\nz\\\"q\\\\\n",
+                "\tspaces =   inner   spaces   \n",
+            ),
+        );
+
+        assert_eq!(config.get("core.editor"), Some("vim"));
+        assert_eq!(config.get("CORE.EDITOR"), Some("vim"));
+        assert_eq!(config.get("core.pager"), Some("less  -R # not a comment"));
+        // Subsections are case-sensitive, except in the legacy `[section.subsection]` form
+        assert_eq!(config.get("remote.Origin.url"), Some("a  b"));
+        assert_eq!(config.get("remote.origin.url"), None);
+        assert_eq!(config.get("branch.main.remote"), Some("origin"));
+        assert_eq!(config.get("escapes.value"), Some("x\ty\nz\"q\\"));
+        assert_eq!(config.get("escapes.spaces"), Some("inner   spaces"));
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        let repo = TempRepo::new();
+        for text in [
+            "key = outside\n",
+            "[core\n",
+            "[core]\n\tkey = \"unterminated\n",
+            "[core]\n\tkey = bad\\qescape\n",
+            "[core]\n\t1key = x\n",
+        ] {
+            let path = repo.write("test.cfg", text);
+            assert!(ConfigFile::read(&path).is_err(), "{text:?}");
+        }
+    }
+
+    #[test]
+    fn bare_keys_are_true() {
+        let repo = TempRepo::new();
+        let config = config(&repo, "[core]\n\tbare\n[multi]\n\tv = off\n\tv\n");
+
+        assert_eq!(config.get_bool("core.bare").unwrap(), Some(true));
+        assert_eq!(config.get("core.bare"), Some(""));
+        assert_eq!(config.get_all("multi.v"), [Some("off"), None]);
+        assert_eq!(config.get_bool("multi.v").unwrap(), Some(true));
+        assert_eq!(config.get_bool("core.missing").unwrap(), None);
+    }
+
+    #[test]
+    fn integers_take_binary_suffixes() {
+        let cases = [
+            ("42", Some(42)),
+            ("-3", Some(-3)),
+            ("1k", Some(1024)),
+            ("2M", Some(2 << 20)),
+            ("1g", Some(1 << 30)),
+            ("k", None),
+            ("1.5k", None),
+            ("9223372036854775807k", None),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(parse_int(value), expected, "{value:?}");
+        }
+
+        let repo = TempRepo::new();
+        let config = config(&repo, "[pack]\n\twindow = 8k\n\tbad = lots\n");
+        assert_eq!(config.get_int("pack.window").unwrap(), Some(8192));
+        assert!(config.get_int("pack.bad").is_err());
+        assert_eq!(parse_bool("1k"), Some(true));
+        assert_eq!(parse_bool("maybe"), None);
+    }
+
+    #[test]
+    fn set_and_unset_keep_comments_and_layout() {
+        let repo = TempRepo::new();
+        let text = concat!(
+            "# top comment\n",
+            "[core]\n",
+            "    editor = vim  # keep me?\n",
+            "\t; section comment\n",
+            "\tpager = less\n",
+            "[user]\n",
+            "\tname = A\n",
+        );
+
+        let edited = edit(&repo, text, |file| {
+            file.set("core.editor", "nano", false).unwrap();
+            file.set("core.autocrlf", "input", false).unwrap();
+            file.set("alias.co", "checkout", false).unwrap();
+            file.unset("user.name", false).unwrap();
+        });
+        assert_eq!(
+            edited,
+            concat!(
+                "# top comment\n",
+                "[core]\n",
+                "    editor = nano\n",
+                "\t; section comment\n",
+                "\tpager = less\n",
+                "\tautocrlf = input\n",
+                "[user]\n",
+                "[alias]\n",
+                "\tco = checkout\n",
+            )
+        );
+    }
+
+    #[test]
+    fn multi_valued_keys() {
+        let repo = TempRepo::new();
+        let text = "[remote \"origin\"]\n\tfetch = a\n";
+
+        let edited = edit(&repo, text, |file| {
+            file.set("remote.origin.fetch", "b", true).unwrap();
+            assert!(file.set("remote.origin.fetch", "c", false).is_err());
+            assert!(file.unset("remote.origin.fetch", false).is_err());
+        });
+        assert_eq!(edited, "[remote \"origin\"]\n\tfetch = a\n\tfetch = b\n");
+        assert_eq!(
+            config(&repo, &edited).get_all("remote.origin.fetch"),
+            [Some("a"), Some("b")]
+        );
+
+        let edited = edit(&repo, &edited, |file| {
+            assert_eq!(file.unset("remote.origin.fetch", true).unwrap(), 2);
+        });
+        assert_eq!(edited, "[remote \"origin\"]\n");
+    }
+
+    #[test]
+    fn written_values_read_back_unchanged() {
+        let repo = TempRepo::new();
+        let values = [
+            " leading",
+            "trailing\t",
+            "a # b",
+            "semi;colon",
+            "quote\"back\\slash",
+            "new\nline",
+        ];
+
+        let edited = edit(&repo, "", |file| {
+            for (i, value) in values.iter().enumerate() {
+                file.set(&format!("test.key{i}"), value, false).unwrap();
+            }
+        });
+        let config = config(&repo, &edited);
+        for (i, value) in values.iter().enumerate() {
+            assert_eq!(config.get(&format!("test.key{i}")), Some(*value));
+        }
+    }
+
+    #[test]
+    fn save_goes_through_a_lock_file() {
+        let repo = TempRepo::new();
+        let path = repo.write("test.cfg", "[core]\n\tbare = false\n");
+        let mut file = ConfigFile::read(&path).unwrap();
+        file.set("core.bare", "true", false).unwrap();
+
+        let lock = repo.write("test.cfg.lock", "");
+        let error = file.save().unwrap_err();
+        assert!(matches!(
+            GitrsError::find(&error),
+            Some(GitrsError::RefLockHeld { .. })
+        ));
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "[core]\n\tbare = false\n"
+        );
+
+        fs::remove_file(lock).unwrap();
+        file.save().unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "[core]\n\tbare = true\n"
+        );
+        assert!(!repo.path("test.cfg.lock").exists());
+    }
+}
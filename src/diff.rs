@@ -0,0 +1,610 @@
+// Line-based diff engine (Myers' O(ND) algorithm) producing unified-style hunks
+use std::{fmt, ops::Range};
+
+/// Number of unchanged lines shown around each change.
+pub const CONTEXT_LINES: usize = 3;
+
+/// A single line of a hunk. Lines hold raw bytes including their trailing newline (if any).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DiffLine {
+    Context(Vec<u8>),
+    Delete(Vec<u8>),
+    Insert(Vec<u8>),
+}
+
+/// A contiguous region of changes, together with its surrounding context.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hunk {
+    /// 0-based index of the first old line covered by the hunk
+    pub old_start: usize,
+    /// 0-based index of the first new line covered by the hunk
+    pub new_start: usize,
+    pub lines: Vec<DiffLine>,
+}
+
+/// One step of the edit script turning the old lines into the new lines.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Edit {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/// Splits raw content into lines, keeping the trailing newline on each line.
+pub fn split_lines(data: &[u8]) -> Vec<&[u8]> {
+    data.split_inclusive(|&b| b == b'\n').collect()
+}
+
+/// Computes the hunks turning `old` into `new`, each with up to `context` lines of context.
+pub fn diff(old: &[u8], new: &[u8], context: usize) -> Vec<Hunk> {
+    let old_lines = split_lines(old);
+    let new_lines = split_lines(new);
+    let edits = myers(&old_lines, &new_lines);
+
+    // Locate the edit indices of every change, then group changes separated by at most
+    // `2 * context` unchanged lines into the same hunk.
+    let changes: Vec<usize> = edits
+        .iter()
+        .enumerate()
+        .filter(|(_, edit)| !matches!(edit, Edit::Equal(..)))
+        .map(|(i, _)| i)
+        .collect();
+
+    let mut groups: Vec<(usize, usize)> = Vec::new();
+    for i in changes {
+        match groups.last_mut() {
+            Some((_, end)) if i - *end <= 2 * context + 1 => *end = i,
+            _ => groups.push((i, i)),
+        }
+    }
+
+    groups
+        .into_iter()
+        .map(|(first, last)| {
+            let start = first.saturating_sub(context);
+            let end = (last + context + 1).min(edits.len());
+            let (old_start, new_start) = position_of(&edits, start);
+
+            let lines = edits[start..end]
+                .iter()
+                .map(|edit| match *edit {
+                    Edit::Equal(i, _) => DiffLine::Context(old_lines[i].to_vec()),
+                    Edit::Delete(i) => DiffLine::Delete(old_lines[i].to_vec()),
+                    Edit::Insert(j) => DiffLine::Insert(new_lines[j].to_vec()),
+                })
+                .collect();
+
+            Hunk {
+                old_start,
+                new_start,
+                lines,
+            }
+        })
+        .collect()
+}
+
+/// Applies the given hunks (sorted, computed against `old`) and returns the resulting content.
+///
+/// Hunks may overlap on their context lines (as produced by `Hunk::split`); the overlapping
+/// prefix of a later hunk is skipped.
+pub fn apply(old: &[u8], hunks: &[Hunk]) -> Vec<u8> {
+    let old_lines = split_lines(old);
+    let mut output = Vec::with_capacity(old.len());
+    let mut cursor = 0;
+
+    for hunk in hunks {
+        let start = hunk.old_start.max(cursor);
+        for line in &old_lines[cursor.min(old_lines.len())..start.min(old_lines.len())] {
+            output.extend_from_slice(line);
+        }
+
+        let mut overlap = cursor.saturating_sub(hunk.old_start);
+        for line in &hunk.lines {
+            match line {
+                DiffLine::Context(_) | DiffLine::Delete(_) if overlap > 0 => overlap -= 1,
+                DiffLine::Context(data) | DiffLine::Insert(data) => output.extend_from_slice(data),
+                DiffLine::Delete(_) => {}
+            }
+        }
+
+        cursor = cursor.max(hunk.old_start + hunk.old_len());
+    }
+
+    for line in old_lines.iter().skip(cursor) {
+        output.extend_from_slice(line);
+    }
+
+    output
+}
+
+impl Hunk {
+    /// Number of old lines covered by the hunk.
+    pub fn old_len(&self) -> usize {
+        self.lines
+            .iter()
+            .filter(|line| !matches!(line, DiffLine::Insert(_)))
+            .count()
+    }
+
+    /// Number of new lines covered by the hunk.
+    pub fn new_len(&self) -> usize {
+        self.lines
+            .iter()
+            .filter(|line| !matches!(line, DiffLine::Delete(_)))
+            .count()
+    }
+
+    /// Lines of the old side of the hunk (context and deletions).
+    pub fn old_side(&self) -> Vec<&[u8]> {
+        self.lines
+            .iter()
+            .filter_map(|line| match line {
+                DiffLine::Context(data) | DiffLine::Delete(data) => Some(data.as_slice()),
+                DiffLine::Insert(_) => None,
+            })
+            .collect()
+    }
+
+    /// Splits the hunk into smaller hunks at every run of context lines between changes.
+    ///
+    /// Neighbouring hunks share the context run that separates them. Returns `None` if the hunk
+    /// cannot be split any further.
+    pub fn split(&self) -> Option<Vec<Hunk>> {
+        // Boundaries of each run of changed lines, as [start, end) indices into `lines`
+        let mut runs: Vec<(usize, usize)> = Vec::new();
+        for (i, line) in self.lines.iter().enumerate() {
+            if matches!(line, DiffLine::Context(_)) {
+                continue;
+            }
+            match runs.last_mut() {
+                Some((_, end)) if *end == i => *end = i + 1,
+                _ => runs.push((i, i + 1)),
+            }
+        }
+
+        if runs.len() < 2 {
+            return None;
+        }
+
+        let mut hunks = Vec::with_capacity(runs.len());
+        for n in 0..runs.len() {
+            let from = if n == 0 { 0 } else { runs[n - 1].1 };
+            let to = runs.get(n + 1).map_or(self.lines.len(), |next| next.0);
+
+            let (old_offset, new_offset) =
+                self.lines[..from]
+                    .iter()
+                    .fold((0, 0), |(old, new), line| match line {
+                        DiffLine::Context(_) => (old + 1, new + 1),
+                        DiffLine::Delete(_) => (old + 1, new),
+                        DiffLine::Insert(_) => (old, new + 1),
+                    });
+
+            hunks.push(Hunk {
+                old_start: self.old_start + old_offset,
+                new_start: self.new_start + new_offset,
+                lines: self.lines[from..to].to_vec(),
+            });
+        }
+
+        Some(hunks)
+    }
+}
+
+impl fmt::Display for Hunk {
+    /// Formats the hunk in unified diff format, starting with its `@@` header.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let range = |start: usize, len: usize| match len {
+            0 => format!("{},0", start),
+            1 => format!("{}", start + 1),
+            _ => format!("{},{}", start + 1, len),
+        };
+
+        writeln!(
+            f,
+            "@@ -{} +{} @@",
+            range(self.old_start, self.old_len()),
+            range(self.new_start, self.new_len())
+        )?;
+
+        for line in &self.lines {
+            let (prefix, data) = match line {
+                DiffLine::Context(data) => (' ', data),
+                DiffLine::Delete(data) => ('-', data),
+                DiffLine::Insert(data) => ('+', data),
+            };
+            write!(f, "{}{}", prefix, String::from_utf8_lossy(data))?;
+            if data.last() != Some(&b'\n') {
+                writeln!(f, "\n\\ No newline at end of file")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the (old, new) line positions reached before the edit at index `at`.
+fn position_of(edits: &[Edit], at: usize) -> (usize, usize) {
+    edits[..at]
+        .iter()
+        .fold((0, 0), |(old, new), edit| match edit {
+            Edit::Equal(..) => (old + 1, new + 1),
+            Edit::Delete(_) => (old + 1, new),
+            Edit::Insert(_) => (old, new + 1),
+        })
+}
+
+/// Computes the shortest edit script between two line sequences using Myers' algorithm.
+///
+/// Uses the linear-space refinement: rather than keeping the furthest-reaching paths of every
+/// step to backtrack through, the middle snake of the optimal path is found by searching from
+/// both ends at once, and the two halves on either side of it are diffed recursively.
+fn myers(old: &[&[u8]], new: &[&[u8]]) -> Vec<Edit> {
+    let max_d = (old.len() + new.len()).div_ceil(2) + 1;
+    let mut forward = Diagonals::new(max_d);
+    let mut backward = Diagonals::new(max_d);
+
+    let mut edits = Vec::with_capacity(old.len() + new.len());
+    conquer(
+        old,
+        0..old.len(),
+        new,
+        0..new.len(),
+        &mut forward,
+        &mut backward,
+        &mut edits,
+    );
+
+    // Like git, show the deletions of each change before its insertions
+    for run in edits.split_mut(|edit| matches!(edit, Edit::Equal(..))) {
+        run.sort_by_key(|edit| matches!(edit, Edit::Insert(_)));
+    }
+    edits
+}
+
+/// Furthest x reached on each diagonal k (`x - y`) of the edit graph, indexed from `-max_d`.
+struct Diagonals {
+    offset: isize,
+    x: Vec<usize>,
+}
+
+impl Diagonals {
+    fn new(max_d: usize) -> Self {
+        Self {
+            offset: max_d as isize,
+            x: vec![0; 2 * max_d + 1],
+        }
+    }
+}
+
+impl std::ops::Index<isize> for Diagonals {
+    type Output = usize;
+
+    fn index(&self, k: isize) -> &usize {
+        &self.x[(k + self.offset) as usize]
+    }
+}
+
+impl std::ops::IndexMut<isize> for Diagonals {
+    fn index_mut(&mut self, k: isize) -> &mut usize {
+        &mut self.x[(k + self.offset) as usize]
+    }
+}
+
+/// Appends the edits turning `old[old_range]` into `new[new_range]`.
+fn conquer(
+    old: &[&[u8]],
+    mut old_range: Range<usize>,
+    new: &[&[u8]],
+    mut new_range: Range<usize>,
+    forward: &mut Diagonals,
+    backward: &mut Diagonals,
+    edits: &mut Vec<Edit>,
+) {
+    // Lines shared at either end need no search
+    while !old_range.is_empty()
+        && !new_range.is_empty()
+        && old[old_range.start] == new[new_range.start]
+    {
+        edits.push(Edit::Equal(old_range.start, new_range.start));
+        old_range.start += 1;
+        new_range.start += 1;
+    }
+    let mut suffix = 0;
+    while suffix < old_range.len()
+        && suffix < new_range.len()
+        && old[old_range.end - suffix - 1] == new[new_range.end - suffix - 1]
+    {
+        suffix += 1;
+    }
+    old_range.end -= suffix;
+    new_range.end -= suffix;
+
+    if old_range.is_empty() {
+        edits.extend(new_range.clone().map(Edit::Insert));
+    } else if new_range.is_empty() {
+        edits.extend(old_range.clone().map(Edit::Delete));
+    } else if let Some((x, y)) = middle_snake(
+        old,
+        old_range.clone(),
+        new,
+        new_range.clone(),
+        forward,
+        backward,
+    ) {
+        conquer(
+            old,
+            old_range.start..x,
+            new,
+            new_range.start..y,
+            forward,
+            backward,
+            edits,
+        );
+        conquer(
+            old,
+            x..old_range.end,
+            new,
+            y..new_range.end,
+            forward,
+            backward,
+            edits,
+        );
+    } else {
+        edits.extend(old_range.clone().map(Edit::Delete));
+        edits.extend(new_range.clone().map(Edit::Insert));
+    }
+
+    edits.extend((0..suffix).map(|i| Edit::Equal(old_range.end + i, new_range.end + i)));
+}
+
+/// Finds where the forward and backward searches for the shortest edit script of the given
+/// ranges meet, returning the (old, new) position at the start of the snake they meet on.
+fn middle_snake(
+    old: &[&[u8]],
+    old_range: Range<usize>,
+    new: &[&[u8]],
+    new_range: Range<usize>,
+    forward: &mut Diagonals,
+    backward: &mut Diagonals,
+) -> Option<(usize, usize)> {
+    let n = old_range.len();
+    let m = new_range.len();
+    let delta = n as isize - m as isize;
+    let odd = delta & 1 == 1;
+    let max_d = (n + m).div_ceil(2) + 1;
+
+    let old_at = |x: usize| old[old_range.start + x];
+    let new_at = |y: usize| new[new_range.start + y];
+
+    forward[1] = 0;
+    backward[1] = 0;
+
+    for d in 0..max_d as isize {
+        // Forward search from the top left corner
+        for k in (-d..=d).rev().step_by(2) {
+            let mut x = if k == -d || (k != d && forward[k - 1] < forward[k + 1]) {
+                forward[k + 1]
+            } else {
+                forward[k - 1] + 1
+            };
+            let mut y = (x as isize - k) as usize;
+            let (x0, y0) = (x, y);
+            while x < n && y < m && old_at(x) == new_at(y) {
+                x += 1;
+                y += 1;
+            }
+            forward[k] = x;
+
+            if odd && (k - delta).abs() < d && forward[k] + backward[-(k - delta)] >= n {
+                return Some((old_range.start + x0, new_range.start + y0));
+            }
+        }
+
+        // Backward search from the bottom right corner, with x and y counted from the end
+        for k in (-d..=d).rev().step_by(2) {
+            let mut x = if k == -d || (k != d && backward[k - 1] < backward[k + 1]) {
+                backward[k + 1]
+            } else {
+                backward[k - 1] + 1
+            };
+            let mut y = (x as isize - k) as usize;
+            while x < n && y < m && old_at(n - x - 1) == new_at(m - y - 1) {
+                x += 1;
+                y += 1;
+            }
+            backward[k] = x;
+
+            if !odd && (k - delta).abs() <= d && backward[k] + forward[-(k - delta)] >= n {
+                return Some((old_range.start + n - x, new_range.start + m - y));
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<u8> {
+        text.replace(' ', "\n").into_bytes()
+    }
+
+    fn edit_count(old: &[u8], new: &[u8]) -> usize {
+        myers(&split_lines(old), &split_lines(new))
+            .iter()
+            .filter(|edit| !matches!(edit, Edit::Equal(..)))
+            .count()
+    }
+
+    /// Checks that the edit script replays `old` into `new`.
+    fn check_script(old: &[u8], new: &[u8]) {
+        let (old_lines, new_lines) = (split_lines(old), split_lines(new));
+        let (mut x, mut y) = (0, 0);
+        let mut rebuilt = Vec::new();
+        for edit in myers(&old_lines, &new_lines) {
+            match edit {
+                Edit::Equal(i, j) => {
+                    assert_eq!((i, j), (x, y));
+                    assert_eq!(old_lines[i], new_lines[j]);
+                    rebuilt.push(new_lines[j]);
+                    x += 1;
+                    y += 1;
+                }
+                Edit::Delete(i) => {
+                    assert_eq!(i, x);
+                    x += 1;
+                }
+                Edit::Insert(j) => {
+                    assert_eq!(j, y);
+                    rebuilt.push(new_lines[j]);
+                    y += 1;
+                }
+            }
+        }
+        assert_eq!((x, y), (old_lines.len(), new_lines.len()));
+        assert_eq!(rebuilt.concat(), new);
+    }
+
+    #[test]
+    fn identical_content_has_no_hunks() {
+        let text = lines("a b c ");
+        assert!(diff(&text, &text, CONTEXT_LINES).is_empty());
+    }
+
+    #[test]
+    fn finds_shortest_edit_script() {
+        // The example from Myers' paper, with a shortest edit script of 5 edits
+        assert_eq!(
+            edit_count(&lines("a b c a b b a "), &lines("c b a b a c ")),
+            5
+        );
+        assert_eq!(edit_count(&lines("a b c "), &lines("a x c ")), 2);
+        assert_eq!(edit_count(b"", &lines("a b ")), 2);
+        assert_eq!(edit_count(&lines("a b "), b""), 2);
+        assert_eq!(edit_count(&lines("a b "), &lines("b a ")), 2);
+    }
+
+    #[test]
+    fn edit_script_replays_old_into_new() {
+        let cases = [
+            ("a b c a b b a ", "c b a b a c "),
+            ("a b c d e f ", "x a c d y f z "),
+            ("", "a "),
+            ("a ", ""),
+            ("a a a a ", "a a "),
+            ("x y ", "y x y x "),
+        ];
+        for (old, new) in cases {
+            check_script(&lines(old), &lines(new));
+            check_script(&lines(new), &lines(old));
+        }
+    }
+
+    #[test]
+    fn large_inputs_use_little_memory() {
+        let old: Vec<u8> = (0..20_000)
+            .flat_map(|i| format!("{}\n", i).into_bytes())
+            .collect();
+        let new: Vec<u8> = (0..20_000)
+            .map(|i| {
+                if i % 1000 == 0 {
+                    format!("changed {}\n", i)
+                } else {
+                    format!("{}\n", i)
+                }
+            })
+            .flat_map(String::into_bytes)
+            .collect();
+        assert_eq!(edit_count(&old, &new), 40);
+        assert_eq!(apply(&old, &diff(&old, &new, CONTEXT_LINES)), new);
+    }
+
+    #[test]
+    fn hunk_has_context_and_unified_format() {
+        let old = lines("1 2 3 4 5 6 7 8 9 ");
+        let new = lines("1 2 3 4 five 6 7 8 9 ");
+        let hunks = diff(&old, &new, CONTEXT_LINES);
+
+        assert_eq!(hunks.len(), 1);
+        assert_eq!((hunks[0].old_start, hunks[0].new_start), (1, 1));
+        assert_eq!(
+            hunks[0].to_string(),
+            "@@ -2,7 +2,7 @@\n 2\n 3\n 4\n-5\n+five\n 6\n 7\n 8\n"
+        );
+    }
+
+    #[test]
+    fn distant_changes_get_separate_hunks() {
+        let old = lines("1 2 3 4 5 6 7 8 9 10 11 12 ");
+        let new = lines("one 2 3 4 5 6 7 8 9 10 11 twelve ");
+        let hunks = diff(&old, &new, CONTEXT_LINES);
+
+        assert_eq!(hunks.len(), 2);
+        assert_eq!(hunks[1].old_start, 8);
+    }
+
+    #[test]
+    fn reports_missing_newline_at_end_of_file() {
+        let hunks = diff(b"a\nb", b"a\nc", CONTEXT_LINES);
+        assert_eq!(
+            hunks[0].to_string(),
+            "@@ -1,2 +1,2 @@\n a\n-b\n\\ No newline at end of file\n+c\n\\ No newline at end of file\n"
+        );
+    }
+
+    #[test]
+    fn apply_reproduces_new_content() {
+        let cases = [
+            (
+                "1 2 3 4 5 6 7 8 9 10 11 12 ",
+                "one 2 3 4 5 6 7 8 9 10 11 twelve ",
+            ),
+            ("a b c ", "a b c d "),
+            ("a b c ", ""),
+            ("", "a b "),
+        ];
+        for (old, new) in cases {
+            let (old, new) = (lines(old), lines(new));
+            assert_eq!(apply(&old, &diff(&old, &new, CONTEXT_LINES)), new);
+        }
+        assert_eq!(apply(b"a\nb", &diff(b"a\nb", b"a\nc", 1)), b"a\nc");
+    }
+
+    #[test]
+    fn apply_with_some_hunks_keeps_the_rest_unchanged() {
+        let old = lines("1 2 3 4 5 6 7 8 9 10 11 12 ");
+        let hunks = diff(&old, &lines("one 2 3 4 5 6 7 8 9 10 11 twelve "), 3);
+        assert_eq!(
+            apply(&old, &hunks[1..]),
+            lines("1 2 3 4 5 6 7 8 9 10 11 twelve ")
+        );
+    }
+
+    #[test]
+    fn split_separates_changes_sharing_context() {
+        let old = lines("1 2 3 4 5 6 ");
+        let new = lines("one 2 3 4 5 six ");
+        let hunks = diff(&old, &new, CONTEXT_LINES);
+        assert_eq!(hunks.len(), 1);
+
+        let parts = hunks[0].split().expect("hunk has two changes");
+        assert_eq!(parts.len(), 2);
+        assert_eq!(
+            parts[0].to_string(),
+            "@@ -1,5 +1,5 @@\n-1\n+one\n 2\n 3\n 4\n 5\n"
+        );
+        assert_eq!(
+            parts[1].to_string(),
+            "@@ -2,5 +2,5 @@\n 2\n 3\n 4\n 5\n-6\n+six\n"
+        );
+        assert!(parts[0].split().is_none());
+
+        // Either part applies on its own, and both together give the full change
+        assert_eq!(apply(&old, &parts[..1]), lines("one 2 3 4 5 6 "));
+        assert_eq!(apply(&old, &parts[1..]), lines("1 2 3 4 5 six "));
+        assert_eq!(apply(&old, &parts), new);
+    }
+}
@@ -0,0 +1,72 @@
+// Failures callers may need to tell apart, carried through `anyhow::Error`s
+use std::{io, path::PathBuf};
+
+/// An error with a well-known cause, raised wherever it is detected and propagated inside
+/// `anyhow::Error` (possibly under added context) up to the caller, which can recover it with
+/// `find`.
+///
+/// In the CLI, each variant exits with its own status (see `exit_code`) so scripts can branch
+/// on it; any other error is fatal and exits with 128, as in git.
+#[derive(Debug, thiserror::Error)]
+pub enum GitrsError {
+    #[error("not a gitrs repository (or any of the parent directories): .gitrs")]
+    NotARepository,
+    #[error("Not a valid object name {0}")]
+    ObjectNotFound(String),
+    #[error("ambiguous object name '{name}', candidates are: {}", .candidates.join(", "))]
+    AmbiguousName {
+        name: String,
+        candidates: Vec<String>,
+    },
+    #[error("object {sha} is corrupt: {reason}")]
+    CorruptObject { sha: String, reason: String },
+    #[error(
+        "Unable to create '{}': File exists. Another gitrs process seems to be running in this \
+         repository; if not, remove the file and try again",
+        .lock.display()
+    )]
+    RefLockHeld { lock: PathBuf },
+    #[error("index file corrupt: {0}")]
+    InvalidIndex(String),
+    /// The command was invoked with a bad combination of arguments
+    #[error("usage: {0}")]
+    Usage(String),
+    /// The command ran, but its outcome is a non-zero status of its own (like `check-ignore`
+    /// matching nothing) rather than a failure. The CLI exits with it without printing anything.
+    #[error("exited with status {0}")]
+    Status(u8),
+}
+
+impl GitrsError {
+    /// Exit status of a command failing with this error.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Self::NotARepository => 10,
+            Self::ObjectNotFound(_) => 11,
+            Self::AmbiguousName { .. } => 12,
+            Self::CorruptObject { .. } => 13,
+            Self::RefLockHeld { .. } => 14,
+            Self::InvalidIndex(_) => 15,
+            Self::Usage(_) => 129,
+            Self::Status(code) => *code,
+        }
+    }
+
+    /// Finds the `GitrsError` that caused `error`, if any, looking through its chain of causes
+    /// and inside I/O errors (as raised while streaming a corrupt object).
+    pub fn find(error: &anyhow::Error) -> Option<&Self> {
+        error.chain().find_map(|cause| {
+            cause.downcast_ref::<Self>().or_else(|| {
+                cause
+                    .downcast_ref::<io::Error>()
+                    .and_then(io::Error::get_ref)
+                    .and_then(|inner| inner.downcast_ref::<Self>())
+            })
+        })
+    }
+
+    /// Wraps the error in an `io::Error`, for `Read` implementations.
+    pub fn into_io(self) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, self)
+    }
+}
@@ -1,11 +1,18 @@
-use log::{debug, info};
+use anyhow::anyhow;
+use log::debug;
 
+use crate::config::{Config, xdg_config_home};
+use crate::object::{GitrsObject, ObjectType, tree::Leaf};
 use crate::repository::Repository;
-use crate::{index::Index, object::GitrsObject};
-use core::str;
+use std::cell::RefCell;
+use std::fs;
 use std::path::Path;
+use std::rc::Rc;
 use std::{collections::HashMap, path::PathBuf};
 
+/// Name of the per-directory ignore file
+const IGNORE_FILE: &str = ".gitrsignore";
+
 /// Describes how an ignore rule should behave.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum MatchKind {
@@ -16,118 +23,690 @@ pub enum MatchKind {
 /// A single `.gitignore`-style rule.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct IgnoreRule {
-    pub pat: glob::Pattern,
+    /// Wildmatch pattern, without the negation prefix, leading `/` or trailing `/`
+    pub pattern: String,
     pub kind: MatchKind,
+    /// The pattern ended with `/` and only matches directories
+    pub dir_only: bool,
+    /// The pattern contained a `/` and is matched against the full path relative to the
+    /// `.gitrsignore` location rather than just the file name
+    pub anchored: bool,
+    /// The pattern as written in its source file
+    pub text: String,
+    /// File the rule was read from (empty if unknown)
+    pub source: PathBuf,
+    /// 1-based line number of the rule in its source file
+    pub line: usize,
 }
 
 /// Collection of `.gitignore` rules, both absolute and relative.
 #[derive(Debug)]
 pub struct IgnoreRules {
+    /// Worktree root, which the absolute rules are matched relative to
+    worktree: PathBuf,
     /// Ignore rules from outside the repo (e.g. `~/.config/git/ignore`)
     absolute: Vec<IgnoreRule>,
-    /// `.gitignore` rules tracked in the repo, keyed by their parent directory.
-    relative: HashMap<PathBuf, Vec<IgnoreRule>>,
+    /// `.gitignore` rules in the repo, keyed by their parent directory. Filled lazily when
+    /// reading from the worktree.
+    relative: RefCell<HashMap<PathBuf, Rc<Vec<IgnoreRule>>>>,
+    /// Where the `.gitrsignore` files are read from
+    source: RuleSource,
+}
+
+/// Where per-directory ignore files are read from.
+#[derive(Debug, PartialEq, Eq)]
+enum RuleSource {
+    /// Loaded on demand from the worktree
+    Worktree,
+    /// Collected up front from a tree object
+    Tree,
 }
 
 impl From<(&str, MatchKind)> for IgnoreRule {
     fn from((pat, kind): (&str, MatchKind)) -> Self {
+        let text = match kind {
+            MatchKind::Include => format!("!{}", pat),
+            MatchKind::Exclude => pat.to_string(),
+        };
+        let (pat, dir_only) = match pat.strip_suffix('/') {
+            Some(stripped) => (stripped, true),
+            None => (pat, false),
+        };
+        let anchored = pat.contains('/');
+
         Self {
-            pat: glob::Pattern::new(pat)
-                .expect(&format!("Couldn't create glob pattern from {}", pat)),
+            pattern: pat.strip_prefix('/').unwrap_or(pat).to_string(),
             kind,
+            dir_only,
+            anchored,
+            text,
+            source: PathBuf::new(),
+            line: 0,
         }
     }
 }
 
 impl IgnoreRule {
     /// Parses a single line into an ignore rule.
+    ///
+    /// Follows `.gitignore` syntax: blank lines and `#` comments are skipped, trailing spaces
+    /// are dropped unless escaped with a backslash, and a leading `!` negates the pattern.
     pub fn parse(raw: &str) -> Option<Self> {
-        let trimmed = raw.trim();
-        if trimmed.is_empty() || trimmed.starts_with('#') {
+        let mut line = raw.trim_end_matches(['\n', '\r']);
+        if line.is_empty() || line.starts_with('#') {
             return None;
         }
 
-        let (kind, pattern) = match trimmed.chars().next()? {
-            '!' => (MatchKind::Include, &trimmed[1..]),
-            '\\' => (MatchKind::Exclude, &trimmed[1..]),
-            _ => (MatchKind::Exclude, trimmed),
+        while line.ends_with(' ') && !line.ends_with("\\ ") {
+            line = &line[..line.len() - 1];
+        }
+
+        let (kind, pattern) = match line.strip_prefix('!') {
+            Some(rest) => (MatchKind::Include, rest),
+            None => (MatchKind::Exclude, line),
         };
 
+        if pattern.is_empty() || pattern == "/" {
+            return None;
+        }
+
         Some(IgnoreRule::from((pattern, kind)))
     }
 
-    /// Parses multiple lines into ignore rules.
-    pub fn parse_lines<I>(lines: I) -> Vec<Self>
-    where
-        I: IntoIterator,
-        I::Item: AsRef<str>,
-    {
-        lines
-            .into_iter()
-            .filter_map(|line| Self::parse(line.as_ref()))
+    /// Parses the contents of an ignore file, recording where each rule came from.
+    pub fn parse_file(source: &Path, content: &str) -> Vec<Self> {
+        content
+            .lines()
+            .enumerate()
+            .filter_map(|(i, line)| {
+                Some(Self {
+                    source: source.to_path_buf(),
+                    line: i + 1,
+                    ..Self::parse(line)?
+                })
+            })
             .collect()
     }
+
+    /// Returns true if the rule matches the given path, expressed relative to the directory
+    /// the rule was defined in (using `/` separators).
+    pub fn matches(&self, relative_path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+
+        let subject = if self.anchored {
+            relative_path
+        } else {
+            relative_path.rsplit('/').next().unwrap_or(relative_path)
+        };
+
+        wildmatch(self.pattern.as_bytes(), subject.as_bytes())
+    }
 }
 
 impl IgnoreRules {
-    /// Reads all `.gitignore` rules from the repository's index.
-    pub fn read(repository: &Repository) -> Option<Self> {
-        let index = Index::read(repository)?;
+    /// Creates a matcher that reads `.gitrsignore` files from the worktree.
+    ///
+    /// Rule files are loaded lazily (and cached) the first time a path in their directory is
+    /// checked, so untracked and modified ignore files take effect immediately.
+    pub fn read(repository: &Repository) -> Self {
+        Self {
+            worktree: repository.worktree.clone(),
+            absolute: Self::read_absolute(repository),
+            relative: RefCell::new(HashMap::new()),
+            source: RuleSource::Worktree,
+        }
+    }
 
-        let relative = index
-            .entries
-            .into_iter()
-            .filter_map(|entry| {
-                let file_name = entry.path.file_name()?;
-                if file_name != ".gitrsignore" {
-                    return None;
+    /// Creates a matcher from the `.gitrsignore` files recorded in a tree object, for operations
+    /// on history rather than on the worktree.
+    pub fn read_from_tree(repository: &Repository, tree_hash: &str) -> anyhow::Result<Self> {
+        let mut relative = HashMap::new();
+        Self::collect_from_tree(repository, tree_hash, &repository.worktree, &mut relative)?;
+
+        debug!("Relative ignore rules: {:?}", relative);
+
+        Ok(Self {
+            worktree: repository.worktree.clone(),
+            absolute: Self::read_absolute(repository),
+            relative: RefCell::new(relative),
+            source: RuleSource::Tree,
+        })
+    }
+
+    /// Recursively collects the `.gitrsignore` blobs of a tree, keyed by the directory they
+    /// would be checked out into.
+    fn collect_from_tree(
+        repository: &Repository,
+        tree_hash: &str,
+        dir: &Path,
+        relative: &mut HashMap<PathBuf, Rc<Vec<IgnoreRule>>>,
+    ) -> anyhow::Result<()> {
+        let tree = match GitrsObject::read(repository, tree_hash)? {
+            GitrsObject::TreeObject(tree) => tree,
+            other => return Err(anyhow!("Expected a tree, found {}", other.get_type())),
+        };
+
+        for leaf in &tree.records {
+            match Leaf::get_type_from_mode(&leaf.file_mode) {
+                ObjectType::Tree => Self::collect_from_tree(
+                    repository,
+                    &leaf.hash,
+                    &dir.join(&leaf.path),
+                    relative,
+                )?,
+                ObjectType::Blob if leaf.path.as_os_str() == IGNORE_FILE => {
+                    if let GitrsObject::BlobObject(blob) =
+                        GitrsObject::read(repository, &leaf.hash)?
+                    {
+                        let rules = IgnoreRule::parse_file(
+                            &dir.join(IGNORE_FILE),
+                            &String::from_utf8_lossy(blob.get_data()),
+                        );
+                        relative.insert(dir.to_path_buf(), Rc::new(rules));
+                    }
                 }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the rules defined in `dir`, loading them from the worktree if not yet cached.
+    fn rules_in(&self, dir: &Path) -> Option<Rc<Vec<IgnoreRule>>> {
+        if let Some(rules) = self.relative.borrow().get(dir) {
+            return Some(Rc::clone(rules));
+        }
+
+        if self.source == RuleSource::Tree {
+            return None;
+        }
 
-                let blob_data = match GitrsObject::read(repository, &entry.sha).ok()? {
-                    GitrsObject::BlobObject(blob) => blob.get_data(),
-                    _ => panic!("Malformed .gitignore entry, not a blob object"),
-                };
+        let source = dir.join(IGNORE_FILE);
+        let rules = match fs::read(&source) {
+            Ok(data) => {
+                debug!("Loading ignore rules from {:?}", dir.display());
+                IgnoreRule::parse_file(&source, &String::from_utf8_lossy(&data))
+            }
+            Err(_) => Vec::new(),
+        };
 
-                debug!("Dumping .gitrsignore data");
-                GitrsObject::dump(&blob_data);
+        let rules = Rc::new(rules);
+        self.relative
+            .borrow_mut()
+            .insert(dir.to_path_buf(), Rc::clone(&rules));
+        Some(rules)
+    }
 
-                let lines = str::from_utf8(&blob_data).ok()?.lines();
-                let parent = entry.path.parent()?.to_path_buf();
+    /// Reads the rules that apply to the whole worktree without being committed: the user-level
+    /// excludes file followed by `.gitrs/info/exclude`, so that the latter takes precedence.
+    fn read_absolute(repository: &Repository) -> Vec<IgnoreRule> {
+        let sources = [
+            Self::user_excludes_file(repository),
+            repository.get_path_to_file_if_exists(&["info", "exclude"]),
+        ];
 
-                Some((parent, IgnoreRule::parse_lines(lines)))
+        sources
+            .into_iter()
+            .flatten()
+            .filter_map(|path| {
+                debug!("Reading exclude file {:?}", path.display());
+                let content = fs::read_to_string(&path).ok()?;
+                Some(IgnoreRule::parse_file(&path, &content))
             })
-            .collect();
+            .flatten()
+            .collect()
+    }
 
-        debug!("Relative ignore rules: {:?}", relative);
+    /// Locates the user-level excludes file: `core.excludesFile` from the configuration if set,
+    /// otherwise `$XDG_CONFIG_HOME/git/ignore` (or `~/.config/git/ignore`).
+    fn user_excludes_file(repository: &Repository) -> Option<PathBuf> {
+        let configured = Config::read(Some(repository))
+            .map_err(|e| debug!("Couldn't read config: {}", e))
+            .ok()
+            .and_then(|config| config.get_path("core.excludesFile"));
 
-        Some(Self {
-            absolute: Vec::new(), // TODO: read absolute ignore rules
-            relative,
-        })
+        configured.or_else(|| xdg_config_home().map(|dir| dir.join("git").join("ignore")))
     }
 
-    /// Checks if the given path matches any ignore rules. Checks for the nearest gitrsignore file,
-    /// eventually checking the absolute ignore rules if none of the repository-specific ignore
-    /// rules match
+    /// Checks if the given path matches any ignore rules.
+    ///
+    /// Within a rule file the last matching rule wins, and rule files closer to the path take
+    /// precedence over those further up, with the absolute rules consulted last. A path inside
+    /// an excluded directory is always excluded: it cannot be re-included by a negated rule.
     pub fn check(&self, path: &Path) -> Option<MatchKind> {
+        self.check_rule(path, path.is_dir()).map(|rule| rule.kind)
+    }
+
+    /// Like `check`, but returns the rule that decided the outcome. The path need not exist, so
+    /// whether it is a directory is passed in.
+    pub fn check_rule(&self, path: &Path, is_dir: bool) -> Option<IgnoreRule> {
         debug!("Called check on: {:?}", path.display());
-        std::iter::successors(path.parent(), |p| p.parent()).find_map(|parent| {
-            // TODO: if nothing matches then look up absolute rules
-            debug!("Trying to lookup path: {:?}", parent.display());
-            self.relative
-                .get(parent)
-                .and_then(|rule_set| Self::matches_rules(rule_set, path))
+
+        let relative = path.strip_prefix(&self.worktree).ok()?;
+        let mut ancestor = self.worktree.clone();
+        let mut components = relative.components().peekable();
+
+        while let Some(component) = components.next() {
+            ancestor.push(component);
+            if components.peek().is_none() {
+                break;
+            }
+            if let Some(rule) = self.match_path(&ancestor, true)
+                && rule.kind == MatchKind::Exclude
+            {
+                debug!("Parent directory {:?} is excluded", ancestor.display());
+                return Some(rule);
+            }
+        }
+
+        self.match_path(path, is_dir)
+    }
+
+    /// Returns true if the path is excluded by the ignore rules
+    pub fn is_ignored(&self, path: &Path) -> bool {
+        self.check(path) == Some(MatchKind::Exclude)
+    }
+
+    /// Matches a single path (ignoring its parent directories) against the nearest rule files
+    fn match_path(&self, path: &Path, is_dir: bool) -> Option<IgnoreRule> {
+        std::iter::successors(path.parent(), |p| p.parent())
+            .take_while(|parent| parent.starts_with(&self.worktree))
+            .find_map(|parent| {
+                debug!("Trying to lookup path: {:?}", parent.display());
+                self.rules_in(parent)
+                    .and_then(|rule_set| Self::matches_rules(&rule_set, parent, path, is_dir))
+            })
+            .or_else(|| Self::matches_rules(&self.absolute, &self.worktree, path, is_dir))
+    }
+
+    // If the path matches some rule, returns whether to include or exclude the file. Later rules
+    // override earlier ones.
+    fn matches_rules(
+        rules: &[IgnoreRule],
+        base: &Path,
+        path: &Path,
+        is_dir: bool,
+    ) -> Option<IgnoreRule> {
+        let relative = path.strip_prefix(base).ok()?;
+        let relative = relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        rules.iter().rev().find_map(|rule| {
+            debug!("Matching pattern: {:?} for {}", rule.pattern, relative);
+            rule.matches(&relative, is_dir).then(|| rule.clone())
         })
     }
+}
+
+/// Matches `text` against a `.gitignore` wildmatch pattern.
+///
+/// `*`, `?` and bracket expressions never match `/`. A `**` that forms a whole path segment
+/// matches across directories: a leading `**/` matches in any directory, `/**/` matches zero or
+/// more directories and a trailing `/**` matches everything inside.
+pub fn wildmatch(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+
+    while p < pattern.len() {
+        match pattern[p] {
+            b'*' => {
+                let mut rest = p;
+                while rest < pattern.len() && pattern[rest] == b'*' {
+                    rest += 1;
+                }
+
+                let is_double = rest - p >= 2;
+                let segment_start = p == 0 || pattern[p - 1] == b'/';
+                let segment_end = rest == pattern.len() || pattern[rest] == b'/';
+
+                if is_double && segment_start && segment_end {
+                    if rest == pattern.len() {
+                        return true;
+                    }
+                    // `**/`: try the rest of the pattern at every directory boundary
+                    let after = &pattern[rest + 1..];
+                    return wildmatch(after, &text[t..])
+                        || (t..text.len())
+                            .any(|i| text[i] == b'/' && wildmatch(after, &text[i + 1..]));
+                }
+
+                let after = &pattern[rest..];
+                for i in t..=text.len() {
+                    if wildmatch(after, &text[i..]) {
+                        return true;
+                    }
+                    if i < text.len() && text[i] == b'/' {
+                        break;
+                    }
+                }
+                return false;
+            }
+            b'?' => {
+                if t >= text.len() || text[t] == b'/' {
+                    return false;
+                }
+                p += 1;
+                t += 1;
+            }
+            b'[' => match match_class(&pattern[p..], text.get(t).copied()) {
+                Some((matched, len)) => {
+                    if !matched {
+                        return false;
+                    }
+                    p += len;
+                    t += 1;
+                }
+                // No closing bracket: treat `[` literally
+                None => {
+                    if text.get(t) != Some(&b'[') {
+                        return false;
+                    }
+                    p += 1;
+                    t += 1;
+                }
+            },
+            b'\\' if p + 1 < pattern.len() => {
+                if text.get(t) != Some(&pattern[p + 1]) {
+                    return false;
+                }
+                p += 2;
+                t += 1;
+            }
+            c => {
+                if text.get(t) != Some(&c) {
+                    return false;
+                }
+                p += 1;
+                t += 1;
+            }
+        }
+    }
+
+    t == text.len()
+}
+
+/// Matches a single byte against the bracket expression at the start of `pattern`.
+///
+/// Returns whether it matched and the length of the bracket expression, or `None` if the
+/// expression is not terminated.
+fn match_class(pattern: &[u8], byte: Option<u8>) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negated = matches!(pattern.get(i), Some(b'!') | Some(b'^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    let mut invalid = false;
+    loop {
+        let mut c = *pattern.get(i)?;
+        if c == b']' && !first {
+            break;
+        }
+        first = false;
+
+        // `[:name:]`, a POSIX character class
+        if c == b'[' && pattern.get(i + 1) == Some(&b':') {
+            let len = pattern[i + 2..].windows(2).position(|w| w == b":]")?;
+            let name = &pattern[i + 2..i + 2 + len];
+            match posix_class(name) {
+                Some(class) => matched |= byte.is_some_and(class),
+                None => invalid = true,
+            }
+            i += len + 4;
+            continue;
+        }
 
-    // If the path matches some rule, returns whether to include or exclude the file
-    fn matches_rules(rules: &Vec<IgnoreRule>, path: &Path) -> Option<MatchKind> {
-        for IgnoreRule { pat, kind } in rules {
-            info!("Matching pattern: {:?} for {}", pat, path.display());
-            if pat.matches_path(path) {
-                return Some(kind.to_owned());
+        if c == b'\\' {
+            i += 1;
+            c = *pattern.get(i)?;
+        }
+
+        if pattern.get(i + 1) == Some(&b'-') && pattern.get(i + 2).is_some_and(|&e| e != b']') {
+            let mut end = pattern[i + 2];
+            i += 2;
+            if end == b'\\' {
+                i += 1;
+                end = *pattern.get(i)?;
             }
+            matched |= byte.is_some_and(|b| c <= b && b <= end);
+        } else {
+            matched |= byte == Some(c);
+        }
+        i += 1;
+    }
+
+    // Like git, a class with an unknown name never matches
+    let matched = !invalid && byte.is_some_and(|b| b != b'/') && (matched != negated);
+    Some((matched, i + 1))
+}
+
+fn posix_class(name: &[u8]) -> Option<fn(u8) -> bool> {
+    Some(match name {
+        b"alnum" => |b: u8| b.is_ascii_alphanumeric(),
+        b"alpha" => |b: u8| b.is_ascii_alphabetic(),
+        b"blank" => |b: u8| b == b' ' || b == b'\t',
+        b"cntrl" => |b: u8| b.is_ascii_control(),
+        b"digit" => |b: u8| b.is_ascii_digit(),
+        b"graph" => |b: u8| b.is_ascii_graphic(),
+        b"lower" => |b: u8| b.is_ascii_lowercase(),
+        b"print" => |b: u8| b.is_ascii_graphic() || b == b' ',
+        b"punct" => |b: u8| b.is_ascii_punctuation(),
+        b"space" => |b: u8| b.is_ascii_whitespace() || b == b'\x0b',
+        b"upper" => |b: u8| b.is_ascii_uppercase(),
+        b"xdigit" => |b: u8| b.is_ascii_hexdigit(),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempRepo;
+
+    #[test]
+    fn wildmatch_table() {
+        let cases: &[(&str, &str, bool)] = &[
+            // Literals, `?` and `*`
+            ("foo", "foo", true),
+            ("foo", "foobar", false),
+            ("f?o", "foo", true),
+            ("f?o", "f/o", false),
+            ("*.o", "main.o", true),
+            ("*.o", "main.c", false),
+            ("*", "", true),
+            ("*", "dir/file", false),
+            ("a*b*c", "aXbYc", true),
+            ("a*b*c", "aXbY", false),
+            // `**` as a whole segment
+            ("**/foo", "foo", true),
+            ("**/foo", "a/b/foo", true),
+            ("**/foo", "a/foobar", false),
+            ("a/**/b", "a/b", true),
+            ("a/**/b", "a/x/y/b", true),
+            ("a/**/b", "ab", false),
+            ("a/**", "a/x/y", true),
+            ("a/**", "b/x", false),
+            // `**` inside a segment is a plain `*`
+            ("a**b", "aXb", true),
+            ("a**b", "a/b", false),
+            // Character classes
+            ("[abc].txt", "b.txt", true),
+            ("[abc].txt", "d.txt", false),
+            ("[a-z]1", "q1", true),
+            ("[a-z]1", "Q1", false),
+            ("[!a]x", "bx", true),
+            ("[!a]x", "ax", false),
+            ("[^a]x", "ax", false),
+            ("[]]", "]", true),
+            ("[!/]", "/", false),
+            ("[a\\-z]", "-", true),
+            ("[a\\-z]", "m", false),
+            ("[ab", "[ab", true),
+            // POSIX classes
+            ("[[:alpha:]]", "q", true),
+            ("[[:alpha:]]", "1", false),
+            ("[[:digit:]]x", "7x", true),
+            ("[[:digit:]]x", "ax", false),
+            ("[[:alnum:]_]", "_", true),
+            ("[[:alnum:]]", "-", false),
+            ("a[[:space:]]b", "a b", true),
+            ("a[[:space:]]b", "a\tb", true),
+            ("a[[:blank:]]b", "a\nb", false),
+            ("[[:upper:]]*", "Makefile", true),
+            ("[[:upper:]]*", "makefile", false),
+            ("[[:lower:]]", "m", true),
+            ("[[:punct:]]", "!", true),
+            ("[[:punct:]]", "/", false),
+            ("[[:xdigit:]]", "F", true),
+            ("[[:xdigit:]]", "g", false),
+            ("[[:cntrl:]]", "\x07", true),
+            ("[[:graph:]]", " ", false),
+            ("[[:print:]]", " ", true),
+            ("[![:digit:]]", "a", true),
+            ("[![:digit:]]", "5", false),
+            ("[a[:digit:]]", "a", true),
+            ("[[:nope:]]", "n", false),
+            // Escapes
+            ("\\*", "*", true),
+            ("\\*", "x", false),
+            ("\\?x", "?x", true),
+        ];
+
+        for &(pattern, text, expected) in cases {
+            assert_eq!(
+                wildmatch(pattern.as_bytes(), text.as_bytes()),
+                expected,
+                "wildmatch({pattern:?}, {text:?})"
+            );
         }
-        None
+    }
+
+    #[test]
+    fn parse_rules() {
+        assert_eq!(IgnoreRule::parse(""), None);
+        assert_eq!(IgnoreRule::parse("# comment"), None);
+        assert_eq!(IgnoreRule::parse("!"), None);
+        assert_eq!(IgnoreRule::parse("/"), None);
+
+        let rule = IgnoreRule::parse("!/build/  ").unwrap();
+        assert_eq!(rule.kind, MatchKind::Include);
+        assert_eq!(rule.pattern, "build");
+        assert!(rule.dir_only);
+        assert!(rule.anchored);
+
+        let rule = IgnoreRule::parse("trailing\\ ").unwrap();
+        assert_eq!(rule.pattern, "trailing\\ ");
+        assert!(!rule.anchored);
+    }
+
+    #[test]
+    fn rule_matches_table() {
+        // (rule, path relative to the rule file, is_dir, expected)
+        let cases: &[(&str, &str, bool, bool)] = &[
+            // Without a slash the pattern matches the name at any depth
+            ("*.log", "debug.log", false, true),
+            ("*.log", "a/b/debug.log", false, true),
+            ("target", "sub/target", true, true),
+            // With a slash it is anchored to the rule file's directory
+            ("/target", "target", true, true),
+            ("/target", "sub/target", true, false),
+            ("doc/*.txt", "doc/a.txt", false, true),
+            ("doc/*.txt", "x/doc/a.txt", false, false),
+            ("doc/*.txt", "doc/sub/a.txt", false, false),
+            ("**/doc/*.txt", "x/doc/a.txt", false, true),
+            // A trailing slash only matches directories
+            ("build/", "build", true, true),
+            ("build/", "build", false, false),
+            ("build/", "a/build", true, true),
+        ];
+
+        for &(rule, path, is_dir, expected) in cases {
+            assert_eq!(
+                IgnoreRule::parse(rule).unwrap().matches(path, is_dir),
+                expected,
+                "{rule:?} against {path:?} (dir: {is_dir})"
+            );
+        }
+    }
+
+    #[test]
+    fn check_precedence_and_negation() {
+        let repo = TempRepo::new();
+        repo.write(".gitrsignore", "*.log\n!keep.log\nout/\n");
+        repo.write("sub/.gitrsignore", "!*.log\nlocal.txt\n");
+        let rules = IgnoreRules::read(&repo);
+
+        let cases: &[(&str, bool, Option<MatchKind>)] = &[
+            ("a.log", false, Some(MatchKind::Exclude)),
+            // A later rule in the same file overrides an earlier one
+            ("keep.log", false, Some(MatchKind::Include)),
+            // A closer rule file overrides one further up
+            ("sub/a.log", false, Some(MatchKind::Include)),
+            ("sub/local.txt", false, Some(MatchKind::Exclude)),
+            ("local.txt", false, None),
+            ("out", true, Some(MatchKind::Exclude)),
+            ("src/main.rs", false, None),
+        ];
+
+        for (path, is_dir, expected) in cases {
+            assert_eq!(
+                rules.check_rule(&repo.path(path), *is_dir).map(|r| r.kind),
+                *expected,
+                "{path}"
+            );
+        }
+    }
+
+    #[test]
+    fn paths_in_excluded_directories_cannot_be_reincluded() {
+        let repo = TempRepo::new();
+        repo.write(".gitrsignore", "out/\n!out/keep.txt\n");
+        repo.write("out/.gitrsignore", "!keep.txt\n");
+        let rules = IgnoreRules::read(&repo);
+
+        let rule = rules.check_rule(&repo.path("out/keep.txt"), false).unwrap();
+        assert_eq!(rule.kind, MatchKind::Exclude);
+        assert_eq!(rule.text, "out/");
+        assert_eq!(rule.line, 1);
+        assert!(rules.is_ignored(&repo.path("out/nested/file")));
+
+        // Excluding the directory's contents rather than the directory allows re-including
+        fs::write(repo.path(".gitrsignore"), "out/*\n!out/keep.txt\n").unwrap();
+        fs::remove_file(repo.path("out/.gitrsignore")).unwrap();
+        let rules = IgnoreRules::read(&repo);
+        assert_eq!(
+            rules
+                .check_rule(&repo.path("out/keep.txt"), false)
+                .map(|r| r.kind),
+            Some(MatchKind::Include)
+        );
+        assert!(rules.is_ignored(&repo.path("out/other.txt")));
+    }
+
+    #[test]
+    fn reads_excludes_file_through_the_config_parser() {
+        let repo = TempRepo::new();
+        let excludes = repo.write("excludes", "*.tmp\n");
+        // Quoting, key case, comments and a later override are all handled by `Config`
+        fs::write(
+            repo.gitdir.join("config"),
+            format!(
+                "[core]\n\texcludesFile = /nonexistent\n[CORE] # comment\n\tEXCLUDESFILE = \"{}\" ; comment\n",
+                excludes.display()
+            ),
+        )
+        .unwrap();
+
+        let rules = IgnoreRules::read(&repo);
+        let rule = rules.check_rule(&repo.path("a.tmp"), false).unwrap();
+        assert_eq!(rule.kind, MatchKind::Exclude);
+        assert_eq!(rule.source, excludes);
     }
 }
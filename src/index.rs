@@ -1,30 +1,113 @@
-use anyhow::{Context, anyhow};
-use log::error;
+use anyhow::{Context, anyhow, bail};
 use std::{
-    fs::{self, File},
-    io::Write,
-    path::PathBuf,
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs::{self, File, Metadata},
+    io::{BufReader, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 use typed_builder::TypedBuilder;
 
 use crate::{
-    object::{GitrsObject, ObjectType},
-    repository::Repository,
+    config::Config,
+    error::GitrsError,
+    ignore::IgnoreRules,
+    object::{
+        GitrsObject, ObjectType,
+        tree::{Leaf, ListOptions, Tree},
+    },
+    repository::{self, Repository, resolve_path},
+    worktree,
 };
 
-/// Four‑byte file signature (“DIRC”) + binary version number.
-const INDEX_SIGNATURE: &[u8; 4] = b"DIRC";
-const INDEX_VERSION: u32 = 2;
+/// Four-byte file signature, followed by a big-endian `u32` version and entry count.
+///
+/// The layout is gitrs's own, so it has its own signature too and git refuses the file instead
+/// of misreading it. Each entry is stored as:
+///
+/// | field  | encoding                                           |
+/// |--------|----------------------------------------------------|
+/// | mtime  | `u64` seconds since the epoch                      |
+/// | hash   | 20 raw bytes                                       |
+/// | size   | `u64` bytes                                        |
+/// | mode   | `u32`                                              |
+/// | stage  | `u8`, 0 unless conflicted                          |
+/// | path   | `u16` length, then that many UTF-8 bytes           |
+///
+/// There is no padding, extension or trailing checksum.
+const INDEX_SIGNATURE: &[u8; 4] = b"GRIX";
+/// Version of the layout above that gets written.
+const INDEX_VERSION: u32 = 1;
+/// Signature of the indexes older gitrs releases wrote, borrowed from git: version 3 has the
+/// layout above, and version 2 lacks the mode and stage. Both are still read, and upgraded when
+/// next written.
+const LEGACY_SIGNATURE: &[u8; 4] = b"DIRC";
+
+/// Mode of a regular, non-executable file.
+pub const MODE_FILE: u32 = 0o100644;
+/// Mode of a file with an executable bit set.
+pub const MODE_EXECUTABLE: u32 = 0o100755;
+/// Mode of a symbolic link, whose blob holds the link target.
+pub const MODE_SYMLINK: u32 = 0o120000;
+/// Mode of a gitlink: a submodule, recorded by the hash of the commit checked out in it.
+pub const MODE_GITLINK: u32 = 0o160000;
+
+/// A `(mode, hash)` pair, as recorded for a path in a tree or the index.
+pub type Content = (u32, String);
 
 const SHA_BYTES: usize = 20; // raw SHA‑1 (or any 160‑bit hash)
 
-#[derive(Default)]
 pub struct Index {
     pub version: u32,
     pub entries: Vec<IndexEntry>,
 }
 
+impl Default for Index {
+    fn default() -> Self {
+        Self {
+            version: INDEX_VERSION,
+            entries: Vec::new(),
+        }
+    }
+}
+
+/// Options controlling which paths `Index::add` stages.
+#[derive(Default)]
+pub struct AddOptions {
+    /// Also stage the deletion of tracked files missing under the given paths (always done
+    /// when no paths are given).
+    pub all: bool,
+    /// Only stage modifications and deletions of already-tracked paths.
+    pub update: bool,
+    /// Stage files even if they are ignored.
+    pub force: bool,
+    /// Compute the changes without writing objects or the index.
+    pub dry_run: bool,
+    /// Number of threads used to hash and write files (at most one if 0).
+    pub jobs: usize,
+}
+
+/// Options controlling how `Index::rm` removes paths.
+#[derive(Default)]
+pub struct RmOptions {
+    /// Only remove paths from the index, keeping the worktree files.
+    pub cached: bool,
+    /// Remove files even if they have unstaged changes.
+    pub force: bool,
+    /// Allow removing directories recursively.
+    pub recursive: bool,
+}
+
+/// A change made to the index while staging.
+#[derive(Debug, PartialEq, Eq)]
+pub enum IndexChange {
+    Add(PathBuf),
+    Remove(PathBuf),
+}
+
 /// **Greatly simplified** index entry
 #[derive(TypedBuilder)]
 pub struct IndexEntry {
@@ -32,75 +115,117 @@ pub struct IndexEntry {
     #[builder(setter(into))]
     pub sha: String, // 40‑char hex string on the Rust side
     pub size_in_bytes: u64,
+    /// Git file mode, e.g. `0o100644`
+    #[builder(default = MODE_FILE)]
+    pub mode: u32,
+    /// Merge stage: 0 for a normal entry, 1-3 for the base/ours/theirs sides of a conflict
+    #[builder(default)]
+    pub stage: u8,
     pub path: PathBuf,
 }
 
-// TODO: these could be TryFrom trait implementations
 impl IndexEntry {
     /// Serialise one entry into raw bytes.
-    fn to_bytes(&self) -> Vec<u8> {
-        let mut buf = Vec::with_capacity(8 + SHA_BYTES + 8 + 2 + self.path.as_os_str().len());
+    fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        let mut buf = Vec::with_capacity(8 + SHA_BYTES + 8 + 5 + 2 + self.path.as_os_str().len());
 
         // 1. mtime (u64 big‑endian)
         buf.extend_from_slice(&Self::system_time_to_secs(self.mtime).to_be_bytes());
 
         // 2. 20‑byte raw hash
-        let raw_sha =
-            hex::decode(&self.sha).expect("`sha` field must contain a valid 40‑char hex string");
-        assert_eq!(raw_sha.len(), SHA_BYTES, "hash must be 20 bytes/160 bits");
+        let raw_sha = hex::decode(&self.sha)
+            .ok()
+            .filter(|raw| raw.len() == SHA_BYTES)
+            .ok_or_else(|| anyhow!("Invalid hash '{}' for {}", self.sha, self.path.display()))?;
         buf.extend_from_slice(&raw_sha);
 
         // 3. file size (u64 big‑endian)
         buf.extend_from_slice(&self.size_in_bytes.to_be_bytes());
 
-        // 4. path: u16 length + UTF‑8 bytes
+        // 4. mode (u32 big‑endian) and stage (u8)
+        buf.extend_from_slice(&self.mode.to_be_bytes());
+        buf.push(self.stage);
+
+        // 5. path: u16 length + UTF‑8 bytes
         let path_bytes = self.path.to_string_lossy().as_bytes().to_owned();
-        let len = u16::try_from(path_bytes.len())
-            .expect("Paths longer than 65 535 bytes are unsupported");
+        let len = u16::try_from(path_bytes.len()).map_err(|_| {
+            anyhow!(
+                "Paths longer than 65 535 bytes are unsupported: {}",
+                self.path.display()
+            )
+        })?;
 
         buf.extend_from_slice(&len.to_be_bytes());
         buf.extend_from_slice(&path_bytes);
 
-        buf
+        Ok(buf)
     }
 
-    /// Consume a slice, returning one entry and advancing the slice.
-    fn take_from(buf: &mut &[u8]) -> Option<Self> {
-        // Need at least the fixed fields first
-        if buf.len() < 8 + SHA_BYTES + 8 + 2 {
-            return None;
-        }
-
+    /// Consume a slice, returning one entry and advancing the slice. Returns `None` if the
+    /// entry is truncated or its path isn't UTF-8.
+    ///
+    /// Without `has_mode`, the entry is in the legacy version 2 layout.
+    fn take_from(buf: &mut &[u8], has_mode: bool) -> Option<Self> {
         // 1. mtime
-        let secs = u64::from_be_bytes(buf[..8].try_into().unwrap());
-        *buf = &buf[8..];
+        let secs = take_u64(buf)?;
 
         // 2. SHA
-        let sha_raw = &buf[..SHA_BYTES];
-        *buf = &buf[SHA_BYTES..];
-        let sha_hex = hex::encode(sha_raw);
+        let sha_hex = hex::encode(take_bytes(buf, SHA_BYTES)?);
 
         // 3. size
-        let size_in_bytes = u64::from_be_bytes(buf[..8].try_into().unwrap());
-        *buf = &buf[8..];
+        let size_in_bytes = take_u64(buf)?;
 
-        // 4. path (length‑prefixed)
-        let len = u16::from_be_bytes(buf[..2].try_into().unwrap()) as usize;
-        *buf = &buf[2..];
-        if buf.len() < len {
-            return None;
-        }
-        let path = PathBuf::from(std::str::from_utf8(&buf[..len]).unwrap());
-        *buf = &buf[len..];
+        // 4. mode and stage, defaulting to a plain file for legacy version 2
+        let (mode, stage) = if has_mode {
+            (take_u32(buf)?, take_bytes(buf, 1)?[0])
+        } else {
+            (MODE_FILE, 0)
+        };
+
+        // 5. path (length-prefixed)
+        let len = u16::from_be_bytes(take_bytes(buf, 2)?.try_into().ok()?) as usize;
+        let path = PathBuf::from(std::str::from_utf8(take_bytes(buf, len)?).ok()?);
 
         Some(Self {
             mtime: Self::secs_to_system_time(secs),
             sha: sha_hex,
             size_in_bytes,
+            mode,
+            stage,
             path,
         })
     }
 
+    /// Returns true if the file in the worktree still has the mode and content recorded in
+    /// this entry.
+    ///
+    /// A missing file counts as matching, since there is nothing to lose by removing it.
+    pub fn matches_worktree(&self) -> anyhow::Result<bool> {
+        Ok(self
+            .worktree_content()?
+            .is_none_or(|(mode, sha)| mode == self.mode && sha == self.sha))
+    }
+
+    /// Returns the mode and hash the entry's path has in the worktree, or `None` if it is
+    /// missing.
+    ///
+    /// A gitlink hashes to the commit checked out in the submodule; one that hasn't been
+    /// checked out yet counts as unchanged.
+    pub fn worktree_content(&self) -> anyhow::Result<Option<Content>> {
+        if fs::symlink_metadata(&self.path).is_err() {
+            return Ok(None);
+        }
+
+        if self.mode == MODE_GITLINK {
+            let head = worktree::submodule_head(&self.path).unwrap_or_else(|| self.sha.clone());
+            return Ok(Some((MODE_GITLINK, head)));
+        }
+
+        let (mode, data) = worktree::read_file(&self.path)?;
+        let sha = GitrsObject::deserialize(&data, ObjectType::Blob)?.compute_hash()?;
+        Ok(Some((mode, sha)))
+    }
+
     /// Convert `SystemTime` to seconds since the Unix epoch (never panics).
     fn system_time_to_secs(t: SystemTime) -> u64 {
         t.duration_since(UNIX_EPOCH)
@@ -119,137 +244,1508 @@ impl IndexEntry {
 /////////////////////////////////////
 
 impl Index {
-    /// Read `repo/.gitrs/index`.  
-    /// Returns `None` if the file is missing or corrupt.
-    pub fn read(repository: &Repository) -> Option<Self> {
-        if let Some(index_file) = repository.get_path_to_file_if_exists(&["index"]) {
-            let data = fs::read(index_file).ok()?;
-            let mut cursor: &[u8] = &data;
+    /// Read `repo/.gitrs/index`, or an empty index if there is none yet.
+    ///
+    /// Fails with `GitrsError::InvalidIndex` if the file is corrupt or of an unsupported version.
+    pub fn read(repository: &Repository) -> anyhow::Result<Self> {
+        let Some(index_file) = repository.get_path_to_file_if_exists(&["index"]) else {
+            return Ok(Index::default());
+        };
+        let data = fs::read(&index_file)
+            .with_context(|| format!("Couldn't read {}", index_file.display()))?;
+        let invalid = |reason: &str| GitrsError::InvalidIndex(reason.to_string());
+        let mut cursor: &[u8] = &data;
 
-            // ── header ──────────────────────────────────────────────────────────
-            if cursor.len() < 12 || &cursor[..4] != INDEX_SIGNATURE {
-                error!("Index file length < header length OR mismatched signature");
-                return None;
+        // ── header ──────────────────────────────────────────────────────────
+        let signature: &[u8; 4] = take_bytes(&mut cursor, 4)
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| invalid("truncated header"))?;
+        let version = take_u32(&mut cursor).ok_or_else(|| invalid("truncated header"))?;
+        let has_mode = match (signature, version) {
+            (INDEX_SIGNATURE, INDEX_VERSION) => true,
+            (LEGACY_SIGNATURE, 3) => true,
+            (LEGACY_SIGNATURE, 2) => false,
+            (INDEX_SIGNATURE | LEGACY_SIGNATURE, _) => {
+                return Err(invalid(&format!("unsupported version {}", version)).into());
             }
-            cursor = &cursor[4..];
+            _ => return Err(invalid("bad signature").into()),
+        };
+
+        let count = take_u32(&mut cursor).ok_or_else(|| invalid("truncated header"))?;
 
-            let version = u32::from_be_bytes(cursor[..4].try_into().unwrap());
-            cursor = &cursor[4..];
+        // ── entries ─────────────────────────────────────────────────────────
+        let mut entries = Vec::with_capacity(count as usize);
+        for i in 0..count {
+            let entry = IndexEntry::take_from(&mut cursor, has_mode)
+                .ok_or_else(|| invalid(&format!("entry {} of {} is truncated", i + 1, count)))?;
+            entries.push(entry);
+        }
+        entries.sort_by(|a, b| (&a.path, a.stage).cmp(&(&b.path, b.stage)));
 
-            if version != INDEX_VERSION {
-                error!("Only index version 2 is supported");
-                return None; // unsupported version
+        // Older indexes are upgraded when next written
+        Ok(Self {
+            version: INDEX_VERSION,
+            entries,
+        })
+    }
+
+    /// Serialise this `Index` back to disk (overwrites if present).
+    pub fn write(&self, repository: &Repository) -> anyhow::Result<()> {
+        // ── header ──────────────────────────────────────────────────────────
+        let mut data = Vec::new();
+        data.extend_from_slice(INDEX_SIGNATURE);
+        data.extend_from_slice(&self.version.to_be_bytes());
+        data.extend_from_slice(&(self.entries.len() as u32).to_be_bytes());
+
+        // ── entries ─────────────────────────────────────────────────────────
+        for e in &self.entries {
+            data.extend_from_slice(&e.to_bytes()?);
+        }
+
+        repository::write_locked(&repository.gitdir.join("index"), |f| f.write_all(&data))
+    }
+
+    /// Stages the given paths in the repository (i.e adds them to the index file -- or creates an
+    /// index if there is no existing index file).
+    ///
+    /// Directories are staged recursively, skipping the `.gitrs` directory and untracked files
+    /// that are ignored (tracked files are always staged, even if they match an ignore rule).
+    /// Naming an ignored path that holds no tracked files is refused unless `force` is set.
+    ///
+    /// A path that no longer exists in the worktree is unstaged; tracked files missing under a
+    /// directory are unstaged with `all` or `update`. When no paths are given the whole worktree
+    /// is used, as with `all`. Returns the list of changes made (or, with `dry_run`, the changes
+    /// that would be made).
+    pub fn add(
+        &mut self,
+        repository: &Repository,
+        paths: &[PathBuf],
+        options: &AddOptions,
+    ) -> anyhow::Result<Vec<IndexChange>> {
+        let rules = if options.force {
+            None
+        } else {
+            Some(IgnoreRules::read(repository))
+        };
+
+        // Without symlink support, links are checked out as plain files holding their target,
+        // which shouldn't be staged as a change of type
+        let symlinks = Config::read(Some(repository))?
+            .get_bool("core.symlinks")?
+            .unwrap_or(true);
+
+        let stage_deletions = options.all || options.update || paths.is_empty();
+        let roots = if paths.is_empty() {
+            vec![repository.worktree.clone()]
+        } else {
+            paths.iter().map(|path| resolve_path(path)).collect()
+        };
+
+        let mut to_stage = BTreeSet::new();
+        let mut to_remove = BTreeSet::new();
+
+        for root in &roots {
+            if !repository.contains(root) {
+                return Err(anyhow!("Path {} outside worktree", root.display()));
+            }
+            if repository.is_gitdir_path(root) {
+                return Err(anyhow!("Cannot stage gitrs internals: {}", root.display()));
             }
 
-            let count = u32::from_be_bytes(cursor[..4].try_into().unwrap());
-            cursor = &cursor[4..];
+            let tracked: Vec<&PathBuf> = self
+                .entries
+                .iter()
+                .map(|entry| &entry.path)
+                .filter(|path| path.starts_with(root))
+                .collect();
 
-            // ── entries ─────────────────────────────────────────────────────────
-            let mut entries = Vec::with_capacity(count as usize);
-            for _ in 0..count {
-                let entry = IndexEntry::take_from(&mut cursor)?;
-                entries.push(entry);
+            if fs::symlink_metadata(root).is_err() {
+                if tracked.is_empty() {
+                    return Err(anyhow!(
+                        "Pathspec '{}' did not match any files",
+                        root.display()
+                    ));
+                }
+                to_remove.extend(tracked.into_iter().cloned());
+                continue;
             }
 
-            Some(Self { version, entries })
-        } else {
-            Some(Index::default())
+            let is_ignored = rules.as_ref().is_some_and(|rules| rules.is_ignored(root));
+            if is_ignored && tracked.is_empty() {
+                return Err(anyhow!(
+                    "Path {} is ignored by one of your .gitrsignore files, use -f to add it",
+                    repository.relative_path(root).display()
+                ));
+            }
+
+            // The walk skips ignored paths, so tracked files are added back explicitly
+            let (present, missing): (Vec<&PathBuf>, Vec<&PathBuf>) = tracked
+                .into_iter()
+                .partition(|path| fs::symlink_metadata(path).is_ok());
+            let mut candidates = if repository::is_dir_no_follow(root) {
+                worktree::walk(repository, root, rules.as_ref())?
+            } else {
+                vec![root.clone()]
+            };
+            if options.update {
+                candidates.retain(|path| present.contains(&path));
+            }
+
+            if stage_deletions {
+                to_remove.extend(missing.into_iter().cloned());
+            }
+            to_stage.extend(candidates);
+            to_stage.extend(present.into_iter().cloned());
         }
-    }
 
-    /// Serialise this `Index` back to disk (overwrites if present).
-    pub fn write(&self, repository: &Repository) -> anyhow::Result<()> {
-        let index_file = repository
-            .create_file(&["index"])
-            .ok_or_else(|| anyhow!("Could not create index file"))?;
+        let mut changes = Vec::new();
 
-        let mut f = File::create(index_file)?;
+        for path in to_remove {
+            self.entries.retain(|entry| entry.path != path);
+            changes.push(IndexChange::Remove(path));
+        }
 
-        // ── header ──────────────────────────────────────────────────────────
-        f.write_all(INDEX_SIGNATURE)?;
-        f.write_all(&INDEX_VERSION.to_be_bytes())?;
-        f.write_all(&(self.entries.len() as u32).to_be_bytes())?;
+        // Hashing only reads the files, so it can run on every path at once
+        let to_stage: Vec<PathBuf> = to_stage.into_iter().collect();
+        let hashed = map_parallel(&to_stage, options.jobs, |path| {
+            hash_worktree_path(repository, path)
+        })?;
 
-        // ── entries ─────────────────────────────────────────────────────────
-        for e in &self.entries {
-            f.write_all(&e.to_bytes())?;
+        let mut updates = Vec::new();
+        for (path, ((mut mode, sha), metadata)) in to_stage.into_iter().zip(hashed) {
+            let existing = self
+                .entries
+                .iter()
+                .find(|entry| entry.path == path && entry.stage == 0);
+
+            if !symlinks && mode == MODE_FILE && existing.is_some_and(|e| e.mode == MODE_SYMLINK) {
+                mode = MODE_SYMLINK;
+            }
+            if existing.is_some_and(|entry| entry.sha == sha && entry.mode == mode) {
+                continue;
+            }
+
+            updates.push((path, mode, sha, metadata));
+        }
+
+        if !options.dry_run {
+            // Compress each new blob once, however many paths share it
+            let missing: BTreeMap<&str, &Path> = updates
+                .iter()
+                .filter(|(_, mode, sha, _)| {
+                    *mode != MODE_GITLINK && !GitrsObject::exists(repository, sha)
+                })
+                .map(|(path, _, sha, _)| (sha.as_str(), path.as_path()))
+                .collect();
+            let missing: Vec<_> = missing.into_iter().collect();
+            map_parallel(&missing, options.jobs, |(sha, path)| {
+                write_blob(repository, sha, path)
+            })?;
         }
 
-        f.flush()?;
+        for (path, mode, sha, metadata) in updates {
+            if !options.dry_run {
+                self.upsert(
+                    IndexEntry::builder()
+                        .mtime(metadata.modified()?)
+                        .sha(sha)
+                        .size_in_bytes(metadata.len())
+                        .mode(mode)
+                        .path(path.clone())
+                        .build(),
+                );
+            }
+
+            changes.push(IndexChange::Add(path));
+        }
+
+        if !options.dry_run {
+            self.write(repository)?;
+        }
 
-        Ok(())
+        Ok(changes)
     }
 
-    // Given a list of paths, stages them in the repository (i.e adds them to the index file -- or
-    // creates an index if there is not exisiting index file)
-    pub fn add(&mut self, repository: &Repository, paths: &Vec<PathBuf>) -> anyhow::Result<()> {
-        self.rm(repository, paths, true)?;
+    /// Inserts an entry, replacing any existing entry for the same path and stage, keeping
+    /// entries sorted. Staging a path at stage 0 resolves any conflict recorded for it.
+    pub fn upsert(&mut self, entry: IndexEntry) {
+        if entry.stage == 0 {
+            self.entries
+                .retain(|existing| existing.path != entry.path || existing.stage == 0);
+        }
+
+        match self.entries.binary_search_by(|existing| {
+            (&existing.path, existing.stage).cmp(&(&entry.path, entry.stage))
+        }) {
+            Ok(i) => self.entries[i] = entry,
+            Err(i) => self.entries.insert(i, entry),
+        }
+    }
+
+    /// Removes the given paths from the index and, unless `cached` is set, from the worktree.
+    ///
+    /// Every path must match a tracked file (directories require `recursive`). Files whose
+    /// worktree content differs from the index are refused unless `force` is set. All paths are
+    /// validated before anything is removed. Returns the removed paths.
+    pub fn rm(
+        &mut self,
+        repository: &Repository,
+        paths: &[PathBuf],
+        options: &RmOptions,
+    ) -> anyhow::Result<Vec<PathBuf>> {
+        let mut removed = BTreeSet::new();
 
         for path in paths {
+            let path = resolve_path(path);
             if !repository.contains(&path) {
                 return Err(anyhow!("Path {} outside worktree", path.display()));
             }
 
-            let data = fs::read(&path)?;
-            let mut blob = GitrsObject::deserialize(&data, ObjectType::Blob);
-            let sha = blob.write(&repository);
+            let matched: Vec<&IndexEntry> = self
+                .entries
+                .iter()
+                .filter(|entry| entry.path.starts_with(&path))
+                .collect();
 
-            let metadata = fs::metadata(&path)?;
-            let mtime = metadata.modified()?;
-            let size = metadata.len();
-            let canonical_path = fs::canonicalize(&path)?;
+            if matched.is_empty() {
+                return Err(anyhow!(
+                    "Pathspec '{}' did not match any tracked files",
+                    repository.relative_path(&path).display()
+                ));
+            }
 
-            self.entries.push(
-                IndexEntry::builder()
-                    .mtime(mtime)
-                    .sha(sha)
-                    .size_in_bytes(size)
-                    .path(canonical_path)
-                    .build(),
-            );
+            if !options.recursive && matched.iter().any(|entry| entry.path != path) {
+                return Err(anyhow!(
+                    "Not removing '{}' recursively without -r",
+                    repository.relative_path(&path).display()
+                ));
+            }
+
+            for entry in matched {
+                if !options.cached && !options.force && !entry.matches_worktree()? {
+                    return Err(anyhow!(
+                        "'{}' has local modifications, use --cached to keep the file or -f to \
+                         force removal",
+                        repository.relative_path(&entry.path).display()
+                    ));
+                }
+                removed.insert(entry.path.clone());
+            }
+        }
+
+        self.entries.retain(|entry| !removed.contains(&entry.path));
+
+        if !options.cached {
+            for path in &removed {
+                match fs::symlink_metadata(path) {
+                    // A submodule's directory is only removed if it was never checked out
+                    Ok(metadata) if metadata.is_dir() => {
+                        let _ = fs::remove_dir(path);
+                    }
+                    Ok(_) => fs::remove_file(path)?,
+                    Err(_) => {}
+                }
+                remove_empty_parents(repository, path);
+            }
         }
 
         self.write(repository)?;
 
-        Ok(())
+        Ok(removed.into_iter().collect())
     }
 
-    // Unstages given list of paths (i.e removes them from the index file if they exist)
-    pub fn rm(
+    /// Moves tracked files or directories in the worktree and renames their index entries.
+    ///
+    /// With a single source, `destination` is the new name unless it is an existing directory,
+    /// in which case (as with multiple sources) sources are moved inside it. Returns the
+    /// `(source, destination)` pairs that were moved.
+    pub fn mv(
+        &mut self,
+        repository: &Repository,
+        sources: &[PathBuf],
+        destination: &Path,
+        force: bool,
+    ) -> anyhow::Result<Vec<(PathBuf, PathBuf)>> {
+        let destination = resolve_path(destination);
+        let into_dir = sources.len() > 1 || repository::is_dir_no_follow(&destination);
+
+        if sources.len() > 1 && !repository::is_dir_no_follow(&destination) {
+            return Err(anyhow!(
+                "Destination '{}' is not a directory",
+                repository.relative_path(&destination).display()
+            ));
+        }
+
+        // Validate every move before touching the worktree
+        let mut moves: Vec<(PathBuf, PathBuf)> = Vec::with_capacity(sources.len());
+        for source in sources {
+            let source = resolve_path(source);
+            let target = if into_dir {
+                destination.join(
+                    source
+                        .file_name()
+                        .ok_or_else(|| anyhow!("Bad source '{}'", source.display()))?,
+                )
+            } else {
+                destination.clone()
+            };
+
+            if !repository.contains(&source) || !repository.contains(&target) {
+                return Err(anyhow!(
+                    "Cannot move {} to {}: outside worktree",
+                    source.display(),
+                    target.display()
+                ));
+            }
+            if fs::symlink_metadata(&source).is_err() {
+                return Err(anyhow!(
+                    "Bad source '{}': does not exist",
+                    repository.relative_path(&source).display()
+                ));
+            }
+            if !self
+                .entries
+                .iter()
+                .any(|entry| entry.path.starts_with(&source))
+            {
+                return Err(anyhow!(
+                    "Not under version control: '{}'",
+                    repository.relative_path(&source).display()
+                ));
+            }
+            if target.starts_with(&source) {
+                return Err(anyhow!(
+                    "Cannot move '{}' into itself",
+                    repository.relative_path(&source).display()
+                ));
+            }
+            // Only -f overwrites, and never a directory
+            let can_overwrite = force && !repository::is_dir_no_follow(&target);
+            if fs::symlink_metadata(&target).is_ok() && !can_overwrite {
+                return Err(anyhow!(
+                    "Destination '{}' exists",
+                    repository.relative_path(&target).display()
+                ));
+            }
+            if !target.parent().is_some_and(Path::is_dir) {
+                return Err(anyhow!(
+                    "Destination directory for '{}' does not exist",
+                    repository.relative_path(&target).display()
+                ));
+            }
+
+            // Moves are applied one after another, so no two may touch the same path
+            if let Some((other, _)) = moves.iter().find(|(other, other_target)| {
+                other_target == &target
+                    || source.starts_with(other)
+                    || other.starts_with(&source)
+                    || target.starts_with(other)
+                    || other_target.starts_with(&source)
+            }) {
+                return Err(anyhow!(
+                    "Cannot move both '{}' and '{}' to '{}'",
+                    repository.relative_path(other).display(),
+                    repository.relative_path(&source).display(),
+                    repository.relative_path(&target).display()
+                ));
+            }
+
+            moves.push((source, target));
+        }
+
+        // Put back whatever was already moved if a rename fails, so the worktree still
+        // matches the index
+        for (done, (source, target)) in moves.iter().enumerate() {
+            if let Err(e) = fs::rename(source, target) {
+                for (source, target) in moves[..done].iter().rev() {
+                    let _ = fs::rename(target, source);
+                }
+                return Err(e).with_context(|| {
+                    format!(
+                        "Failed to move {} to {}",
+                        source.display(),
+                        target.display()
+                    )
+                });
+            }
+        }
+
+        for (source, target) in &moves {
+            // Overwriting a tracked file with -f replaces its entry
+            self.entries.retain(|entry| &entry.path != target);
+            for entry in self.entries.iter_mut() {
+                if let Ok(rest) = entry.path.strip_prefix(source) {
+                    entry.path = if rest.as_os_str().is_empty() {
+                        target.clone()
+                    } else {
+                        target.join(rest)
+                    };
+                }
+            }
+        }
+
+        self.entries
+            .sort_by(|a, b| (&a.path, a.stage).cmp(&(&b.path, b.stage)));
+        self.write(repository)?;
+
+        Ok(moves)
+    }
+}
+
+/////////////////////////////////////
+// Trees
+/////////////////////////////////////
+
+impl Index {
+    /// Writes the index as nested tree objects, returning the hash of the root tree (or, with
+    /// a `prefix` relative to the worktree, of the tree for that directory).
+    ///
+    /// Fails if the index has unresolved conflicts.
+    pub fn write_tree(
+        &self,
+        repository: &Repository,
+        prefix: Option<&Path>,
+    ) -> anyhow::Result<String> {
+        if let Some(entry) = self.entries.iter().find(|entry| entry.stage > 0) {
+            bail!(
+                "Cannot write a tree with unmerged entry '{}'",
+                repository.relative_path(&entry.path).display()
+            );
+        }
+
+        // The leaves of every directory, keyed by its path relative to the worktree
+        let mut dirs: BTreeMap<PathBuf, Vec<Leaf>> = BTreeMap::new();
+        dirs.insert(PathBuf::new(), Vec::new());
+
+        for entry in &self.entries {
+            let path = repository.relative_path(&entry.path);
+            let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+                bail!("Bad path in index: {}", entry.path.display());
+            };
+
+            for dir in parent.ancestors() {
+                dirs.entry(dir.to_path_buf()).or_default();
+            }
+            dirs.entry(parent.to_path_buf()).or_default().push(Leaf {
+                file_mode: format!("{:06o}", entry.mode),
+                path: PathBuf::from(name),
+                hash: entry.sha.clone(),
+            });
+        }
+
+        // Write the deepest directories first, so each tree can refer to its subtrees
+        let mut order: Vec<PathBuf> = dirs.keys().cloned().collect();
+        order.sort_by_key(|dir| Reverse(dir.components().count()));
+
+        let mut hashes = HashMap::new();
+        for dir in order {
+            let records = dirs.remove(&dir).unwrap_or_default();
+            let hash = GitrsObject::TreeObject(Tree { records }).write(repository)?;
+
+            if let (Some(parent), Some(name)) = (dir.parent(), dir.file_name()) {
+                dirs.entry(parent.to_path_buf()).or_default().push(Leaf {
+                    file_mode: "040000".to_string(),
+                    path: PathBuf::from(name),
+                    hash: hash.clone(),
+                });
+            }
+            hashes.insert(dir, hash);
+        }
+
+        let prefix = prefix.map(Path::to_path_buf).unwrap_or_default();
+        hashes
+            .remove(&prefix)
+            .ok_or_else(|| anyhow!("Prefix '{}' not found in index", prefix.display()))
+    }
+
+    /// Replaces the index with the contents of a tree.
+    ///
+    /// With a `prefix` (relative to the worktree) the tree is instead added under that
+    /// directory, which must not already contain tracked files.
+    pub fn read_tree(
         &mut self,
         repository: &Repository,
-        paths: &Vec<PathBuf>,
-        skip_missing: bool,
+        tree: &str,
+        prefix: Option<&Path>,
     ) -> anyhow::Result<()> {
-        for path in paths {
-            if !repository.contains(&path) {
-                return Err(anyhow!("Path {} outside worktree", path.display()));
+        let blobs = tree_blobs(repository, tree)?;
+
+        match prefix {
+            Some(prefix) => {
+                let root = repository.worktree.join(prefix);
+                if self
+                    .entries
+                    .iter()
+                    .any(|entry| entry.path.starts_with(&root))
+                {
+                    bail!("Prefix '{}' already has tracked files", prefix.display());
+                }
+
+                for (path, blob) in &blobs {
+                    self.upsert(unstat_entry(root.join(path), blob, 0));
+                }
             }
+            None => {
+                self.entries = blobs
+                    .iter()
+                    .map(|(path, blob)| unstat_entry(repository.worktree.join(path), blob, 0))
+                    .collect();
+            }
+        }
 
-            let canonical_path = fs::canonicalize(&path)?;
+        self.write(repository)
+    }
 
-            let index_opt = self
-                .entries
+    /// Merges one to three trees into the index (`read-tree -m`), returning the paths left
+    /// in conflict.
+    ///
+    /// - One tree: the index is replaced by the tree, keeping the stat information of entries
+    ///   that didn't change.
+    /// - Two trees (`HEAD`, `MERGE`): moves the index from `HEAD` to `MERGE`, keeping paths
+    ///   whose staged content differs from `HEAD` when `MERGE` didn't change them. Fails, without
+    ///   touching the index, if a staged change would be overwritten.
+    /// - Three trees (base, ours, theirs): paths changed on only one side (or the same way on
+    ///   both) take that side; the others are recorded as conflicts at stages 1-3.
+    ///
+    /// The index must not already have conflicts, and for a three-way merge it must match ours.
+    pub fn merge_trees(
+        &mut self,
+        repository: &Repository,
+        trees: &[String],
+    ) -> anyhow::Result<Vec<PathBuf>> {
+        if !(1..=3).contains(&trees.len()) {
+            bail!("Can only merge 1, 2 or 3 trees, not {}", trees.len());
+        }
+        if self.entries.iter().any(|entry| entry.stage > 0) {
+            bail!("You need to resolve your current index first");
+        }
+
+        let sides = trees
+            .iter()
+            .map(|tree| tree_blobs(repository, tree))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let current: BTreeMap<PathBuf, Content> = self
+            .entries
+            .iter()
+            .map(|entry| {
+                (
+                    repository.relative_path(&entry.path).to_path_buf(),
+                    (entry.mode, entry.sha.clone()),
+                )
+            })
+            .collect();
+
+        let paths: BTreeSet<&PathBuf> = sides
+            .iter()
+            .flat_map(BTreeMap::keys)
+            .chain(current.keys())
+            .collect();
+
+        // A three-way merge starts from `ours`, so anything staged on top of it would be lost
+        if sides.len() == 3
+            && let Some(path) = paths
                 .iter()
-                .position(|entry| entry.path == canonical_path);
-
-            match index_opt {
-                None => {
-                    if !skip_missing {
-                        return Err(anyhow!(
-                            "Path {} does not exist in index",
-                            canonical_path.display()
-                        ));
+                .find(|path| current.get(**path) != sides[1].get(**path))
+        {
+            bail!(
+                "Entry '{}' differs from the tree being merged into, cannot merge",
+                path.display()
+            );
+        }
+
+        let mut entries = Vec::new();
+        let mut conflicts = Vec::new();
+
+        for path in paths {
+            let side = |i: usize| sides[i].get(path);
+            let staged = current.get(path);
+
+            let resolved = match sides.len() {
+                1 => side(0),
+                2 => {
+                    let (head, target) = (side(0), side(1));
+                    if head == target || staged == target {
+                        staged
+                    } else if staged == head {
+                        target
+                    } else {
+                        bail!(
+                            "Entry '{}' has staged changes that would be overwritten by merge",
+                            path.display()
+                        );
                     }
                 }
-                Some(index) => {
-                    self.entries.remove(index);
+                _ => {
+                    let (base, ours, theirs) = (side(0), side(1), side(2));
+                    if ours == theirs || base == theirs {
+                        ours
+                    } else if base == ours {
+                        theirs
+                    } else {
+                        for (stage, blob) in [base, ours, theirs].into_iter().enumerate() {
+                            if let Some(blob) = blob {
+                                entries.push(unstat_entry(
+                                    repository.worktree.join(path),
+                                    blob,
+                                    stage as u8 + 1,
+                                ));
+                            }
+                        }
+                        conflicts.push(path.clone());
+                        continue;
+                    }
                 }
+            };
+
+            let Some(blob) = resolved else {
+                continue;
+            };
+            let full_path = repository.worktree.join(path);
+
+            // Unchanged entries keep their stat information
+            match self.entries.iter().position(|entry| {
+                entry.path == full_path && (entry.mode, &entry.sha) == (blob.0, &blob.1)
+            }) {
+                Some(i) => entries.push(self.entries.swap_remove(i)),
+                None => entries.push(unstat_entry(full_path, blob, 0)),
             }
         }
 
+        entries.sort_by(|a, b| (&a.path, a.stage).cmp(&(&b.path, b.stage)));
+        self.entries = entries;
         self.write(repository)?;
 
-        Ok(())
+        Ok(conflicts)
+    }
+}
+
+/// Reads every non-tree entry of a tree, recursively, keyed by its path from the tree's root.
+pub fn tree_blobs(
+    repository: &Repository,
+    hash: &str,
+) -> anyhow::Result<BTreeMap<PathBuf, Content>> {
+    let tree = match GitrsObject::read(repository, hash)? {
+        GitrsObject::TreeObject(tree) => tree,
+        other => bail!("Expected a tree at {}, found {}", hash, other.get_type()),
+    };
+
+    tree.list(
+        repository,
+        &ListOptions {
+            recursive: true,
+            trees_only: false,
+            show_trees: false,
+            paths: Vec::new(),
+        },
+    )?
+    .into_iter()
+    .map(|leaf| {
+        let mode = u32::from_str_radix(&leaf.file_mode, 8)
+            .with_context(|| format!("Bad mode {} in tree {}", leaf.file_mode, hash))?;
+        Ok((leaf.path, (mode, leaf.hash)))
+    })
+    .collect()
+}
+
+/// Builds an entry for content read from a tree, with no stat information from the worktree.
+fn unstat_entry(path: PathBuf, (mode, sha): &Content, stage: u8) -> IndexEntry {
+    IndexEntry::builder()
+        .mtime(UNIX_EPOCH)
+        .sha(sha.clone())
+        .size_in_bytes(0)
+        .mode(*mode)
+        .stage(stage)
+        .path(path)
+        .build()
+}
+
+/// Removes now-empty directories between `path` and the worktree root.
+fn remove_empty_parents(repository: &Repository, path: &Path) {
+    for parent in path.ancestors().skip(1) {
+        if parent == repository.worktree || !repository::is_empty_dir(parent) {
+            break;
+        }
+        if fs::remove_dir(parent).is_err() {
+            break;
+        }
+    }
+}
+
+/// Splits `len` bytes off the front of `buf`, or returns `None` if it is too short.
+fn take_bytes<'a>(buf: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    if buf.len() < len {
+        return None;
+    }
+    let (taken, rest) = buf.split_at(len);
+    *buf = rest;
+    Some(taken)
+}
+
+/// Splits a big-endian `u32` off the front of `buf`.
+fn take_u32(buf: &mut &[u8]) -> Option<u32> {
+    Some(u32::from_be_bytes(take_bytes(buf, 4)?.try_into().ok()?))
+}
+
+/// Splits a big-endian `u64` off the front of `buf`.
+fn take_u64(buf: &mut &[u8]) -> Option<u64> {
+    Some(u64::from_be_bytes(take_bytes(buf, 8)?.try_into().ok()?))
+}
+
+/// Hashes a path to be staged as a blob, returning its mode and hash along with its metadata.
+///
+/// Regular files are hashed as they are read rather than loaded whole. Directories are nested
+/// repositories, recorded as a gitlink to the commit they have checked out.
+fn hash_worktree_path(repository: &Repository, path: &Path) -> anyhow::Result<(Content, Metadata)> {
+    let metadata =
+        fs::symlink_metadata(path).with_context(|| format!("Failed to stat {}", path.display()))?;
+
+    let content = if metadata.is_dir() {
+        let head = worktree::submodule_head(path).ok_or_else(|| {
+            anyhow!(
+                "Submodule '{}' has no commit checked out",
+                repository.relative_path(path).display()
+            )
+        })?;
+        (MODE_GITLINK, head)
+    } else if metadata.file_type().is_symlink() {
+        let (mode, data) = worktree::read_file(path)?;
+        (mode, GitrsObject::hash_raw(&data, &ObjectType::Blob, None)?)
+    } else {
+        let file =
+            File::open(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let sha = GitrsObject::hash_reader(BufReader::new(file), metadata.len(), &ObjectType::Blob)
+            .with_context(|| format!("Failed to hash {}", path.display()))?;
+        (worktree::file_mode(&metadata), sha)
+    };
+
+    Ok((content, metadata))
+}
+
+/// Stores the content of the file at `path` as a blob, checking it still hashes to `sha`.
+///
+/// Regular files are compressed as they are read rather than loaded whole.
+fn write_blob(repository: &Repository, sha: &str, path: &Path) -> anyhow::Result<()> {
+    let metadata =
+        fs::symlink_metadata(path).with_context(|| format!("Failed to stat {}", path.display()))?;
+
+    let written = if metadata.file_type().is_symlink() {
+        let (_, data) = worktree::read_file(path)?;
+        GitrsObject::hash_raw(&data, &ObjectType::Blob, Some(repository))?
+    } else {
+        let file =
+            File::open(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        GitrsObject::write_stream(
+            repository,
+            BufReader::new(file),
+            metadata.len(),
+            &ObjectType::Blob,
+        )
+        .with_context(|| format!("Failed to store {}", path.display()))?
+    };
+
+    if written != sha {
+        bail!("{} changed while being added", path.display());
+    }
+    Ok(())
+}
+
+/// Applies `f` to every item on up to `jobs` threads, returning the results in order.
+///
+/// Stops handing out items once one of them fails, and returns the error of the earliest item
+/// that failed.
+fn map_parallel<T: Sync, U: Send>(
+    items: &[T],
+    jobs: usize,
+    f: impl Fn(&T) -> anyhow::Result<U> + Sync,
+) -> anyhow::Result<Vec<U>> {
+    if jobs <= 1 || items.len() <= 1 {
+        return items.iter().map(f).collect();
+    }
+
+    let next = AtomicUsize::new(0);
+    let work = || -> Result<Vec<(usize, U)>, (usize, anyhow::Error)> {
+        let mut results = Vec::new();
+        loop {
+            let i = next.fetch_add(1, Ordering::Relaxed);
+            let Some(item) = items.get(i) else {
+                return Ok(results);
+            };
+            match f(item) {
+                Ok(result) => results.push((i, result)),
+                Err(e) => {
+                    next.store(items.len(), Ordering::Relaxed);
+                    return Err((i, e));
+                }
+            }
+        }
+    };
+
+    // Every item before a failing one has already been handed out, so the failure with the lowest
+    // index is the one a sequential run would have hit first
+    let mut results = Vec::new();
+    let mut first_error: Option<(usize, anyhow::Error)> = None;
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs.min(items.len()))
+            .map(|_| scope.spawn(work))
+            .collect();
+
+        for worker in workers {
+            match worker
+                .join()
+                .unwrap_or_else(|_| Err((0, anyhow!("Worker thread panicked"))))
+            {
+                Ok(done) => results.extend(done),
+                Err((i, e)) => {
+                    if first_error.as_ref().is_none_or(|(first, _)| i < *first) {
+                        first_error = Some((i, e));
+                    }
+                }
+            }
+        }
+    });
+    if let Some((_, e)) = first_error {
+        return Err(e);
+    }
+
+    results.sort_by_key(|(i, _)| *i);
+    Ok(results.into_iter().map(|(_, result)| result).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use super::*;
+    use crate::testing::TempRepo;
+
+    /// A repository with `a.txt` and `link -> a.txt` staged.
+    fn repo_with_symlink() -> (TempRepo, Index) {
+        let repo = TempRepo::new();
+        repo.write("a.txt", "hello\n");
+        symlink("a.txt", repo.path("link")).unwrap();
+
+        let mut index = Index::default();
+        index.add(&repo, &[], &AddOptions::default()).unwrap();
+        (repo, index)
+    }
+
+    fn staged(index: &Index) -> Vec<(u32, PathBuf)> {
+        index
+            .entries
+            .iter()
+            .map(|entry| (entry.mode, entry.path.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn add_stages_a_symlink_named_directly() {
+        let repo = TempRepo::new();
+        repo.write("a.txt", "hello\n");
+        fs::create_dir(repo.path("dir")).unwrap();
+        symlink("a.txt", repo.path("link")).unwrap();
+        symlink("dir", repo.path("dirlink")).unwrap();
+
+        let mut index = Index::default();
+        index
+            .add(
+                &repo,
+                &[repo.path("a.txt"), repo.path("link"), repo.path("dirlink")],
+                &AddOptions::default(),
+            )
+            .unwrap();
+
+        assert_eq!(
+            staged(&index),
+            [
+                (MODE_FILE, repo.path("a.txt")),
+                (MODE_SYMLINK, repo.path("dirlink")),
+                (MODE_SYMLINK, repo.path("link"))
+            ]
+        );
+        let link = index.entries.iter().find(|e| e.path == repo.path("link"));
+        let GitrsObject::BlobObject(blob) = GitrsObject::read(&repo, &link.unwrap().sha).unwrap()
+        else {
+            panic!("symlink is not stored as a blob");
+        };
+        assert_eq!(blob.get_data(), b"a.txt");
+    }
+
+    #[test]
+    fn rm_removes_the_symlink_not_its_target() {
+        let (repo, mut index) = repo_with_symlink();
+
+        let removed = index
+            .rm(&repo, &[repo.path("link")], &RmOptions::default())
+            .unwrap();
+
+        assert_eq!(removed, [repo.path("link")]);
+        assert_eq!(staged(&index), [(MODE_FILE, repo.path("a.txt"))]);
+        assert!(fs::symlink_metadata(repo.path("link")).is_err());
+        assert_eq!(fs::read_to_string(repo.path("a.txt")).unwrap(), "hello\n");
+    }
+
+    #[test]
+    fn mv_moves_the_symlink_not_its_target() {
+        let (repo, mut index) = repo_with_symlink();
+
+        index
+            .mv(&repo, &[repo.path("link")], &repo.path("x"), false)
+            .unwrap();
+
+        assert_eq!(
+            staged(&index),
+            [
+                (MODE_FILE, repo.path("a.txt")),
+                (MODE_SYMLINK, repo.path("x"))
+            ]
+        );
+        assert_eq!(fs::read_link(repo.path("x")).unwrap(), Path::new("a.txt"));
+        assert_eq!(fs::read_to_string(repo.path("a.txt")).unwrap(), "hello\n");
+    }
+
+    #[test]
+    fn mv_into_a_symlinked_directory_is_refused() {
+        let (repo, mut index) = repo_with_symlink();
+        fs::create_dir(repo.path("dir")).unwrap();
+        symlink("dir", repo.path("dirlink")).unwrap();
+
+        // The link is an existing non-directory destination, not a directory to move into
+        assert!(
+            index
+                .mv(&repo, &[repo.path("a.txt")], &repo.path("dirlink"), false)
+                .is_err()
+        );
+        assert!(repo.path("a.txt").exists());
+    }
+
+    #[test]
+    fn mv_refuses_conflicting_moves_before_moving_anything() {
+        let (repo, mut index) = repo_with_files();
+        repo.write("other/b", "other\n");
+        add(&repo, &mut index, &[], AddOptions::default());
+        fs::create_dir(repo.path("to")).unwrap();
+        let before = tracked(&index);
+
+        // Both would land on to/b
+        let sources = [repo.path("a"), repo.path("dir/b"), repo.path("other/b")];
+        assert!(index.mv(&repo, &sources, &repo.path("to"), false).is_err());
+        // One source lives inside another
+        let sources = [repo.path("dir/b"), repo.path("dir")];
+        assert!(index.mv(&repo, &sources, &repo.path("to"), false).is_err());
+
+        assert_eq!(tracked(&index), before);
+        assert_eq!(tracked(&Index::read(&repo).unwrap()), before);
+        for path in ["a", "dir/b", "other/b"] {
+            assert!(repo.path(path).exists(), "{path} was moved");
+        }
+        assert!(fs::read_dir(repo.path("to")).unwrap().next().is_none());
+    }
+
+    fn entry(path: PathBuf, mode: u32, stage: u8) -> IndexEntry {
+        IndexEntry::builder()
+            .mtime(UNIX_EPOCH + Duration::from_secs(1527025023))
+            .sha(format!("{:040x}", mode + u32::from(stage)))
+            .size_in_bytes(42)
+            .mode(mode)
+            .stage(stage)
+            .path(path)
+            .build()
+    }
+
+    fn described(index: &Index) -> Vec<(PathBuf, u32, u8, String, u64, SystemTime)> {
+        index
+            .entries
+            .iter()
+            .map(|e| {
+                (
+                    e.path.clone(),
+                    e.mode,
+                    e.stage,
+                    e.sha.clone(),
+                    e.size_in_bytes,
+                    e.mtime,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn write_then_read_keeps_every_field() {
+        let repo = TempRepo::new();
+        let index = Index {
+            version: INDEX_VERSION,
+            entries: vec![
+                entry(repo.path("conflict"), MODE_FILE, 1),
+                entry(repo.path("conflict"), MODE_EXECUTABLE, 2),
+                entry(repo.path("conflict"), MODE_FILE, 3),
+                entry(repo.path("dir/link"), MODE_SYMLINK, 0),
+                entry(repo.path("sub"), MODE_GITLINK, 0),
+                entry(repo.path("tool"), MODE_EXECUTABLE, 0),
+            ],
+        };
+        index.write(&repo).unwrap();
+
+        let data = fs::read(repo.gitdir.join("index")).unwrap();
+        assert_eq!(&data[..8], b"GRIX\0\0\0\x01");
+        assert_eq!(described(&Index::read(&repo).unwrap()), described(&index));
+    }
+
+    #[test]
+    fn legacy_indexes_are_still_read() {
+        let repo = TempRepo::new();
+        let tool = entry(repo.path("tool"), MODE_EXECUTABLE, 2);
+        let with_mode = tool.to_bytes().unwrap();
+        // Version 2 entries have no mode or stage between the size and the path
+        let mut without_mode = with_mode[..36].to_vec();
+        without_mode.extend_from_slice(&with_mode[41..]);
+
+        for (version, entry, mode, stage) in [
+            (3u32, &with_mode, MODE_EXECUTABLE, 2),
+            (2, &without_mode, MODE_FILE, 0),
+        ] {
+            let mut data = b"DIRC".to_vec();
+            data.extend_from_slice(&version.to_be_bytes());
+            data.extend_from_slice(&1u32.to_be_bytes());
+            data.extend_from_slice(entry);
+            fs::write(repo.gitdir.join("index"), data).unwrap();
+
+            let index = Index::read(&repo).unwrap();
+            assert_eq!(index.version, INDEX_VERSION);
+            assert_eq!(
+                described(&index),
+                [(
+                    repo.path("tool"),
+                    mode,
+                    stage,
+                    tool.sha.clone(),
+                    42,
+                    tool.mtime
+                )]
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_signatures_and_versions_are_rejected() {
+        let repo = TempRepo::new();
+        for header in [
+            &b"DIRC\0\0\0\x04\0\0\0\0"[..],
+            b"GRIX\0\0\0\x02\0\0\0\0",
+            b"XXXX\0\0\0\x01\0\0\0\0",
+            b"GR",
+        ] {
+            fs::write(repo.gitdir.join("index"), header).unwrap();
+            let error = Index::read(&repo).err().unwrap();
+            assert!(matches!(
+                GitrsError::find(&error),
+                Some(GitrsError::InvalidIndex(_))
+            ));
+        }
+    }
+
+    /// Writes a tree holding `files` (name and content pairs) and returns its hash.
+    fn tree_of(repo: &TempRepo, files: &[(&str, &str)]) -> String {
+        let mut index = Index::default();
+        for (name, content) in files {
+            let sha =
+                GitrsObject::hash_raw(content.as_bytes(), &ObjectType::Blob, Some(repo)).unwrap();
+            index.upsert(unstat_entry(repo.path(name), &(MODE_FILE, sha), 0));
+        }
+        index.write_tree(repo, None).unwrap()
+    }
+
+    /// The staged content of each path and stage, with paths relative to the worktree.
+    fn contents(repo: &TempRepo, index: &Index) -> Vec<(String, u8, String)> {
+        index
+            .entries
+            .iter()
+            .map(|entry| {
+                let content = match GitrsObject::read(repo, &entry.sha).unwrap() {
+                    GitrsObject::BlobObject(blob) => blob.into_data(),
+                    _ => panic!("not a blob"),
+                };
+                (
+                    repo.relative_path(&entry.path).display().to_string(),
+                    entry.stage,
+                    String::from_utf8(content).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn write_tree_matches_git() {
+        let (repo, index) = repo_with_files();
+
+        assert_eq!(
+            index.write_tree(&repo, None).unwrap(),
+            "5133e6bc499e3fcd41adeb014d20097055b6211e"
+        );
+        assert_eq!(
+            index.write_tree(&repo, Some(Path::new("dir"))).unwrap(),
+            "e42ba3e77f66f623836b47df796932f7e5604aec"
+        );
+        assert!(index.write_tree(&repo, Some(Path::new("nope"))).is_err());
+    }
+
+    #[test]
+    fn read_tree_with_prefix_round_trips() {
+        let (repo, mut index) = repo_with_files();
+        let dir = index.write_tree(&repo, Some(Path::new("dir"))).unwrap();
+
+        index
+            .read_tree(&repo, &dir, Some(Path::new("copy/of")))
+            .unwrap();
+        assert_eq!(
+            tracked(&Index::read(&repo).unwrap()),
+            ["a", "copy/of/b", "copy/of/c", "dir/b", "dir/c"].map(|name| repo.path(name))
+        );
+        assert_eq!(
+            index.write_tree(&repo, Some(Path::new("copy/of"))).unwrap(),
+            dir
+        );
+        // The prefix now has tracked files
+        assert!(
+            index
+                .read_tree(&repo, &dir, Some(Path::new("copy")))
+                .is_err()
+        );
+
+        index.read_tree(&repo, &dir, None).unwrap();
+        assert_eq!(tracked(&index), [repo.path("b"), repo.path("c")]);
+    }
+
+    #[test]
+    fn two_tree_merge_keeps_staged_changes_it_doesnt_touch() {
+        let repo = TempRepo::new();
+        let head = tree_of(
+            &repo,
+            &[("same", "1\n"), ("changed", "1\n"), ("staged", "1\n")],
+        );
+        let target = tree_of(
+            &repo,
+            &[("same", "1\n"), ("changed", "2\n"), ("staged", "1\n")],
+        );
+
+        let mut index = Index::default();
+        index.read_tree(&repo, &head, None).unwrap();
+        let sha = GitrsObject::hash_raw(b"local\n", &ObjectType::Blob, Some(&repo)).unwrap();
+        index.upsert(unstat_entry(repo.path("staged"), &(MODE_FILE, sha), 0));
+
+        let trees = [head.clone(), target];
+        assert!(index.merge_trees(&repo, &trees).unwrap().is_empty());
+        assert_eq!(
+            contents(&repo, &index),
+            [
+                ("changed".to_string(), 0, "2\n".to_string()),
+                ("same".to_string(), 0, "1\n".to_string()),
+                ("staged".to_string(), 0, "local\n".to_string()),
+            ]
+        );
+
+        // A staged change the merge would overwrite is refused
+        let other = tree_of(
+            &repo,
+            &[("same", "1\n"), ("changed", "2\n"), ("staged", "3\n")],
+        );
+        assert!(index.merge_trees(&repo, &[head, other]).is_err());
+        assert_eq!(
+            contents(&repo, &Index::read(&repo).unwrap())[2].2,
+            "local\n"
+        );
+    }
+
+    #[test]
+    fn three_tree_merge_records_conflicts_in_stages() {
+        let repo = TempRepo::new();
+        let base = tree_of(
+            &repo,
+            &[("both", "base\n"), ("ours", "base\n"), ("theirs", "base\n")],
+        );
+        let ours = tree_of(
+            &repo,
+            &[("both", "ours\n"), ("ours", "ours\n"), ("theirs", "base\n")],
+        );
+        let theirs = tree_of(
+            &repo,
+            &[
+                ("both", "theirs\n"),
+                ("ours", "base\n"),
+                ("theirs", "theirs\n"),
+                ("new", "new\n"),
+            ],
+        );
+
+        let mut index = Index::default();
+        index.read_tree(&repo, &ours, None).unwrap();
+        let conflicts = index.merge_trees(&repo, &[base, ours, theirs]).unwrap();
+
+        assert_eq!(conflicts, [PathBuf::from("both")]);
+        let entry =
+            |path: &str, stage, content: &str| (path.to_string(), stage, content.to_string());
+        assert_eq!(
+            contents(&repo, &index),
+            [
+                entry("both", 1, "base\n"),
+                entry("both", 2, "ours\n"),
+                entry("both", 3, "theirs\n"),
+                entry("new", 0, "new\n"),
+                entry("ours", 0, "ours\n"),
+                entry("theirs", 0, "theirs\n"),
+            ]
+        );
+        assert!(index.write_tree(&repo, None).is_err());
+        // Conflicts have to be resolved before merging again
+        let empty = tree_of(&repo, &[]);
+        let trees = [empty.clone(), empty.clone(), empty];
+        assert!(index.merge_trees(&repo, &trees).is_err());
+    }
+
+    #[test]
+    fn three_tree_merge_needs_the_index_to_match_ours() {
+        let repo = TempRepo::new();
+        let base = tree_of(&repo, &[("file", "base\n")]);
+        let ours = tree_of(&repo, &[("file", "base\n")]);
+        let theirs = tree_of(&repo, &[("file", "theirs\n")]);
+        let trees = [base, ours.clone(), theirs];
+
+        for staged in [("file", "local\n"), ("extra", "new\n")] {
+            let mut index = Index::default();
+            index.read_tree(&repo, &ours, None).unwrap();
+            let sha =
+                GitrsObject::hash_raw(staged.1.as_bytes(), &ObjectType::Blob, Some(&repo)).unwrap();
+            index.upsert(unstat_entry(repo.path(staged.0), &(MODE_FILE, sha), 0));
+            index.write(&repo).unwrap();
+            let before = contents(&repo, &index);
+
+            assert!(index.merge_trees(&repo, &trees).is_err());
+            assert_eq!(contents(&repo, &Index::read(&repo).unwrap()), before);
+        }
+    }
+
+    fn add(
+        repo: &TempRepo,
+        index: &mut Index,
+        paths: &[&str],
+        options: AddOptions,
+    ) -> Vec<IndexChange> {
+        let paths: Vec<PathBuf> = paths.iter().map(|path| repo.path(path)).collect();
+        index.add(repo, &paths, &options).unwrap()
+    }
+
+    fn tracked(index: &Index) -> Vec<PathBuf> {
+        index
+            .entries
+            .iter()
+            .map(|entry| entry.path.clone())
+            .collect()
+    }
+
+    /// A repository with `a`, `dir/b` and `dir/c` staged.
+    fn repo_with_files() -> (TempRepo, Index) {
+        let repo = TempRepo::new();
+        repo.write("a", "a\n");
+        repo.write("dir/b", "b\n");
+        repo.write("dir/c", "c\n");
+
+        let mut index = Index::default();
+        add(&repo, &mut index, &[], AddOptions::default());
+        (repo, index)
+    }
+
+    #[test]
+    fn add_stages_deletions_under_a_path_only_with_all() {
+        let (repo, mut index) = repo_with_files();
+        fs::remove_file(repo.path("dir/b")).unwrap();
+        repo.write("dir/c", "changed\n");
+        repo.write("dir/d", "d\n");
+
+        let changes = add(&repo, &mut index, &["dir"], AddOptions::default());
+        assert_eq!(
+            changes,
+            [
+                IndexChange::Add(repo.path("dir/c")),
+                IndexChange::Add(repo.path("dir/d"))
+            ]
+        );
+        assert!(tracked(&index).contains(&repo.path("dir/b")));
+
+        let all = AddOptions {
+            all: true,
+            ..Default::default()
+        };
+        let changes = add(&repo, &mut index, &["dir"], all);
+        assert_eq!(changes, [IndexChange::Remove(repo.path("dir/b"))]);
+        assert_eq!(
+            tracked(&index),
+            [repo.path("a"), repo.path("dir/c"), repo.path("dir/d")]
+        );
+    }
+
+    #[test]
+    fn add_update_only_touches_tracked_files() {
+        let (repo, mut index) = repo_with_files();
+        repo.write("a", "changed\n");
+        fs::remove_file(repo.path("dir/b")).unwrap();
+        repo.write("new", "new\n");
+
+        let update = AddOptions {
+            update: true,
+            ..Default::default()
+        };
+        let changes = add(&repo, &mut index, &[], update);
+        assert_eq!(
+            changes,
+            [
+                IndexChange::Remove(repo.path("dir/b")),
+                IndexChange::Add(repo.path("a"))
+            ]
+        );
+        assert_eq!(tracked(&index), [repo.path("a"), repo.path("dir/c")]);
+    }
+
+    #[test]
+    fn add_stages_tracked_files_matching_an_ignore_rule() {
+        let (repo, mut index) = repo_with_files();
+        repo.write(".gitrsignore", "a\ndir/\n");
+        add(&repo, &mut index, &[".gitrsignore"], AddOptions::default());
+        repo.write("a", "changed\n");
+        repo.write("dir/c", "changed\n");
+        repo.write("dir/untracked", "x\n");
+
+        for options in [
+            AddOptions {
+                update: true,
+                ..Default::default()
+            },
+            AddOptions {
+                all: true,
+                ..Default::default()
+            },
+        ] {
+            let dry_run = AddOptions {
+                dry_run: true,
+                ..options
+            };
+            let changes = add(&repo, &mut index, &[], dry_run);
+            assert_eq!(
+                changes,
+                [
+                    IndexChange::Add(repo.path("a")),
+                    IndexChange::Add(repo.path("dir/c"))
+                ]
+            );
+        }
+
+        // Naming the ignored directory stages its tracked files, but not the untracked one
+        let changes = add(&repo, &mut index, &["dir"], AddOptions::default());
+        assert_eq!(changes, [IndexChange::Add(repo.path("dir/c"))]);
+    }
+
+    #[test]
+    fn add_refuses_ignored_paths_without_force() {
+        let repo = TempRepo::new();
+        repo.write(".gitrsignore", "*.log\nbuild/\n");
+        repo.write("debug.log", "x\n");
+        repo.write("build/out", "x\n");
+        let mut index = Index::default();
+
+        for path in ["debug.log", "build", "build/out"] {
+            assert!(
+                index
+                    .add(&repo, &[repo.path(path)], &AddOptions::default())
+                    .is_err(),
+                "{path}"
+            );
+        }
+        // Adding everything skips them silently
+        add(&repo, &mut index, &[], AddOptions::default());
+        assert_eq!(tracked(&index), [repo.path(".gitrsignore")]);
+
+        let force = AddOptions {
+            force: true,
+            ..Default::default()
+        };
+        add(&repo, &mut index, &["build", "debug.log"], force);
+        assert_eq!(
+            tracked(&index),
+            [
+                repo.path(".gitrsignore"),
+                repo.path("build/out"),
+                repo.path("debug.log")
+            ]
+        );
+    }
+
+    #[test]
+    fn add_dry_run_changes_nothing() {
+        let (repo, mut index) = repo_with_files();
+        repo.write("a", "changed\n");
+        repo.write("new", "new\n");
+        let before: Vec<_> = index.entries.iter().map(|e| e.sha.clone()).collect();
+
+        let dry_run = AddOptions {
+            dry_run: true,
+            ..Default::default()
+        };
+        let changes = add(&repo, &mut index, &[], dry_run);
+        assert_eq!(
+            changes,
+            [
+                IndexChange::Add(repo.path("a")),
+                IndexChange::Add(repo.path("new"))
+            ]
+        );
+
+        let after: Vec<_> = index.entries.iter().map(|e| e.sha.clone()).collect();
+        assert_eq!(before, after);
+        assert_eq!(Index::read(&repo).unwrap().entries.len(), 3);
+        let ((_, sha), _) = hash_worktree_path(&repo, &repo.path("new")).unwrap();
+        assert!(!GitrsObject::exists(&repo, &sha));
+    }
+
+    #[test]
+    fn write_goes_through_a_lock_file() {
+        let repo = TempRepo::new();
+        repo.write("a", "a\n");
+        let mut index = Index::default();
+        index.add(&repo, &[], &AddOptions::default()).unwrap();
+        index.write(&repo).unwrap();
+        assert!(!repo.gitdir.join("index.lock").exists());
+
+        fs::write(repo.gitdir.join("index.lock"), "").unwrap();
+        let err = Index::default().write(&repo).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<GitrsError>(),
+            Some(GitrsError::RefLockHeld { .. })
+        ));
+        // The existing index is left alone
+        assert_eq!(Index::read(&repo).unwrap().entries.len(), 1);
+    }
+
+    #[test]
+    fn map_parallel_keeps_order_and_returns_the_first_error() {
+        let items: Vec<usize> = (0..200).collect();
+        for jobs in [1, 4] {
+            let doubled = map_parallel(&items, jobs, |i| {
+                // Uneven work so results come back out of order
+                thread::sleep(Duration::from_micros((*i as u64 * 7) % 50));
+                Ok(i * 2)
+            })
+            .unwrap();
+            assert_eq!(doubled, items.iter().map(|i| i * 2).collect::<Vec<_>>());
+
+            let err = map_parallel(&items, jobs, |i| {
+                if *i > 50 {
+                    thread::sleep(Duration::from_micros(100));
+                }
+                if *i == 50 || *i >= 150 {
+                    bail!("failed on {i}")
+                }
+                Ok(*i)
+            })
+            .unwrap_err();
+            assert_eq!(err.to_string(), "failed on 50");
+        }
     }
 }
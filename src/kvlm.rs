@@ -1,154 +1,282 @@
 // Key-Value List with Message
-use indexmap::IndexMap;
 
 /// Represents a key-value list with an optional message body.
 ///
-/// This structure preserves insertion order of keys using `IndexMap`,
-/// which is important for serialization correctness.
-///
-/// Note:
-/// - Duplicate keys must appear consecutively for order to be preserved.
-/// - The message body (if any) is stored with a `None` key.
+/// Headers and the message are kept as raw bytes in their original order, so any well-formed
+/// commit or tag object serializes back to exactly the bytes it was parsed from (even with a
+/// non-UTF-8 message or a multi-line `gpgsig` header).
 pub struct Kvlm {
-    /// Maps keys (optional `String`) to one or more values (`Vec<String>`).
-    /// The `None` key is reserved for the message body.
-    data: IndexMap<Option<String>, Vec<String>>,
+    /// `(key, value)` pairs; continuation lines are stored without their leading space.
+    headers: Vec<(Vec<u8>, Vec<u8>)>,
+    /// The message body, if the object has a blank line after its headers.
+    message: Option<Vec<u8>>,
+}
+
+/// Errors raised while parsing a `Kvlm`, with the byte offset they were found at.
+#[derive(Debug, thiserror::Error)]
+pub enum KvlmError {
+    #[error("Malformed object at byte {offset}: header line has no value")]
+    MissingValue { offset: usize },
+    #[error("Malformed object at byte {offset}: empty header key")]
+    EmptyKey { offset: usize },
+    #[error("Malformed object at byte {offset}: header line is not terminated by a newline")]
+    UnterminatedHeader { offset: usize },
 }
 
 impl Kvlm {
-    /// Creates a new `Kvlm` by parsing raw byte data.
-    pub fn new(raw_data: &[u8]) -> Self {
-        Self {
-            data: Self::parse(raw_data),
+    /// Parses raw byte data into a `Kvlm`.
+    ///
+    /// Expects data to be formatted with lines of the form `key value`, continuation
+    /// lines starting with a space, and an optional message separated by a blank line.
+    pub fn parse(raw_data: &[u8]) -> Result<Self, KvlmError> {
+        let mut pos = 0;
+        let mut kvlm = Self::init();
+
+        while pos < raw_data.len() {
+            // Check for blank line separating headers from message body
+            if raw_data[pos] == b'\n' {
+                kvlm.message = Some(raw_data[pos + 1..].to_vec());
+                break;
+            }
+
+            // The key runs up to the first space, which must come before the end of the line
+            let space_idx = raw_data[pos..]
+                .iter()
+                .position(|&b| b == b' ' || b == b'\n')
+                .map(|i| i + pos)
+                .filter(|&i| raw_data[i] == b' ')
+                .ok_or(KvlmError::MissingValue { offset: pos })?;
+            if space_idx == pos {
+                return Err(KvlmError::EmptyKey { offset: pos });
+            }
+
+            // Find the end of the value, including continuation lines starting with space
+            let mut end = space_idx;
+            loop {
+                end = raw_data[end + 1..]
+                    .iter()
+                    .position(|&b| b == b'\n')
+                    .map(|i| i + end + 1)
+                    .ok_or(KvlmError::UnterminatedHeader { offset: pos })?;
+
+                if raw_data.get(end + 1) != Some(&b' ') {
+                    break;
+                }
+            }
+
+            // Drop the continuation indent after each embedded newline
+            let mut value = Vec::with_capacity(end - space_idx);
+            let mut continuation = false;
+            for &byte in &raw_data[space_idx + 1..end] {
+                if !(continuation && byte == b' ') {
+                    value.push(byte);
+                }
+                continuation = byte == b'\n';
+            }
+
+            kvlm.headers
+                .push((raw_data[pos..space_idx].to_vec(), value));
+            pos = end + 1;
         }
+
+        Ok(kvlm)
     }
 
     /// Creates an empty `Kvlm`.
     pub fn init() -> Self {
         Self {
-            data: IndexMap::new(),
+            headers: Vec::new(),
+            message: None,
         }
     }
 
     /// Serializes the key-value data (including the message if present) into a byte vector.
     ///
     /// Keys and values are serialized with continuation lines encoded with a leading space.
-    /// The message body (key = `None`) is appended after a blank line.
+    /// The message body is appended after a blank line.
     pub fn serialize(&self) -> Vec<u8> {
         let mut output = Vec::new();
 
-        for (key, values) in &self.data {
-            if let Some(field) = key {
-                for value in values {
-                    // Encode continuation lines by replacing `\n` with `\n `
-                    // and accumulate in a temporary buffer.
-                    let mut encoded = Vec::with_capacity(value.len());
-                    for (i, part) in value.split('\n').enumerate() {
-                        if i > 0 {
-                            encoded.extend_from_slice(b"\n ");
-                        }
-                        encoded.extend_from_slice(part.as_bytes());
-                    }
-
-                    output.extend_from_slice(field.as_bytes());
+        for (key, value) in &self.headers {
+            output.extend_from_slice(key);
+            output.push(b' ');
+            for &byte in value {
+                output.push(byte);
+                if byte == b'\n' {
                     output.push(b' ');
-                    output.extend_from_slice(&encoded);
-                    output.push(b'\n');
                 }
             }
+            output.push(b'\n');
         }
 
-        // Append the message body after a blank line, if it exists.
-        if let Some(messages) = self.data.get(&None) {
-            if let Some(message) = messages.first() {
-                output.push(b'\n');
-                output.extend_from_slice(message.as_bytes());
-            }
+        if let Some(message) = &self.message {
+            output.push(b'\n');
+            output.extend_from_slice(message);
         }
 
         output
     }
 
-    /// Returns a reference to the message body string.
-    pub fn get_message(&self) -> &str {
-        self.data
-            .get(&None)
-            .expect("Each Kvlm must specify a message")
-            .first()
-            .expect("Cannot have an empty message")
-            .as_str()
+    /// Returns the message body, or an empty slice if there is none.
+    pub fn get_message(&self) -> &[u8] {
+        self.message.as_deref().unwrap_or_default()
     }
 
-    /// Returns a reference to the list of values associated with a given key, if present.
-    pub fn get_key(&self, key: &str) -> Option<&Vec<String>> {
-        self.data.get(&Some(key.to_string()))
+    /// Returns every value of a key, in order.
+    pub fn get_all(&self, key: &str) -> Vec<&[u8]> {
+        self.headers
+            .iter()
+            .filter(|(k, _)| k == key.as_bytes())
+            .map(|(_, value)| value.as_slice())
+            .collect()
     }
 
-    /// Inserts a single value for the specified key, replacing any existing values.
-    pub fn insert(&mut self, key: &str, value: &str) {
-        self.data
-            .insert(Some(key.to_string()), vec![value.to_string()]);
+    /// Returns the first value of a key, if present.
+    pub fn get_first(&self, key: &str) -> Option<&[u8]> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k == key.as_bytes())
+            .map(|(_, value)| value.as_slice())
     }
 
-    /// Parses raw byte data into an `IndexMap` of key-value pairs with an optional message.
+    /// Iterates over every header as `(key, value)` pairs in serialization order.
+    pub fn headers(&self) -> impl Iterator<Item = (&[u8], &[u8])> {
+        self.headers
+            .iter()
+            .map(|(key, value)| (key.as_slice(), value.as_slice()))
+    }
+
+    /// Inserts a single value for the specified key, replacing any existing values.
     ///
-    /// Expects data to be formatted with lines of the form `key value`, continuation
-    /// lines starting with a space, and an optional message separated by a blank line.
-    fn parse(raw_data: &[u8]) -> IndexMap<Option<String>, Vec<String>> {
-        let mut pos = 0;
-        let mut result: IndexMap<Option<String>, Vec<String>> = IndexMap::new();
+    /// The value takes the place of the first existing one, or goes last if the key is new.
+    pub fn insert(&mut self, key: &str, value: impl AsRef<[u8]>) {
+        let mut replaced = false;
 
-        while pos < raw_data.len() {
-            // Check for blank line separating headers from message body
-            if raw_data[pos] == b'\n' {
-                let message = String::from_utf8_lossy(&raw_data[pos + 1..]).into_owned();
-                result.insert(None, vec![message]);
-                break;
+        self.headers.retain_mut(|(k, v)| {
+            if k != key.as_bytes() {
+                return true;
             }
+            if replaced {
+                return false;
+            }
+            *v = value.as_ref().to_vec();
+            replaced = true;
+            true
+        });
 
-            // Find space separating key and value
-            let space_idx = raw_data[pos..]
-                .iter()
-                .position(|&b| b == b' ')
-                .map(|i| i + pos)
-                .expect("Expected space after key");
+        if !replaced {
+            self.append(key, value);
+        }
+    }
 
-            // Extract key as UTF-8 string
-            let key = String::from_utf8_lossy(&raw_data[pos..space_idx]).into_owned();
+    /// Appends a value for the specified key, keeping any existing values.
+    pub fn append(&mut self, key: &str, value: impl AsRef<[u8]>) {
+        self.headers
+            .push((key.as_bytes().to_vec(), value.as_ref().to_vec()));
+    }
 
-            // Find the end of the value, including continuation lines starting with space
-            let mut end = space_idx;
-            loop {
-                let newline_idx = raw_data[end + 1..]
-                    .iter()
-                    .position(|&b| b == b'\n')
-                    .map(|i| i + end + 1)
-                    .expect("Expected newline");
+    /// Sets the message body, replacing any existing one.
+    pub fn set_message(&mut self, message: impl AsRef<[u8]>) {
+        self.message = Some(message.as_ref().to_vec());
+    }
+}
 
-                end = newline_idx;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-                // If next line doesn't start with space, break
-                if raw_data.get(newline_idx + 1) != Some(&b' ') {
-                    break;
-                }
-            }
+    const SIGNED: &[u8] = b"tree 29ff16c9c14e2652b22f8b78bb08a5a07930c147
+parent 206941306e8a8af65b66eaaaea388a7ae24d49a0
+parent 4a5e2b9d6a1c1f2ad38e0c6c5cb3bd21c2a0f1e7
+author A U Thor <author@example.com> 1527025023 +0200
+committer A U Thor <author@example.com> 1527025044 +0200
+gpgsig -----BEGIN PGP SIGNATURE-----
+ 
+ iQIzBAABCAAdFiEExwXquOM8bWb4Q2zVGxM2FxoLkGQFAlsEjZQACgkQGxM2FxoL
+ kGQdcBAAqPP+ln4nGDd2gETXjvOpOxLzIMEw4A9gU6CzWzm+oB8mEIKyaH0UFIPh
+ =lgTX
+ -----END PGP SIGNATURE-----
 
-            // Extract the raw value slice, including continuation lines
-            let value_raw_data = &raw_data[space_idx + 1..=end];
-            // Replace continuation indent "\n " with "\n"
-            let value = String::from_utf8_lossy(value_raw_data)
-                .replace("\n ", "\n")
-                .to_string();
+Create first draft
+";
 
-            // Insert or append to the key's vector of values
-            result
-                .entry(Some(key))
-                .and_modify(|v| v.push(value.clone()))
-                .or_insert_with(|| vec![value]);
+    #[test]
+    fn signed_commit_roundtrips() {
+        let kvlm = Kvlm::parse(SIGNED).unwrap();
 
-            pos = end + 1;
-        }
+        let signature = kvlm.get_first("gpgsig").unwrap();
+        assert!(signature.starts_with(b"-----BEGIN PGP SIGNATURE-----\n\niQIz"));
+        assert!(signature.ends_with(b"=lgTX\n-----END PGP SIGNATURE-----"));
+        assert_eq!(kvlm.get_message(), b"Create first draft\n");
+        assert_eq!(kvlm.serialize(), SIGNED);
+    }
+
+    #[test]
+    fn duplicate_keys_keep_their_order() {
+        let mut kvlm = Kvlm::parse(SIGNED).unwrap();
+        assert_eq!(
+            kvlm.get_all("parent"),
+            [
+                &b"206941306e8a8af65b66eaaaea388a7ae24d49a0"[..],
+                b"4a5e2b9d6a1c1f2ad38e0c6c5cb3bd21c2a0f1e7",
+            ]
+        );
+        assert_eq!(
+            kvlm.get_first("parent"),
+            Some(&b"206941306e8a8af65b66eaaaea388a7ae24d49a0"[..])
+        );
+
+        // Inserting replaces the first value in place and drops the others
+        kvlm.insert("parent", "0000000000000000000000000000000000000000");
+        let keys: Vec<_> = kvlm.headers().map(|(key, _)| key).collect();
+        assert_eq!(
+            keys,
+            [&b"tree"[..], b"parent", b"author", b"committer", b"gpgsig"]
+        );
+        assert_eq!(kvlm.get_all("parent").len(), 1);
+    }
+
+    #[test]
+    fn missing_and_empty_messages_roundtrip() {
+        let headers_only = b"object abc\ntype commit\n";
+        let kvlm = Kvlm::parse(headers_only).unwrap();
+        assert_eq!(kvlm.get_message(), b"");
+        assert_eq!(kvlm.serialize(), headers_only);
+
+        let empty_message = b"object abc\n\n";
+        let kvlm = Kvlm::parse(empty_message).unwrap();
+        assert_eq!(kvlm.get_message(), b"");
+        assert_eq!(kvlm.serialize(), empty_message);
+
+        // Bytes after the blank line are the message verbatim, even if they look like headers
+        let raw = b"tree abc\n\nparent def\n\xff\n";
+        let kvlm = Kvlm::parse(raw).unwrap();
+        assert_eq!(kvlm.get_message(), b"parent def\n\xff\n");
+        assert_eq!(kvlm.get_all("parent").len(), 0);
+        assert_eq!(kvlm.serialize(), raw);
+    }
 
-        result
+    #[test]
+    fn errors_report_their_offset() {
+        assert!(matches!(
+            Kvlm::parse(b"tree abc\nnovalue\n"),
+            Err(KvlmError::MissingValue { offset: 9 })
+        ));
+        assert!(matches!(
+            Kvlm::parse(b"tree"),
+            Err(KvlmError::MissingValue { offset: 0 })
+        ));
+        assert!(matches!(
+            Kvlm::parse(b" tree abc\n"),
+            Err(KvlmError::EmptyKey { offset: 0 })
+        ));
+        assert!(matches!(
+            Kvlm::parse(b"tree abc\nparent def"),
+            Err(KvlmError::UnterminatedHeader { offset: 9 })
+        ));
+        assert!(matches!(
+            Kvlm::parse(b"tree abc\ngpgsig line\n continued"),
+            Err(KvlmError::UnterminatedHeader { offset: 9 })
+        ));
     }
 }
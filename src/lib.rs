@@ -0,0 +1,37 @@
+//! A small git implementation, storing its repository in a `.gitrs` directory.
+//!
+//! The types re-exported at the crate root are the stable API for reading and writing a
+//! repository: open one with [`Repository::find_repository`] (or [`Repository::init`]), read
+//! objects with [`GitrsObject::find`] and [`GitrsObject::read`], stage changes through
+//! [`Index`], and use [`Repository::commit`], [`Repository::status`] and [`Repository::log`]
+//! for the common workflows. Errors are `anyhow::Error`s; those with a well-known cause carry
+//! a [`GitrsError`] that can be recovered with [`GitrsError::find`].
+//!
+//! The modules themselves are public so the `git-rs` binary can build its commands on them,
+//! but anything not re-exported here may change between releases.
+
+pub mod batch;
+pub mod config;
+pub mod diff;
+pub mod error;
+pub mod ignore;
+pub mod index;
+pub mod kvlm;
+pub mod object;
+pub mod patch;
+pub mod refs;
+pub mod repository;
+pub mod status;
+pub mod submodule;
+pub mod worktree;
+
+#[cfg(test)]
+mod testing;
+
+pub use error::GitrsError;
+pub use ignore::IgnoreRules;
+pub use index::Index;
+pub use object::{GitrsObject, ObjectType, blob::Blob, commit::Commit, tag::Tag, tree::Tree};
+pub use refs::Ref;
+pub use repository::{Log, NewCommit, Repository};
+pub use status::Status;
@@ -1,24 +1,30 @@
-mod ignore;
-mod index;
-mod kvlm;
-mod object;
-mod refs;
-mod repository;
-
-use clap::{Parser, Subcommand};
-use ignore::IgnoreRules;
-use index::Index;
-use log::{error, info};
-use object::GitrsObject::{CommitObject, TreeObject};
-use object::commit::Commit;
-use object::tag::{Tag, TagType};
-use object::tree::Leaf;
-use object::{GitrsObject, ObjectFindOptions, ObjectType};
-use refs::Ref;
-use repository::Repository;
-use std::fs::{self, File};
-use std::io::{BufReader, Read};
-use std::path::{Path, PathBuf};
+use anyhow::{Context, anyhow, bail};
+use clap::{ArgAction, Parser, Subcommand};
+use git_rs::batch::{self, BatchOptions};
+use git_rs::config::{self, Config, ConfigFile, ConfigScope};
+use git_rs::ignore::{IgnoreRules, MatchKind};
+use git_rs::index::{AddOptions, Content, Index, IndexChange, IndexEntry, RmOptions};
+use git_rs::object::GitrsObject::{CommitObject, TreeObject};
+use git_rs::object::commit::Commit;
+use git_rs::object::signature::{Signature, SignatureRole};
+use git_rs::object::tag::{Tag, TagType};
+use git_rs::object::tree::{CheckoutOptions, Leaf, ListOptions};
+use git_rs::object::{GitrsObject, ObjectFindOptions, ObjectType};
+use git_rs::refs::Ref;
+use git_rs::repository::{self, Repository};
+use git_rs::status::{self, Change, ChangeKind};
+use git_rs::submodule::{Submodule, SubmoduleUpdate};
+use git_rs::{GitrsError, patch, worktree};
+use log::{LevelFilter, info, warn};
+use serde_json::{Value, json};
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process::ExitCode;
+use std::str::FromStr;
+use std::thread;
 
 /// Gitrs CLI commands
 #[derive(Subcommand, Debug)]
@@ -30,14 +36,75 @@ enum Command {
         #[arg(default_value = ".")]
         path: String,
     },
-    /// Read a file, hash its contents, and store it in the repository
+    /// Compute the object hash of files or stdin, optionally storing them in the repository
     HashObject {
-        #[arg(value_parser)]
+        /// Type of object to create
+        #[arg(short = 't', value_parser, default_value = "blob")]
         object_type: ObjectType,
-        path: String,
+        /// Write the object into the repository instead of only printing its hash
+        #[arg(short = 'w')]
+        write: bool,
+        /// Read the object's content from stdin
+        #[arg(long)]
+        stdin: bool,
+        /// Read the paths of the files to hash from stdin, one per line
+        #[arg(long = "stdin-paths", conflicts_with_all = ["stdin", "paths"])]
+        stdin_paths: bool,
+        /// Skip checking that commit, tag and tree content is well-formed
+        #[arg(long)]
+        literally: bool,
+        paths: Vec<PathBuf>,
     },
     /// Print raw (uncompressed, no header) contents of an object to stdout
-    CatFile { object: String },
+    ///
+    /// With a type, peels the object (following tags and commits) until one of that type is
+    /// found and prints its raw content
+    CatFile {
+        /// Show the object's type
+        #[arg(short = 't', group = "mode")]
+        show_type: bool,
+        /// Show the object's size in bytes
+        #[arg(short = 's', group = "mode")]
+        show_size: bool,
+        /// Exit with status 0 if the object exists and is valid, 1 otherwise, printing nothing
+        #[arg(short = 'e', group = "mode")]
+        exists: bool,
+        /// Pretty-print the object's content
+        #[arg(short = 'p', group = "mode")]
+        pretty: bool,
+        /// Print `<sha> <type> <size>` (or the given format) and the content of each object
+        /// named on stdin
+        #[arg(
+            long,
+            group = "mode",
+            value_name = "FORMAT",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = batch::DEFAULT_FORMAT
+        )]
+        batch: Option<String>,
+        /// Like --batch, but only print the `<sha> <type> <size>` (or formatted) line
+        #[arg(
+            long = "batch-check",
+            group = "mode",
+            value_name = "FORMAT",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = batch::DEFAULT_FORMAT
+        )]
+        batch_check: Option<String>,
+        /// With --batch or --batch-check, report every object instead of reading stdin (fails
+        /// if the repository has packfiles)
+        #[arg(long = "batch-all-objects")]
+        batch_all_objects: bool,
+        /// `[<type>] <object>`
+        #[arg(
+            num_args = 1..=2,
+            value_names = ["TYPE", "OBJECT"],
+            required_unless_present_any = ["batch", "batch_check"]
+        )]
+        args: Vec<String>,
+    },
     /// Log commits starting from a specified commit (default HEAD)
     Log {
         #[arg(default_value = "HEAD")]
@@ -45,18 +112,78 @@ enum Command {
     },
     /// List tree contents, optionally recursively
     LsTree {
+        /// Recurse into subtrees, showing full paths
         #[arg(short = 'r', long = "recursive")]
         recursive: bool,
+        /// Only show trees
+        #[arg(short = 'd')]
+        trees_only: bool,
+        /// Show trees even when recursing into them
+        #[arg(short = 't')]
+        show_trees: bool,
+        /// Only show paths
+        #[arg(long = "name-only", alias = "name-status")]
+        name_only: bool,
+        /// Show the size of blobs
+        #[arg(short = 'l', long = "long", conflicts_with = "name_only")]
+        long: bool,
+        /// Tree, commit or tag to list
+        tree: String,
+        /// Only show entries at or under these paths (relative to the current directory; a
+        /// trailing `/` lists a tree's contents instead of the tree)
+        paths: Vec<PathBuf>,
+    },
+    /// Write the index as a tree object and print its hash
+    WriteTree {
+        /// Write the tree for this directory (relative to the worktree root) instead
+        #[arg(long)]
+        prefix: Option<PathBuf>,
+    },
+    /// Read trees into the index
+    ///
+    /// With -m, merges one tree (keeping stat information), two trees (moving from the first
+    /// to the second) or three trees (base, ours, theirs; recording conflicts as stages 1-3)
+    ReadTree {
+        /// Merge the trees into the index instead of replacing it
+        #[arg(short = 'm', conflicts_with = "prefix")]
+        merge: bool,
+        /// Read the tree into this directory (relative to the worktree root)
+        #[arg(long)]
+        prefix: Option<PathBuf>,
+        #[arg(required = true, num_args = 1..=3)]
+        trees: Vec<String>,
+    },
+    /// Create a commit object from a tree and print its hash, without updating any ref
+    ///
+    /// The message is read from stdin if no -m is given
+    CommitTree {
         tree: String,
+        /// Parent commit (may be repeated)
+        #[arg(short = 'p')]
+        parents: Vec<String>,
+        /// Paragraph of the commit message (may be repeated)
+        #[arg(short = 'm')]
+        messages: Vec<String>,
     },
     /// Checkout a commit into a specified directory
-    Checkout { commit: String, path: String },
+    ///
+    /// The directory is created if needed; an existing one must be empty
+    Checkout {
+        commit: String,
+        path: String,
+        /// Number of threads used to write files
+        #[arg(short = 'j', long = "jobs", default_value_t = 1)]
+        jobs: usize,
+    },
     /// List references
     ShowRef,
     /// Create or list tags
     Tag {
         #[arg(short = 'a', long = "annotated")]
         annotated: bool,
+        /// Message for an annotated tag (implies -a)
+        #[arg(short = 'm', long = "message")]
+        message: Option<String>,
         name: Option<String>,
         object: Option<String>,
     },
@@ -66,25 +193,157 @@ enum Command {
         object_type: ObjectType,
         name: String,
     },
-    /// Check ignore rules against specified paths
+    /// Check ignore rules against specified paths, printing those that are ignored
+    ///
+    /// Exits with status 0 if any path is ignored and 1 otherwise. With -v, shows the source
+    /// file, line number and pattern of the matching rule.
     CheckIgnore {
-        #[arg(required = true)]
+        /// Show the matching rule for each path, including rules that re-include it
+        #[arg(id = "show_source", short = 'v', long = "verbose")]
+        show_source: bool,
+        /// Log more diagnostics
+        ///
+        /// Shares the id of the global -v so that it isn't added here, where -v is taken
+        #[arg(id = "verbose", long = "log-verbose", action = ArgAction::Count, hide = true)]
+        log_verbose: u8,
+        /// Read `.gitrsignore` files from the given tree-ish instead of the worktree
+        #[arg(long = "tree")]
+        tree: Option<String>,
+        /// Read paths from stdin, one per line
+        #[arg(long = "stdin", conflicts_with = "paths")]
+        stdin: bool,
+        /// Also show paths that don't match any rule (with -v)
+        #[arg(short = 'n', long = "non-matching", requires = "show_source")]
+        non_matching: bool,
+        /// Check tracked files too, instead of treating them as never ignored
+        #[arg(long = "no-index")]
+        no_index: bool,
+        #[arg(required_unless_present = "stdin")]
         paths: Vec<String>,
     },
-    /// Displays the names of files in the staging area
-    LsFiles,
-    /// Stages given files
+    /// Show information about files in the index and the worktree
+    LsFiles {
+        /// Show tracked files (the default when no other mode is given)
+        #[arg(short = 'c', long = "cached")]
+        cached: bool,
+        /// Show the mode, hash and merge stage of tracked files
+        #[arg(short = 's', long = "stage")]
+        stage: bool,
+        /// Show tracked files whose worktree content differs from the index (or is missing)
+        #[arg(short = 'm', long = "modified")]
+        modified: bool,
+        /// Show tracked files missing from the worktree
+        #[arg(short = 'd', long = "deleted")]
+        deleted: bool,
+        /// Show untracked files
+        #[arg(short = 'o', long = "others")]
+        others: bool,
+        /// Show only ignored files (with -o or -c); implies --exclude-standard
+        #[arg(short = 'i', long = "ignored")]
+        ignored: bool,
+        /// Skip files excluded by the standard ignore rules
+        #[arg(long = "exclude-standard")]
+        exclude_standard: bool,
+        /// Show only unmerged entries (implies -s)
+        #[arg(short = 'u', long = "unmerged")]
+        unmerged: bool,
+        /// Terminate paths with NUL instead of newline
+        #[arg(short = 'z')]
+        nul_terminated: bool,
+        /// Only show files under these paths (defaults to the current directory)
+        paths: Vec<PathBuf>,
+    },
+    /// Stages given files and directories (or the whole worktree with -A/-u/-p)
     Add {
-        #[arg(required = true)]
+        /// Also stage the deletion of tracked files missing under the given paths (the whole
+        /// worktree if none are given)
+        #[arg(short = 'A', long = "all", conflicts_with = "update")]
+        all: bool,
+        #[arg(short = 'u', long = "update")]
+        update: bool,
+        #[arg(short = 'f', long = "force")]
+        force: bool,
+        #[arg(short = 'n', long = "dry-run")]
+        dry_run: bool,
+        #[arg(short = 'p', long = "patch", conflicts_with_all = ["all", "update", "force", "dry_run"])]
+        patch: bool,
+        /// Number of threads used to hash and write files (defaults to the number of CPUs)
+        #[arg(short = 'j', long = "jobs")]
+        jobs: Option<usize>,
+        #[arg(required_unless_present_any = ["all", "update", "patch"])]
         paths: Vec<String>,
     },
-    /// Unstages given files
+    /// Removes files from the index and the worktree (or only the index with --cached)
     Rm {
+        #[arg(long = "cached")]
+        cached: bool,
+        #[arg(short = 'f', long = "force")]
+        force: bool,
+        #[arg(short = 'r')]
+        recursive: bool,
         #[arg(required = true)]
         paths: Vec<String>,
     },
-    /// Log staging area status
+    /// Moves or renames tracked files and directories
+    Mv {
+        #[arg(short = 'f', long = "force")]
+        force: bool,
+        /// One or more sources followed by the destination
+        #[arg(required = true, num_args = 2..)]
+        paths: Vec<String>,
+    },
+    /// Show staged, unstaged and untracked changes, including submodules with new commits
     Status,
+    /// Show changes between the index and the worktree (or HEAD and the index with --cached)
+    Diff {
+        #[arg(long = "cached", alias = "staged")]
+        cached: bool,
+        /// Only show changes at or under these paths
+        paths: Vec<PathBuf>,
+    },
+    /// Initialize and check out submodules declared in `.gitmodules`
+    Submodule {
+        #[command(subcommand)]
+        action: SubmoduleAction,
+    },
+    /// Get and set repository or global options
+    ///
+    /// With just a name, prints its value (exit status 1 if unset); with a name and a value,
+    /// sets it in the chosen file (the repository config by default)
+    Config {
+        #[arg(long = "global", group = "location")]
+        global: bool,
+        #[arg(long = "system", group = "location")]
+        system: bool,
+        #[arg(long = "local", group = "location")]
+        local: bool,
+        #[arg(short = 'f', long = "file", group = "location")]
+        file: Option<PathBuf>,
+        #[arg(short = 'l', long = "list", group = "action")]
+        list: bool,
+        #[arg(long = "get-all", group = "action", requires = "name")]
+        get_all: bool,
+        #[arg(long = "unset", group = "action", requires = "name")]
+        unset: bool,
+        #[arg(long = "unset-all", group = "action", requires = "name")]
+        unset_all: bool,
+        #[arg(long = "add", group = "action", requires = "value")]
+        add: bool,
+        /// Interpret values as the given type when reading them
+        #[arg(long = "type", value_parser = ["bool", "int", "path"])]
+        value_type: Option<String>,
+        /// Show the file each value was read from (with --list)
+        #[arg(long = "show-origin")]
+        show_origin: bool,
+        /// Show the scope (system, global, local) each value was read from (with --list)
+        #[arg(long = "show-scope")]
+        show_scope: bool,
+        #[arg(required_unless_present = "list")]
+        name: Option<String>,
+        value: Option<String>,
+    },
+    /// Print the author or committer identity (GIT_AUTHOR_IDENT or GIT_COMMITTER_IDENT)
+    Var { variable: String },
     /// Commit staged changes  with message
     Commit {
         #[arg(short = 'm', long = "message")]
@@ -92,180 +351,596 @@ enum Command {
     },
 }
 
+/// `submodule` subcommands
+#[derive(Subcommand, Debug)]
+enum SubmoduleAction {
+    /// Register the URLs of submodules in the repository config
+    Init {
+        /// Only these submodule paths (default: all)
+        paths: Vec<PathBuf>,
+    },
+    /// Check out the commits recorded in the index, cloning from local paths as needed
+    Update {
+        /// Register the submodules' URLs first, as `submodule init` does
+        #[arg(long)]
+        init: bool,
+        /// Only these submodule paths (default: all)
+        paths: Vec<PathBuf>,
+    },
+}
+
 /// Main CLI struct for gitrs
+///
+/// Command results are printed to stdout; diagnostics are logged to stderr, at the level set by
+/// -v/-q or else by `RUST_LOG` (warnings and errors by default)
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Gitrs {
+    /// Log more diagnostics (-v for progress, -vv for debugging, -vvv for tracing)
+    #[arg(
+        short = 'v',
+        long = "verbose",
+        action = ArgAction::Count,
+        conflicts_with = "quiet",
+        global = true
+    )]
+    verbose: u8,
+    /// Only log errors
+    #[arg(short = 'q', long = "quiet", global = true)]
+    quiet: bool,
+    /// Print the results of read commands as JSON
+    #[arg(long, global = true)]
+    json: bool,
     #[command(subcommand)]
     cmd: Command,
 }
 
-fn main() {
-    env_logger::Builder::new()
-        .filter_level(log::LevelFilter::Debug)
-        .init();
-
+fn main() -> ExitCode {
     let gitrs = Gitrs::parse();
 
-    match gitrs.cmd {
+    let mut logger = env_logger::Builder::new();
+    logger
+        .filter_level(LevelFilter::Warn)
+        .parse_default_env()
+        .format(|buf, record| {
+            writeln!(
+                buf,
+                "{}: {}",
+                record.level().as_str().to_lowercase(),
+                record.args()
+            )
+        });
+    match (gitrs.quiet, gitrs.verbose) {
+        (true, _) => logger.filter_level(LevelFilter::Error),
+        (_, 0) => &mut logger,
+        (_, 1) => logger.filter_level(LevelFilter::Info),
+        (_, 2) => logger.filter_level(LevelFilter::Debug),
+        _ => logger.filter_level(LevelFilter::Trace),
+    };
+    logger.init();
+
+    match run(gitrs.cmd, gitrs.json) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            // Reported like git, whatever the log level
+            let cause = GitrsError::find(&e);
+            match cause {
+                Some(GitrsError::Status(_)) => {}
+                Some(GitrsError::Usage(_)) => eprintln!("{:#}", e),
+                _ => eprintln!("fatal: {:#}", e),
+            }
+            ExitCode::from(cause.map_or(128, GitrsError::exit_code))
+        }
+    }
+}
+
+/// Runs a command, printing its results to stdout.
+///
+/// Failures are returned to be reported by `main`, as are the non-zero statuses of commands
+/// with a well-defined status of their own (like `check-ignore`), as `GitrsError::Status`.
+fn run(cmd: Command, json: bool) -> anyhow::Result<()> {
+    match cmd {
         Command::Init { path } => {
             // Initialize a new repository at the given path
-            match Repository::init(Path::new(&path)) {
-                Ok(_) => info!("Successfully initialized git repository"),
-                Err(e) => error!("Error initializing repository: {}", e),
-            }
+            let repository =
+                Repository::init(Path::new(&path)).context("Error initializing repository")?;
+            println!(
+                "Initialized empty gitrs repository in {}",
+                repository::resolve_path(&repository.gitdir).display()
+            );
         }
 
-        Command::HashObject { object_type, path } => {
-            // Read file, hash, and write as git object
-            let file = File::open(&path).expect("Could not open file");
-            let mut data = Vec::new();
-            BufReader::new(file)
-                .read_to_end(&mut data)
-                .expect("Could not read file");
-
-            let repository = Repository::find_repository();
-            let hash = GitrsObject::deserialize_and_write(&repository, &data, object_type);
-            info!("{}", hash);
+        Command::HashObject {
+            object_type,
+            write,
+            stdin,
+            stdin_paths,
+            literally,
+            paths,
+        } => {
+            // Only look for a repository when the objects need to be stored
+            let repository = write.then(Repository::find_repository).transpose()?;
+
+            let mut paths = paths;
+            if stdin_paths {
+                paths = io::stdin()
+                    .lines()
+                    .map_while(Result::ok)
+                    .filter(|line| !line.is_empty())
+                    .map(PathBuf::from)
+                    .collect();
+            } else if !stdin && paths.is_empty() {
+                bail!(GitrsError::Usage(
+                    "hash-object [-t <type>] [-w] [--stdin] [--literally] <file>...".to_string()
+                ));
+            }
+
+            // Blobs (and anything hashed --literally) are streamed rather than loaded whole;
+            // other types are read into memory to be validated first
+            let validate = !literally && object_type != ObjectType::Blob;
+            let hash = |reader: &mut dyn Read, size: u64| match &repository {
+                Some(repository) => {
+                    GitrsObject::write_stream(repository, reader, size, &object_type)
+                }
+                None => GitrsObject::hash_reader(reader, size, &object_type),
+            };
+            let hash_validated = |data: &[u8], source: &str| -> anyhow::Result<String> {
+                if !literally {
+                    GitrsObject::validate(data, object_type.clone()).context(source.to_string())?;
+                }
+                hash(&mut &data[..], data.len() as u64)
+            };
+
+            if stdin {
+                // The size has to be known up front, so stdin is buffered
+                let mut data = Vec::new();
+                io::stdin()
+                    .read_to_end(&mut data)
+                    .context("Couldn't read <stdin>")?;
+                println!("{}", hash_validated(&data, "<stdin>")?);
+            }
+
+            for path in &paths {
+                let source = path.display().to_string();
+                let mut file =
+                    fs::File::open(path).with_context(|| format!("Couldn't read {}", source))?;
+
+                let sha = if validate {
+                    let mut data = Vec::new();
+                    file.read_to_end(&mut data)
+                        .with_context(|| format!("Couldn't read {}", source))?;
+                    hash_validated(&data, &source)?
+                } else {
+                    let size = file
+                        .metadata()
+                        .with_context(|| format!("Couldn't read {}", source))?
+                        .len();
+                    hash(&mut file, size).with_context(|| format!("Couldn't hash {}", source))?
+                };
+                println!("{}", sha);
+            }
         }
 
-        Command::CatFile { object } => {
-            // TODO: accept type argument for more precise lookup
-            let repository = Repository::find_repository();
+        Command::CatFile {
+            show_type,
+            show_size,
+            exists,
+            pretty,
+            batch: batch_format,
+            batch_check,
+            batch_all_objects,
+            args,
+        } => {
+            let repository = Repository::find_repository()?;
+
+            let batch_mode = batch_format
+                .map(|format| (format, true))
+                .or_else(|| batch_check.map(|format| (format, false)));
+
+            if let Some((format, contents)) = batch_mode {
+                if !args.is_empty() {
+                    bail!(GitrsError::Usage(
+                        "--batch and --batch-check don't take object arguments".to_string()
+                    ));
+                }
+
+                let options = BatchOptions {
+                    format,
+                    contents,
+                    all_objects: batch_all_objects,
+                    json,
+                };
+                return batch::run(
+                    &repository,
+                    &options,
+                    &mut io::stdin().lock(),
+                    &mut io::BufWriter::new(io::stdout().lock()),
+                );
+            }
+
+            if batch_all_objects {
+                bail!(GitrsError::Usage(
+                    "--batch-all-objects requires --batch or --batch-check".to_string()
+                ));
+            }
 
-            let hash = GitrsObject::find(&repository, &object, None)
-                .unwrap_or_else(|_| panic!("Couldn't find object named '{}'", object));
+            let mode_given = show_type || show_size || exists || pretty;
+            let (options, name) = match &args[..] {
+                [name] if mode_given => (None, name),
+                [object_type, name] if !mode_given => (
+                    Some(ObjectFindOptions {
+                        object_type: ObjectType::from_str(object_type).map_err(|e| anyhow!(e))?,
+                        should_follow: true,
+                    }),
+                    name,
+                ),
+                _ => bail!(GitrsError::Usage(
+                    "cat-file (-t | -s | -e | -p | <type>) <object>".to_string()
+                )),
+            };
 
-            let mut obj = GitrsObject::read(&repository, &hash)
-                .unwrap_or_else(|_| panic!("Couldn't read object with hash '{}'", hash));
+            let object = GitrsObject::find(&repository, name, options).and_then(|hash| {
+                let reader = GitrsObject::open(&repository, &hash)?;
+                Ok((hash, reader))
+            });
+            let (hash, mut reader) = match object {
+                Ok(object) => object,
+                Err(e)
+                    if exists
+                        && matches!(GitrsError::find(&e), Some(GitrsError::ObjectNotFound(_))) =>
+                {
+                    bail!(GitrsError::Status(1))
+                }
+                Err(e) => return Err(e),
+            };
 
-            info!("Object contents:");
-            GitrsObject::dump(&obj.serialize());
+            // Only the header is needed for -t and -s, and content is streamed through as is
+            // unless a tree has to be parsed for -p
+            let mut stdout = io::stdout().lock();
+            let result = if exists {
+                // Corrupt objects are reported, as by git
+                GitrsObject::read(&repository, &hash)?;
+                Ok(())
+            } else if json {
+                let mut record = json!({
+                    "hash": hash,
+                    "type": reader.object_type.to_string(),
+                    "size": reader.size,
+                });
+                if show_type || show_size {
+                    // Only the header was asked for
+                } else if pretty && reader.object_type == ObjectType::Tree {
+                    let TreeObject(tree) = GitrsObject::read(&repository, &hash)? else {
+                        bail!("Expected a tree at {}", hash);
+                    };
+                    record["entries"] = tree.records.iter().map(leaf_json).collect();
+                } else {
+                    let mut content = Vec::new();
+                    reader.read_to_end(&mut content)?;
+                    record["content"] = json!(String::from_utf8_lossy(&content));
+                }
+                writeln!(stdout, "{}", record)
+            } else if show_type {
+                writeln!(stdout, "{}", reader.object_type)
+            } else if show_size {
+                writeln!(stdout, "{}", reader.size)
+            } else if pretty && reader.object_type == ObjectType::Tree {
+                let TreeObject(tree) = GitrsObject::read(&repository, &hash)? else {
+                    bail!("Expected a tree at {}", hash);
+                };
+                tree.records
+                    .iter()
+                    .try_for_each(|leaf| writeln!(stdout, "{}", leaf))
+            } else {
+                io::copy(&mut reader, &mut stdout).map(|_| ())
+            };
+
+            result.context("Couldn't write object")?;
         }
 
         Command::Log { commit } => {
-            let repository = Repository::find_repository();
+            let repository = Repository::find_repository()?;
 
-            let hash = GitrsObject::find(
-                &repository,
-                &commit,
-                Some(ObjectFindOptions {
-                    object_type: ObjectType::Commit,
-                    should_follow: false,
-                }),
-            )
-            .unwrap_or_else(|_| panic!("Couldn't find commit named '{}'", commit));
+            let mut entries = Vec::new();
+            for entry in repository.log(&commit)? {
+                let (hash, commit_obj) = entry?;
+
+                if json {
+                    entries.push(json!({
+                        "hash": hash,
+                        "tree": commit_obj.tree(),
+                        "parents": commit_obj.parents(),
+                        "author": commit_obj.author().as_ref().map(signature_json),
+                        "committer": commit_obj.committer().as_ref().map(signature_json),
+                        "message": commit_obj.message(),
+                    }));
+                } else {
+                    println!("[{}] {}", Commit::short(&hash), commit_obj.summary());
+                    if let Some(author) = commit_obj.author() {
+                        println!("Author: {} <{}>", author.name, author.email);
+                    }
+                }
+            }
+
+            if json {
+                print_json(&Value::Array(entries));
+            }
+        }
 
-            if let Ok(CommitObject(commit_obj)) = GitrsObject::read(&repository, &hash) {
-                info!("[{}] {}", Commit::short(&hash), commit_obj.message());
+        Command::LsTree {
+            recursive,
+            trees_only,
+            show_trees,
+            name_only,
+            long,
+            tree,
+            paths,
+        } => {
+            let repository = Repository::find_repository()?;
+
+            let hash = find_peeled(&repository, &tree, ObjectType::Tree)?;
+            let TreeObject(tree_obj) = GitrsObject::read(&repository, &hash)? else {
+                bail!("Not a tree object: {}", tree);
+            };
+
+            // Like git, paths are relative to the current directory, which limits the listing
+            // when no paths are given
+            let cwd = env::current_dir().context("Couldn't determine current directory")?;
+            let cwd = repository::resolve_path(&cwd);
+            let paths = if paths.is_empty() {
+                vec![Path::new(".").to_path_buf()]
             } else {
-                panic!("Expected commit object for hash {}", hash);
+                paths
+            };
+            let paths = paths
+                .iter()
+                .map(|path| tree_filter(&repository, path))
+                .collect::<anyhow::Result<_>>()?;
+
+            let options = ListOptions {
+                recursive,
+                trees_only,
+                show_trees,
+                paths,
+            };
+            let leaves = tree_obj.list(&repository, &options)?;
+            let shown = |leaf: &Leaf| {
+                repository::relative_to(&repository.worktree.join(&leaf.path), &cwd)
+                    .display()
+                    .to_string()
+            };
+
+            let blob_size = |leaf: &Leaf| match Leaf::get_type_from_mode(&leaf.file_mode) {
+                ObjectType::Blob => GitrsObject::open(&repository, &leaf.hash)
+                    .ok()
+                    .map(|reader| reader.size),
+                _ => None,
+            };
+
+            if json {
+                let entries = leaves
+                    .iter()
+                    .map(|leaf| {
+                        let mut entry = leaf_json(leaf);
+                        if long {
+                            entry["size"] = json!(blob_size(leaf));
+                        }
+                        entry
+                    })
+                    .collect();
+                print_json(&Value::Array(entries));
+                return Ok(());
             }
+
+            for leaf in leaves {
+                if name_only {
+                    println!("{}", shown(&leaf));
+                } else if long {
+                    let size = blob_size(&leaf).map_or_else(|| "-".to_string(), |s| s.to_string());
+                    println!(
+                        "{} {} {} {:>7}\t{}",
+                        leaf.file_mode,
+                        Leaf::get_type_from_mode(&leaf.file_mode),
+                        leaf.hash,
+                        size,
+                        shown(&leaf)
+                    );
+                } else {
+                    println!(
+                        "{} {} {}\t{}",
+                        leaf.file_mode,
+                        Leaf::get_type_from_mode(&leaf.file_mode),
+                        leaf.hash,
+                        shown(&leaf)
+                    );
+                }
+            }
+        }
+
+        Command::WriteTree { prefix } => {
+            let repository = Repository::find_repository()?;
+            let index = Index::read(&repository)?;
+
+            println!("{}", index.write_tree(&repository, prefix.as_deref())?);
         }
 
-        Command::LsTree { recursive: _, tree } => {
-            let repository = Repository::find_repository();
+        Command::ReadTree {
+            merge,
+            prefix,
+            trees,
+        } => {
+            let repository = Repository::find_repository()?;
+            let mut index = Index::read(&repository)?;
+
+            if !merge && trees.len() > 1 {
+                bail!(GitrsError::Usage(
+                    "Reading more than one tree requires -m".to_string()
+                ));
+            }
+
+            let hashes = trees
+                .iter()
+                .map(|tree| find_peeled(&repository, tree, ObjectType::Tree))
+                .collect::<anyhow::Result<Vec<_>>>()?;
 
-            if let Ok(TreeObject(tree_obj)) = GitrsObject::read(&repository, &tree) {
-                // TODO: fix formatting and implement recursive listing
-                for leaf in &tree_obj.records {
-                    let obj_type = Leaf::get_type_from_mode(&leaf.file_mode);
-                    info!("Found {} object", obj_type);
+            if merge {
+                for path in index.merge_trees(&repository, &hashes)? {
+                    warn!("CONFLICT: {}", path.display());
                 }
             } else {
-                panic!("Expected a tree object for {}", tree);
+                index.read_tree(&repository, &hashes[0], prefix.as_deref())?;
             }
         }
 
+        Command::CommitTree {
+            tree,
+            parents,
+            messages,
+        } => {
+            let repository = Repository::find_repository()?;
+
+            let tree = find_peeled(&repository, &tree, ObjectType::Tree)?;
+            let parents = parents
+                .iter()
+                .map(|parent| find_peeled(&repository, parent, ObjectType::Commit))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+
+            let message = if messages.is_empty() {
+                let mut message = String::new();
+                io::stdin()
+                    .read_to_string(&mut message)
+                    .context("Couldn't read commit message from stdin")?;
+                message
+            } else {
+                messages.join("\n\n")
+            };
+
+            println!("{}", Commit::create(&repository, &tree, parents, &message)?);
+        }
+
         Command::Checkout {
             commit,
             path: path_str,
+            jobs,
         } => {
             let path = Path::new(&path_str);
 
-            // TODO: create directory if it doesn't exist
-            if !repository::is_empty_dir(path) {
-                panic!("Expected an empty directory at {}", path_str);
+            if path.exists() && !repository::is_empty_dir(path) {
+                bail!("Expected an empty directory at {}", path_str);
             }
 
-            let repository = Repository::find_repository();
+            let repository = Repository::find_repository()?;
 
-            let commit_obj = match GitrsObject::read(&repository, &commit) {
-                Ok(CommitObject(obj)) => obj,
-                _ => panic!("Expected a commit object for {}", commit),
+            let hash = find_peeled(&repository, &commit, ObjectType::Commit)?;
+            let CommitObject(commit_obj) = GitrsObject::read(&repository, &hash)? else {
+                bail!("Expected a commit object for {}", commit);
             };
 
-            let tree_obj = match GitrsObject::read(&repository, commit_obj.get_tree_hash()) {
-                Ok(TreeObject(obj)) => obj,
-                _ => panic!("Couldn't find tree for commit {}", commit),
+            let tree_hash = commit_obj
+                .tree()
+                .ok_or_else(|| anyhow!("Commit {} has no tree", commit))?;
+            let TreeObject(tree_obj) = GitrsObject::read(&repository, tree_hash)? else {
+                bail!("Couldn't find tree for commit {}", commit);
             };
 
-            tree_obj
-                .checkout(&repository, path)
-                .expect("Error occurred during checkout");
+            let progress = |done: usize, total: usize| {
+                if done == total || done.is_multiple_of(100) {
+                    info!("Checking out files: {}/{}", done, total);
+                }
+            };
+            let count = tree_obj
+                .checkout(
+                    &repository,
+                    path,
+                    &CheckoutOptions {
+                        jobs,
+                        progress: Some(&progress),
+                    },
+                )
+                .context("Checkout failed")?;
+
+            info!("Checked out {} files into {}", count, path_str);
         }
 
         Command::ShowRef => {
-            let repository = Repository::find_repository();
+            let repository = Repository::find_repository()?;
 
             let refs = Ref::list_at(
                 &repository,
                 &repository
                     .get_path_to_dir_if_exists(&["refs"])
-                    .expect("Expected refs dir"),
+                    .context("Expected refs dir")?,
             )
-            .expect("Couldn't resolve refs");
+            .context("Couldn't resolve refs")?;
 
-            // TODO: implement pretty print for refs
-            for (ref_key, ref_val) in refs.iter() {
-                info!("ref: {} {}", ref_key, ref_val);
+            if json {
+                print_json(&Value::Array(
+                    refs.iter()
+                        .map(|(name, hash)| json!({ "ref": name, "hash": hash }))
+                        .collect(),
+                ));
+                return Ok(());
+            }
+            for (name, hash) in refs.iter() {
+                println!("{} {}", hash, name);
             }
         }
 
         Command::Tag {
             annotated,
+            message,
             name,
             object,
         } => {
-            let repository = Repository::find_repository();
+            let repository = Repository::find_repository()?;
 
             match name {
                 Some(tag_name) => {
-                    let tag_type = if annotated {
-                        TagType::Object
-                    } else {
-                        TagType::Lightweight
+                    let tag_type = match message {
+                        Some(message) => TagType::Object { message },
+                        None if annotated => bail!(GitrsError::Usage(
+                            "Annotated tags require a message (-m)".to_string()
+                        )),
+                        None => TagType::Lightweight,
                     };
 
-                    if let Some(obj_ref) = object {
-                        let hash = GitrsObject::find(&repository, &obj_ref, None)
-                            .unwrap_or_else(|_| panic!("Couldn't find object named '{}'", obj_ref));
+                    let Some(obj_ref) = object else {
+                        bail!(GitrsError::Usage(
+                            "Must provide object reference when creating a tag".to_string()
+                        ));
+                    };
+                    let hash = GitrsObject::find(&repository, &obj_ref, None)?;
 
-                        Tag::create(&repository, &tag_name, &hash, tag_type)
-                            .expect("Couldn't create tag");
-                    } else {
-                        panic!("Must provide object reference when creating a tag");
-                    }
+                    Tag::create(&repository, &tag_name, &hash, tag_type)
+                        .context("Couldn't create tag")?;
                 }
                 None => {
-                    // TODO: extract common pretty print method for refs and tags
-                    let refs = Ref::list_at(
-                        &repository,
-                        &repository
-                            .get_path_to_dir_if_exists(&["refs"])
-                            .expect("Expected refs dir"),
-                    )
-                    .expect("Couldn't resolve refs");
-
-                    for (ref_key, ref_val) in refs.iter() {
-                        info!("ref: {} {}", ref_key, ref_val);
+                    let tags = match repository.get_path_to_dir_if_exists(&["refs", "tags"]) {
+                        Some(path) => {
+                            Ref::list_at(&repository, &path).context("Couldn't resolve tags")?
+                        }
+                        None => Default::default(),
+                    };
+                    let tags = tags.iter().map(|(name, hash)| {
+                        (name.strip_prefix("refs/tags/").unwrap_or(name), hash)
+                    });
+
+                    if json {
+                        print_json(&Value::Array(
+                            tags.map(|(name, hash)| json!({ "name": name, "hash": hash }))
+                                .collect(),
+                        ));
+                        return Ok(());
+                    }
+                    for (name, _) in tags {
+                        println!("{}", name);
                     }
                 }
             }
         }
 
         Command::RevParse { object_type, name } => {
-            let repository = Repository::find_repository();
+            let repository = Repository::find_repository()?;
 
             let hash = GitrsObject::find(
                 &repository,
@@ -274,75 +949,801 @@ fn main() {
                     object_type,
                     should_follow: true,
                 }),
-            )
-            .unwrap_or_else(|_| panic!("Couldn't find object named '{}'", name));
+            )?;
 
-            info!("{}", hash);
+            if json {
+                print_json(&json!({ "hash": hash }));
+            } else {
+                println!("{}", hash);
+            }
         }
 
-        Command::CheckIgnore { paths } => {
-            let repository = Repository::find_repository();
-            match IgnoreRules::read(&repository) {
-                Some(rules) => paths.iter().for_each(|path| {
-                    match rules.check(&fs::canonicalize(path).expect("Couldn't canonicalize path"))
-                    {
-                        Some(kind) => info!("{} {:?}", path, kind),
-                        None => info!("No rule matching {}", path),
+        Command::CheckIgnore {
+            show_source,
+            tree,
+            stdin,
+            non_matching,
+            no_index,
+            paths,
+            ..
+        } => {
+            let repository = Repository::find_repository()?;
+            let rules = match tree {
+                Some(tree_ish) => {
+                    let hash = find_peeled(&repository, &tree_ish, ObjectType::Tree)?;
+                    IgnoreRules::read_from_tree(&repository, &hash)
+                        .context("Couldn't read ignore rules")?
+                }
+                None => IgnoreRules::read(&repository),
+            };
+
+            let tracked: HashSet<PathBuf> = if no_index {
+                HashSet::new()
+            } else {
+                Index::read(&repository)?
+                    .entries
+                    .into_iter()
+                    .map(|entry| entry.path)
+                    .collect()
+            };
+
+            let inputs: Box<dyn Iterator<Item = io::Result<String>>> = if stdin {
+                Box::new(io::stdin().lines())
+            } else {
+                Box::new(paths.into_iter().map(Ok))
+            };
+
+            let mut any_ignored = false;
+            let mut records = Vec::new();
+            for input in inputs {
+                let input = input.context("Couldn't read stdin")?;
+                let path = repository::resolve_path(Path::new(&input));
+                let is_dir = input.ends_with('/') || path.is_dir();
+
+                let rule = if tracked.contains(&path) {
+                    None
+                } else {
+                    rules.check_rule(&path, is_dir)
+                };
+
+                let is_excluded = rule
+                    .as_ref()
+                    .is_some_and(|rule| rule.kind == MatchKind::Exclude);
+                any_ignored |= is_excluded;
+
+                if json {
+                    if is_excluded || (show_source && (rule.is_some() || non_matching)) {
+                        records.push(json!({
+                            "path": input,
+                            "ignored": is_excluded,
+                            "rule": rule.map(|rule| json!({
+                                "source": repository.relative_path(&rule.source),
+                                "line": rule.line,
+                                "pattern": rule.text,
+                            })),
+                        }));
                     }
-                }),
-                None => info!("No ignore rules found in repository"),
+                    continue;
+                }
+
+                match rule {
+                    Some(rule) => {
+                        if show_source {
+                            println!(
+                                "{}:{}:{}\t{}",
+                                repository.relative_path(&rule.source).display(),
+                                rule.line,
+                                rule.text,
+                                input
+                            );
+                        } else if is_excluded {
+                            println!("{}", input);
+                        }
+                    }
+                    None if non_matching => println!("::\t{}", input),
+                    None => {}
+                }
+            }
+
+            if json {
+                print_json(&Value::Array(records));
+            }
+
+            if !any_ignored {
+                bail!(GitrsError::Status(1));
             }
         }
-        Command::LsFiles => {
-            let repository = Repository::find_repository();
-            let index = Index::read(&repository).expect("Couldn't read or initialize index file");
+        Command::LsFiles {
+            cached,
+            stage,
+            modified,
+            deleted,
+            others,
+            ignored,
+            exclude_standard,
+            unmerged,
+            nul_terminated,
+            paths,
+        } => {
+            let repository = Repository::find_repository()?;
+            let index = Index::read(&repository)?;
+
+            if ignored && !others && !cached {
+                bail!(GitrsError::Usage(
+                    "ls-files -i must be used with either -o or -c".to_string()
+                ));
+            }
+
+            let cwd = env::current_dir().context("Couldn't determine current directory")?;
+            let cwd = repository::resolve_path(&cwd);
+            let roots: Vec<PathBuf> = if paths.is_empty() {
+                vec![cwd.clone()]
+            } else {
+                paths
+                    .iter()
+                    .map(|path| repository::resolve_path(path))
+                    .collect()
+            };
+            let in_roots = |path: &Path| roots.iter().any(|root| path.starts_with(root));
+
+            // Paths to show, with the entry to describe when showing stages
+            let mut untracked = Vec::new();
+            let mut shown: Vec<(&Path, Option<&IndexEntry>)> = Vec::new();
+
+            let show_stage = stage || unmerged;
+            let show_cached = cached || show_stage || !(modified || deleted || others);
+            let rules = (exclude_standard || ignored).then(|| IgnoreRules::read(&repository));
+            let is_ignored = |path: &Path| rules.as_ref().is_some_and(|r| r.is_ignored(path));
+
+            if others {
+                let tracked: HashSet<&Path> = index
+                    .entries
+                    .iter()
+                    .map(|entry| entry.path.as_path())
+                    .collect();
+
+                // When listing ignored files the walk must include them
+                let walk_rules = rules.as_ref().filter(|_| !ignored);
+                for root in roots.iter().filter(|root| root.exists()) {
+                    let files = worktree::walk(&repository, root, walk_rules)?;
+
+                    untracked.extend(
+                        files
+                            .into_iter()
+                            .filter(|path| !tracked.contains(path.as_path()))
+                            .filter(|path| !ignored || is_ignored(path)),
+                    );
+                }
+            }
+            shown.extend(untracked.iter().map(|path| (path.as_path(), None)));
+
+            for entry in index.entries.iter().filter(|entry| in_roots(&entry.path)) {
+                if ignored && !is_ignored(&entry.path) {
+                    continue;
+                }
+
+                if show_cached && (!unmerged || entry.stage > 0) {
+                    shown.push((&entry.path, show_stage.then_some(entry)));
+                }
+
+                let is_deleted = fs::symlink_metadata(&entry.path).is_err();
+                if deleted && is_deleted {
+                    shown.push((&entry.path, None));
+                }
+                if modified && (is_deleted || !entry.matches_worktree().unwrap_or(false)) {
+                    shown.push((&entry.path, None));
+                }
+            }
 
-            info!("Dumping index file list");
+            if json {
+                print_json(&Value::Array(
+                    shown
+                        .into_iter()
+                        .map(|(path, entry)| {
+                            let mut record = json!({ "path": repository::relative_to(path, &cwd) });
+                            if let Some(entry) = entry {
+                                record["mode"] = json!(format!("{:06o}", entry.mode));
+                                record["hash"] = json!(entry.sha);
+                                record["stage"] = json!(entry.stage);
+                            }
+                            record
+                        })
+                        .collect(),
+                ));
+                return Ok(());
+            }
 
-            for entry in index.entries {
-                info!("{}", entry.path.display());
+            let terminator = if nul_terminated { '\0' } else { '\n' };
+            let mut stdout = io::BufWriter::new(io::stdout().lock());
+            for (path, entry) in shown {
+                if let Some(entry) = entry {
+                    let _ = write!(stdout, "{:06o} {} {}\t", entry.mode, entry.sha, entry.stage);
+                }
+                let _ = write!(
+                    stdout,
+                    "{}{}",
+                    repository::relative_to(path, &cwd).display(),
+                    terminator
+                );
             }
         }
-        Command::Add { paths } => {
-            let repository = Repository::find_repository();
-            let mut index =
-                Index::read(&repository).expect("Couldn't read or initialize index file");
-            index
+
+        Command::Add {
+            all,
+            update,
+            force,
+            dry_run,
+            patch,
+            jobs,
+            paths,
+        } => {
+            let repository = Repository::find_repository()?;
+            let mut index = Index::read(&repository)?;
+
+            if patch {
+                let staged = patch::add_patch(
+                    &repository,
+                    &mut index,
+                    &paths.iter().map(PathBuf::from).collect::<Vec<_>>(),
+                    &mut io::stdin().lock(),
+                    &mut io::stdout(),
+                )
+                .context("Couldn't stage hunks")?;
+
+                info!("Staged hunks in {} file(s)", staged);
+                return Ok(());
+            }
+
+            let changes = index
                 .add(
                     &repository,
-                    &paths
-                        .iter()
-                        .map(|path_str| PathBuf::from(path_str))
-                        .collect(),
+                    &paths.iter().map(PathBuf::from).collect::<Vec<_>>(),
+                    &AddOptions {
+                        all,
+                        update,
+                        force,
+                        dry_run,
+                        jobs: jobs.unwrap_or_else(|| {
+                            thread::available_parallelism().map_or(1, |n| n.get())
+                        }),
+                    },
                 )
-                .expect("Couldn't add to index");
+                .context("Couldn't add to index")?;
 
-            info!("Staged {:?}", paths);
+            // What would be staged is the result of a dry run, and only a diagnostic otherwise
+            for change in changes {
+                let line = match change {
+                    IndexChange::Add(path) => {
+                        format!("add '{}'", repository.relative_path(&path).display())
+                    }
+                    IndexChange::Remove(path) => {
+                        format!("remove '{}'", repository.relative_path(&path).display())
+                    }
+                };
+                if dry_run {
+                    println!("{}", line);
+                } else {
+                    info!("{}", line);
+                }
+            }
         }
-        Command::Rm { paths } => {
-            let repository = Repository::find_repository();
-            let mut index =
-                Index::read(&repository).expect("Couldn't read or initialize index file");
-            index
+        Command::Rm {
+            cached,
+            force,
+            recursive,
+            paths,
+        } => {
+            let repository = Repository::find_repository()?;
+            let mut index = Index::read(&repository)?;
+            let removed = index
                 .rm(
                     &repository,
-                    &paths
+                    &paths.iter().map(PathBuf::from).collect::<Vec<_>>(),
+                    &RmOptions {
+                        cached,
+                        force,
+                        recursive,
+                    },
+                )
+                .context("Couldn't remove files")?;
+
+            for path in removed {
+                println!("rm '{}'", repository.relative_path(&path).display());
+            }
+        }
+        Command::Mv { force, mut paths } => {
+            let repository = Repository::find_repository()?;
+            let mut index = Index::read(&repository)?;
+            let destination = paths
+                .pop()
+                .map(PathBuf::from)
+                .ok_or_else(|| GitrsError::Usage("mv <source>... <destination>".to_string()))?;
+            let moves = index
+                .mv(
+                    &repository,
+                    &paths.iter().map(PathBuf::from).collect::<Vec<_>>(),
+                    &destination,
+                    force,
+                )
+                .context("Couldn't move files")?;
+
+            for (source, target) in moves {
+                info!(
+                    "Renamed '{}' to '{}'",
+                    repository.relative_path(&source).display(),
+                    repository.relative_path(&target).display()
+                );
+            }
+        }
+        Command::Config {
+            global,
+            system,
+            local,
+            file,
+            list,
+            get_all,
+            unset,
+            unset_all,
+            add,
+            value_type,
+            show_origin,
+            show_scope,
+            name,
+            value,
+        } => {
+            let repository = env::current_dir()
+                .ok()
+                .and_then(|cwd| Repository::find_repository_at(&cwd));
+
+            let location = match (&file, global, system, local) {
+                (Some(path), ..) => Some((path.clone(), ConfigScope::File)),
+                (None, true, ..) => Some((
+                    Config::scope_file(ConfigScope::Global, None)
+                        .context("Couldn't locate global config file")?,
+                    ConfigScope::Global,
+                )),
+                (None, _, true, _) => Some((
+                    Config::scope_file(ConfigScope::System, None)
+                        .context("Couldn't locate system config file")?,
+                    ConfigScope::System,
+                )),
+                (None, _, _, true) => Some((
+                    Config::scope_file(ConfigScope::Local, repository.as_ref())
+                        .ok_or(GitrsError::NotARepository)?,
+                    ConfigScope::Local,
+                )),
+                _ => None,
+            };
+
+            // A bare key (`None`) is true as a boolean and empty otherwise
+            let format_value = |value: Option<&str>| -> anyhow::Result<String> {
+                let Some(value) = value else {
+                    return Ok(if value_type.as_deref() == Some("bool") {
+                        "true".to_string()
+                    } else {
+                        String::new()
+                    });
+                };
+                Ok(match value_type.as_deref() {
+                    Some("bool") => config::parse_bool(value)
+                        .ok_or_else(|| anyhow!("Bad boolean config value '{}'", value))?
+                        .to_string(),
+                    Some("int") => config::parse_int(value)
+                        .ok_or_else(|| anyhow!("Bad numeric config value '{}'", value))?
+                        .to_string(),
+                    Some("path") => config::expand_path(value).display().to_string(),
+                    _ => value.to_string(),
+                })
+            };
+            let usage = || GitrsError::Usage("config [<options>] <name> [<value>]".to_string());
+
+            let is_write = unset || unset_all || value.is_some();
+            if is_write {
+                let path = match location {
+                    Some((path, _)) => path,
+                    None => Config::scope_file(ConfigScope::Local, repository.as_ref())
+                        .ok_or(GitrsError::NotARepository)?,
+                };
+                let name = name.ok_or_else(usage)?;
+                let mut config_file =
+                    ConfigFile::read(&path).context("Couldn't read config file")?;
+
+                if unset || unset_all {
+                    let removed = config_file
+                        .unset(&name, unset_all)
+                        .with_context(|| format!("Couldn't unset '{}'", name))?;
+                    if removed == 0 {
+                        bail!(GitrsError::Status(5));
+                    }
+                } else {
+                    config_file
+                        .set(&name, value.as_deref().unwrap_or_default(), add)
+                        .with_context(|| format!("Couldn't set '{}'", name))?;
+                }
+
+                config_file.save().context("Couldn't write config file")?;
+                return Ok(());
+            }
+
+            let config = match &location {
+                Some((path, scope)) => Config::read_file(path, *scope, repository.as_ref()),
+                None => Config::read(repository.as_ref()),
+            }
+            .context("Couldn't read config")?;
+
+            if list && json {
+                print_json(&Value::Array(
+                    config
+                        .entries()
                         .iter()
-                        .map(|path_str| PathBuf::from(path_str))
+                        .map(|entry| {
+                            json!({
+                                "name": entry.name(),
+                                "value": entry.value,
+                                "scope": entry.scope.to_string(),
+                                "origin": entry.origin,
+                            })
+                        })
                         .collect(),
-                    false,
+                ));
+                return Ok(());
+            }
+            if list {
+                for entry in config.entries() {
+                    let mut prefix = String::new();
+                    if show_scope {
+                        prefix.push_str(&format!("{}\t", entry.scope));
+                    }
+                    if show_origin {
+                        prefix.push_str(&format!("file:{}\t", entry.origin.display()));
+                    }
+                    match &entry.value {
+                        Some(value) => println!("{}{}={}", prefix, entry.name(), value),
+                        None => println!("{}{}", prefix, entry.name()),
+                    }
+                }
+                return Ok(());
+            }
+
+            let name = name.ok_or_else(usage)?;
+            let values: Vec<String> = match (get_all, value_type.as_deref()) {
+                (false, Some("bool")) => config
+                    .get_bool(&name)?
+                    .map(|value| value.to_string())
+                    .into_iter()
+                    .collect(),
+                (false, Some("int")) => config
+                    .get_int(&name)?
+                    .map(|value| value.to_string())
+                    .into_iter()
+                    .collect(),
+                (false, _) => config
+                    .get(&name)
+                    .map(|value| format_value(Some(value)))
+                    .transpose()?
+                    .into_iter()
+                    .collect(),
+                (true, _) => config
+                    .get_all(&name)
+                    .into_iter()
+                    .map(format_value)
+                    .collect::<anyhow::Result<_>>()?,
+            };
+
+            if values.is_empty() {
+                bail!(GitrsError::Status(1));
+            }
+            if json {
+                print_json(&if get_all {
+                    json!({ "name": name, "values": values })
+                } else {
+                    json!({ "name": name, "value": values[0] })
+                });
+                return Ok(());
+            }
+            for value in values {
+                println!("{}", value);
+            }
+        }
+        Command::Var { variable } => {
+            let repository = Repository::find_repository()?;
+
+            let role = match variable.as_str() {
+                "GIT_AUTHOR_IDENT" => SignatureRole::Author,
+                "GIT_COMMITTER_IDENT" => SignatureRole::Committer,
+                other => bail!(GitrsError::Usage(format!(
+                    "var (GIT_AUTHOR_IDENT | GIT_COMMITTER_IDENT), not '{}'",
+                    other
+                ))),
+            };
+
+            let signature = Signature::for_role(&repository, role)?;
+            if json {
+                print_json(&signature_json(&signature));
+            } else {
+                println!("{}", signature);
+            }
+        }
+        Command::Status => {
+            let repository = Repository::find_repository()?;
+            let status = repository.status()?;
+
+            if json {
+                let changes = |changes: &[Change]| -> Vec<Value> {
+                    changes
+                        .iter()
+                        .map(|change| change_json(&repository, change))
+                        .collect()
+                };
+                let paths = |paths: &[PathBuf]| -> Vec<Value> {
+                    paths
+                        .iter()
+                        .map(|path| json!(repository.relative_path(path)))
+                        .collect()
+                };
+                print_json(&json!({
+                    "branch": status.branch,
+                    "staged": changes(&status.staged),
+                    "unmerged": paths(&status.unmerged),
+                    "unstaged": changes(&status.unstaged),
+                    "untracked": status
+                        .untracked
+                        .iter()
+                        .map(|path| json!(untracked_name(path, repository.relative_path(path))))
+                        .collect::<Vec<_>>(),
+                }));
+                return Ok(());
+            }
+
+            let cwd = env::current_dir().context("Couldn't determine current directory")?;
+            let cwd = repository::resolve_path(&cwd);
+            let relative = |path: &Path| repository::relative_to(path, &cwd).display().to_string();
+            let describe = |change: &Change| {
+                let kind = match change.kind() {
+                    ChangeKind::Added => "new file",
+                    ChangeKind::Modified => "modified",
+                    ChangeKind::Deleted => "deleted",
+                };
+                let note = match change.kind() {
+                    ChangeKind::Modified if change.is_submodule() => " (new commits)",
+                    _ => "",
+                };
+                format!(
+                    "\t{:<12}{}{}",
+                    format!("{}:", kind),
+                    relative(&change.path),
+                    note
                 )
-                .expect("Couldn't remove file from index");
+            };
+
+            match &status.branch {
+                Some(branch) => println!("On branch {}", branch),
+                None => println!("HEAD detached"),
+            }
+
+            let sections: [(&str, Vec<String>); 4] = [
+                (
+                    "Changes to be committed:",
+                    status.staged.iter().map(describe).collect(),
+                ),
+                (
+                    "Unmerged paths:",
+                    status
+                        .unmerged
+                        .iter()
+                        .map(|path| format!("\tboth modified:   {}", relative(path)))
+                        .collect(),
+                ),
+                (
+                    "Changes not staged for commit:",
+                    status.unstaged.iter().map(describe).collect(),
+                ),
+                (
+                    "Untracked files:",
+                    status
+                        .untracked
+                        .iter()
+                        .map(|path| {
+                            format!("\t{}", untracked_name(path, Path::new(&relative(path))))
+                        })
+                        .collect(),
+                ),
+            ];
+
+            let mut clean = true;
+            for (title, lines) in sections.iter().filter(|(_, lines)| !lines.is_empty()) {
+                clean = false;
+                println!("\n{}", title);
+                for line in lines {
+                    println!("{}", line);
+                }
+            }
+            if clean {
+                println!("nothing to commit, working tree clean");
+            }
+        }
+        Command::Diff { cached, paths } => {
+            let repository = Repository::find_repository()?;
+            let status = repository.status()?;
+
+            let roots: Vec<PathBuf> = paths
+                .iter()
+                .map(|path| repository::resolve_path(path))
+                .collect();
+            let changes: Vec<Change> = if cached {
+                status.staged
+            } else {
+                status.unstaged
+            }
+            .into_iter()
+            .filter(|change| roots.is_empty() || roots.iter().any(|r| change.path.starts_with(r)))
+            .collect();
+
+            if json {
+                print_json(&Value::Array(
+                    changes
+                        .iter()
+                        .map(|change| change_json(&repository, change))
+                        .collect(),
+                ));
+                return Ok(());
+            }
+            status::write_diff(&repository, &changes, !cached, &mut io::stdout())?;
+        }
+        Command::Submodule { action } => {
+            let repository = Repository::find_repository()?;
+            let submodules = Submodule::list(&repository).context("Couldn't read .gitmodules")?;
 
-            info!("Removed {:?}", paths);
+            let (init, update, paths) = match action {
+                SubmoduleAction::Init { paths } => (true, false, paths),
+                SubmoduleAction::Update { init, paths } => (init, true, paths),
+            };
+            let roots: Vec<PathBuf> = paths
+                .iter()
+                .map(|path| repository::resolve_path(path))
+                .collect();
+            let selected = submodules.iter().filter(|submodule| {
+                roots.is_empty() || roots.contains(&repository.worktree.join(&submodule.path))
+            });
+
+            let index = Index::read(&repository)?;
+            for submodule in selected {
+                if init && let Some(url) = submodule.init(&repository)? {
+                    println!(
+                        "Submodule '{}' ({}) registered for path '{}'",
+                        submodule.name,
+                        url,
+                        submodule.path.display()
+                    );
+                }
+
+                if update {
+                    let result = submodule.update(&repository, &index).with_context(|| {
+                        format!(
+                            "Failed to update submodule path '{}'",
+                            submodule.path.display()
+                        )
+                    })?;
+                    if let SubmoduleUpdate::CheckedOut(commit) = result {
+                        println!(
+                            "Submodule path '{}': checked out '{}'",
+                            submodule.path.display(),
+                            commit
+                        );
+                    }
+                }
+            }
         }
-        Command::Status => todo!(),
         Command::Commit { message } => {
-            // 1. Convert the inedx into a tree object
-            // 2. Generate and store the corresponding commit object
-            // 3. Update the HEAD branch to the new commit
-            let repository = Repository::find_repository();
-            // TODO:
+            let repository = Repository::find_repository()?;
+            let Some(commit) = repository.commit(&message)? else {
+                println!("nothing to commit");
+                bail!(GitrsError::Status(1));
+            };
+
+            println!(
+                "[{} {}] {}",
+                commit
+                    .target
+                    .strip_prefix("refs/heads/")
+                    .unwrap_or(&commit.target),
+                Commit::short(&commit.hash),
+                message.lines().next().unwrap_or_default()
+            );
         }
+    }
+
+    Ok(())
+}
+
+/// Resolves `name` to an object of the given type, peeling tags and commits.
+fn find_peeled(
+    repository: &Repository,
+    name: &str,
+    object_type: ObjectType,
+) -> anyhow::Result<String> {
+    GitrsObject::find(
+        repository,
+        name,
+        Some(ObjectFindOptions {
+            object_type,
+            should_follow: true,
+        }),
+    )
+}
+
+/// Resolves an `ls-tree` path given relative to the current directory into a filter relative
+/// to the worktree root. Paths naming a directory's contents (`dir/`, `.` or `..`) keep a
+/// trailing `/`, so the tree's entries are listed rather than the tree itself.
+fn tree_filter(repository: &Repository, path: &Path) -> anyhow::Result<PathBuf> {
+    let resolved = repository::resolve_path(path);
+    if !repository.contains(&resolved) {
+        bail!("'{}' is outside the repository", path.display());
+    }
+
+    let relative = repository.relative_path(&resolved).to_path_buf();
+    let names_contents = path.to_string_lossy().ends_with('/')
+        || matches!(
+            path.components().next_back(),
+            Some(Component::CurDir | Component::ParentDir)
+        );
+    Ok(if names_contents && !relative.as_os_str().is_empty() {
+        PathBuf::from(format!("{}/", relative.display()))
+    } else {
+        relative
+    })
+}
+
+/// Prints a JSON document on a single line, for `--json`.
+fn print_json(value: &Value) {
+    println!("{}", value);
+}
+
+/// Describes a tree entry for `--json` output.
+fn leaf_json(leaf: &Leaf) -> Value {
+    json!({
+        "mode": leaf.file_mode,
+        "type": Leaf::get_type_from_mode(&leaf.file_mode).to_string(),
+        "hash": leaf.hash,
+        "path": leaf.path,
+    })
+}
+
+/// Describes a signature for `--json` output.
+fn signature_json(signature: &Signature) -> Value {
+    json!({
+        "name": signature.name,
+        "email": signature.email,
+        "timestamp": signature.timestamp,
+        "tz_offset": signature.tz_offset,
+    })
+}
+
+/// Describes a change for `--json` output, with its path relative to the worktree root.
+fn change_json(repository: &Repository, change: &Change) -> Value {
+    let side = |side: &Option<Content>| {
+        side.as_ref()
+            .map(|(mode, hash)| json!({ "mode": format!("{:06o}", mode), "hash": hash }))
     };
+    let kind = match change.kind() {
+        ChangeKind::Added => "added",
+        ChangeKind::Modified => "modified",
+        ChangeKind::Deleted => "deleted",
+    };
+
+    json!({
+        "path": repository.relative_path(&change.path),
+        "change": kind,
+        "old": side(&change.old),
+        "new": side(&change.new),
+    })
+}
+
+/// Shows an untracked path as `shown`, with a trailing `/` if it is a directory.
+fn untracked_name(path: &Path, shown: &Path) -> String {
+    if repository::is_dir_no_follow(path) {
+        format!("{}/", shown.display())
+    } else {
+        shown.display().to_string()
+    }
 }
@@ -2,40 +2,47 @@
 pub mod blob;
 pub mod commit;
 pub mod error;
+pub mod signature;
+pub mod stream;
 pub mod tag;
 pub mod tree;
 
-use std::fs::{self, File};
-use std::io::{BufReader, Read};
-use std::str::{FromStr, from_utf8};
+use std::borrow::Cow;
+use std::fs;
+use std::io::{self, Read};
+use std::str::FromStr;
 
 use anyhow::{Result, anyhow};
-use flate2::bufread::ZlibDecoder;
-use log::info;
 use sha1::{Digest, Sha1};
 
+use crate::error::GitrsError;
 use crate::refs::Ref;
 use crate::repository::Repository;
 use blob::Blob;
 use commit::Commit;
 use error::ObjectError;
+use stream::ObjectReader;
 use tag::Tag;
 use tree::Tree;
 
 /////////////////////////////////////
-/// Object Representation
+// Object Representation
 /////////////////////////////////////
 
 /// Trait representing a gitrs object that can be serialized and deserialized.
 pub trait Object {
-    /// Serialize the object into a vector of bytes.
-    fn serialize(&mut self) -> Vec<u8>;
+    /// Serialize the object into bytes, failing if its fields can't be encoded. Objects that
+    /// already hold their content (blobs) lend it out rather than copying it.
+    fn serialize(&mut self) -> Result<Cow<'_, [u8]>, ObjectError>;
 
     /// Deserialize the object from a slice of bytes.
-    fn deserialize(data: &[u8]) -> Self;
+    fn deserialize(data: &[u8]) -> Result<Self, ObjectError>
+    where
+        Self: Sized;
 }
 
 /// Enum of all supported gitrs object types.
+#[allow(clippy::enum_variant_names)]
 pub enum GitrsObject {
     BlobObject(Blob),
     CommitObject(Commit),
@@ -90,6 +97,20 @@ impl FromStr for ObjectType {
     }
 }
 
+/// Returns whether `sha` is a full 40-character hex SHA-1 hash.
+pub fn is_valid_hash(sha: &str) -> bool {
+    sha.len() == 40 && sha.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Returns `message` ending in a newline, as commit and tag messages are stored.
+pub fn with_trailing_newline(message: &str) -> String {
+    if message.ends_with('\n') {
+        message.to_string()
+    } else {
+        format!("{}\n", message)
+    }
+}
+
 /// Options to control how object resolution behaves.
 pub struct ObjectFindOptions {
     /// The expected type of the object.
@@ -109,95 +130,148 @@ impl GitrsObject {
         }
     }
 
-    /// Serializes the git object including its type header and content.
-    pub fn serialize(&mut self) -> Vec<u8> {
-        match self {
+    /// Serializes the object's content.
+    ///
+    /// Fails with `GitrsError::CorruptObject` if the object holds data that can't be encoded,
+    /// such as a tree entry whose hash isn't valid hex.
+    pub fn serialize(&mut self) -> Result<Cow<'_, [u8]>> {
+        let object_type = self.get_type();
+        let data = match self {
             GitrsObject::BlobObject(blob) => blob.serialize(),
             GitrsObject::CommitObject(commit) => commit.serialize(),
             GitrsObject::TagObject(tag) => tag.serialize(),
             GitrsObject::TreeObject(tree) => tree.serialize(),
-        }
+        };
+
+        data.map_err(|e| {
+            GitrsError::CorruptObject {
+                sha: format!("(new {})", object_type),
+                reason: e.to_string(),
+            }
+            .into()
+        })
     }
 
     /// Deserializes data into the appropriate GitrsObject variant based on the type string.
-    pub fn deserialize(data: &[u8], object_type: ObjectType) -> Self {
-        match object_type {
-            ObjectType::Blob => Self::BlobObject(Blob::deserialize(data)),
-            ObjectType::Commit => Self::CommitObject(Commit::deserialize(data)),
-            ObjectType::Tag => Self::TagObject(Tag::deserialize(data)),
-            ObjectType::Tree => Self::TreeObject(Tree::deserialize(data)),
+    pub fn deserialize(data: &[u8], object_type: ObjectType) -> Result<Self, ObjectError> {
+        Ok(match object_type {
+            ObjectType::Blob => Self::BlobObject(Blob::deserialize(data)?),
+            ObjectType::Commit => Self::CommitObject(Commit::deserialize(data)?),
+            ObjectType::Tag => Self::TagObject(Tag::deserialize(data)?),
+            ObjectType::Tree => Self::TreeObject(Tree::deserialize(data)?),
+        })
+    }
+
+    /// Checks that `data` is a well-formed object of the given type.
+    pub fn validate(data: &[u8], object_type: ObjectType) -> Result<(), ObjectError> {
+        match Self::deserialize(data, object_type)? {
+            GitrsObject::BlobObject(_) => Ok(()),
+            GitrsObject::CommitObject(commit) => commit.validate(),
+            GitrsObject::TagObject(tag) => tag.validate(),
+            GitrsObject::TreeObject(tree) => tree.validate(),
         }
     }
 
-    /// Deserialize data and write the object to the repository, returning the SHA-1 hash.
-    pub fn deserialize_and_write(
-        repository: &Repository,
+    /// Hashes `data` as an object of the given type without parsing it, also writing it to
+    /// `repository` if one is given.
+    ///
+    /// The content is stored byte for byte, so callers should `validate` it first unless they
+    /// really mean to store a malformed object.
+    pub fn hash_raw(
         data: &[u8],
-        object_type: ObjectType,
-    ) -> String {
-        Self::deserialize(data, object_type).write(repository)
+        object_type: &ObjectType,
+        repository: Option<&Repository>,
+    ) -> Result<String> {
+        match repository {
+            Some(repository) => stream::write(repository, data, data.len() as u64, object_type),
+            None => {
+                let mut payload = format!("{} {}\x00", object_type, data.len()).into_bytes();
+                payload.extend_from_slice(data);
+                Ok(Self::hash(&mut payload))
+            }
+        }
     }
 
     /// Reads and decompresses an object by its SHA from the repository.
     ///
     /// Validates header and size, then returns the parsed object.
     pub fn read(repository: &Repository, sha: &str) -> Result<Self> {
-        let path = repository
-            .get_path_to_file_if_exists(&["objects", &sha[..2], &sha[2..]])
-            .ok_or_else(|| anyhow!("Object file does not exist"))?;
-
-        let file = File::open(path).expect("Could not open file");
-        let buf_reader = BufReader::new(file);
-        let mut decoder = ZlibDecoder::new(buf_reader);
-        let mut decompressed = Vec::new();
-        decoder.read_to_end(&mut decompressed)?;
-
-        info!("Dumping raw object");
-        Self::dump(&decompressed);
-
-        let type_end = decompressed
-            .iter()
-            .position(|&b| b == b' ')
-            .ok_or_else(|| anyhow!("Malformed object: missing space in header"))?;
-
-        let size_end = decompressed[type_end..]
-            .iter()
-            .position(|&b| b == 0)
-            .map(|i| i + type_end)
-            .ok_or_else(|| anyhow!("Malformed object: missing null byte in header"))?;
-
-        let object_type_str = from_utf8(&decompressed[..type_end])?;
-        let object_type = ObjectType::try_from(object_type_str)?;
-        let object_size: usize = from_utf8(&decompressed[type_end + 1..size_end])?.parse()?;
-
-        let content = &decompressed[size_end + 1..];
-        if object_size != content.len() {
-            return Err(anyhow!(
-                "Malformed object {}: size mismatch (expected {}, got {})",
-                sha,
-                object_size,
-                content.len()
-            ));
-        }
+        let mut reader = Self::open(repository, sha)?;
+        let object_type = reader.object_type.clone();
 
-        Ok(Self::deserialize(content, object_type))
+        let mut content = Vec::with_capacity(usize::try_from(reader.size).unwrap_or(0));
+        reader.read_to_end(&mut content)?;
+
+        Self::deserialize(&content, object_type).map_err(|e| {
+            GitrsError::CorruptObject {
+                sha: sha.to_string(),
+                reason: e.to_string(),
+            }
+            .into()
+        })
+    }
+
+    /// Opens a stored object for reading its content as a stream, without loading it.
+    pub fn open(repository: &Repository, sha: &str) -> Result<ObjectReader> {
+        ObjectReader::open(repository, sha)
     }
 
     /// Serializes and writes the object into the repository, returning its SHA-1 hash.
-    pub fn write(&mut self, repository: &Repository) -> String {
-        let data = self.serialize();
-        let header = format!("{} {}\x00", self.get_type(), data.len());
+    pub fn write(&mut self, repository: &Repository) -> Result<String> {
+        let object_type = self.get_type();
+        let data = self.serialize()?;
+        stream::write(repository, &data[..], data.len() as u64, &object_type)
+    }
+
+    /// Stores `size` bytes read from `reader` as an object of the given type, returning its
+    /// hash. The content is hashed and compressed as it is read rather than loaded whole.
+    pub fn write_stream<R: Read>(
+        repository: &Repository,
+        reader: R,
+        size: u64,
+        object_type: &ObjectType,
+    ) -> Result<String> {
+        stream::write(repository, reader, size, object_type)
+    }
+
+    /// Computes the SHA-1 hash the object would be stored under, without writing it.
+    pub fn compute_hash(&mut self) -> Result<String> {
+        Ok(Self::hash(&mut self.encode()?))
+    }
+
+    /// Serializes the object and prepends its `<type> <size>\0` header.
+    fn encode(&mut self) -> Result<Vec<u8>> {
+        let object_type = self.get_type();
+        let data = self.serialize()?;
+        let header = format!("{} {}\x00", object_type, data.len());
 
         let mut payload = header.into_bytes();
-        payload.extend(data);
+        payload.extend_from_slice(&data);
+        Ok(payload)
+    }
+
+    /// Hashes `size` bytes read from `reader` as an object of the given type, without holding
+    /// them in memory.
+    ///
+    /// Fails if the reader yields a different number of bytes.
+    pub fn hash_reader<R: Read>(reader: R, size: u64, object_type: &ObjectType) -> Result<String> {
+        let mut hasher = Sha1::new();
+        hasher.update(format!("{} {}\x00", object_type, size).as_bytes());
 
-        let sha = Self::hash(&mut payload);
+        let read = io::copy(&mut reader.take(size + 1), &mut hasher)?;
+        if read != size {
+            return Err(anyhow!("Expected {} bytes of content, read {}", size, read));
+        }
 
-        repository
-            .upsert_file(&["objects", &sha[..2], &sha[2..]], &payload)
-            .expect("Could not write object file");
+        Ok(hex::encode(hasher.finalize()))
+    }
 
-        sha
+    /// Returns true if an object with this hash is stored in the repository.
+    pub fn exists(repository: &Repository, sha: &str) -> bool {
+        is_valid_hash(sha)
+            && repository
+                .get_path_to_file_if_exists(&["objects", &sha[..2], &sha[2..]])
+                .is_some()
     }
 
     pub fn hash(data: &mut Vec<u8>) -> String {
@@ -215,7 +289,7 @@ impl GitrsObject {
         let shas = Self::resolve(repository, name)?;
 
         match shas.len() {
-            0 => Err(anyhow!("Couldn't find object with name: {}", name)),
+            0 => Err(GitrsError::ObjectNotFound(name.to_string()).into()),
             1 => {
                 let sha = &shas[0];
                 match options_opt {
@@ -223,11 +297,11 @@ impl GitrsObject {
                     None => Ok(sha.clone()),
                 }
             }
-            _ => Err(anyhow!(
-                "Ambiguous reference '{}'. Candidates: {:?}",
-                name,
-                shas
-            )),
+            _ => Err(GitrsError::AmbiguousName {
+                name: name.to_string(),
+                candidates: shas,
+            }
+            .into()),
         }
     }
 
@@ -249,10 +323,16 @@ impl GitrsObject {
 
         match object {
             GitrsObject::CommitObject(commit) if options.object_type == ObjectType::Tree => {
-                Self::find_with_options(repository, commit.get_tree_hash(), options)
+                let tree = commit
+                    .tree()
+                    .ok_or_else(|| anyhow!("Commit {} has no tree", sha))?;
+                Self::find_with_options(repository, tree, options)
             }
             GitrsObject::TagObject(tag) => {
-                Self::find_with_options(repository, tag.get_object_hash(), options)
+                let target = tag
+                    .object_hash()
+                    .ok_or_else(|| anyhow!("Tag {} has no target object", sha))?;
+                Self::find_with_options(repository, target, options)
             }
             _ => Err(anyhow!("No object matching requested type")),
         }
@@ -263,15 +343,20 @@ impl GitrsObject {
         match name {
             _ if name.trim().is_empty() => Err(anyhow!("Cannot resolve empty object name")),
 
-            "HEAD" => Ok(vec![Ref::resolve(repository, &["HEAD"])?]),
+            // HEAD doesn't resolve on an unborn branch
+            "HEAD" => Ok(Ref::resolve(repository, &["HEAD"])
+                .ok()
+                .into_iter()
+                .collect()),
 
-            _ if name.chars().all(|c| c.is_ascii_hexdigit()) => {
+            // Like git, require at least 4 hex digits for an abbreviated hash
+            _ if name.len() >= 4 && name.chars().all(|c| c.is_ascii_hexdigit()) => {
                 let dir = &name[..2].to_lowercase();
                 let prefix = &name[2..].to_lowercase();
 
-                let obj_dir = repository
-                    .get_path_to_dir_if_exists(&["objects", dir])
-                    .ok_or_else(|| anyhow!("Object directory missing: objects/{}", dir))?;
+                let Some(obj_dir) = repository.get_path_to_dir_if_exists(&["objects", dir]) else {
+                    return Ok(Vec::new());
+                };
 
                 Ok(fs::read_dir(obj_dir)?
                     .filter_map(Result::ok)
@@ -296,14 +381,45 @@ impl GitrsObject {
         }
     }
 
-    /// Prints a hex dump of the provided buffer to stdout.
-    pub fn dump(buf: &Vec<u8>) {
-        for (i, byte) in buf.iter().enumerate() {
-            if i % 16 == 0 {
-                print!("\n{:08x}: ", i);
+    /// Lists the hashes of every object in the repository, sorted.
+    ///
+    /// gitrs never writes packfiles and can't read them, so rather than leave out packed objects
+    /// (e.g. copied over from a git repository) this fails if `objects/pack` holds any packs.
+    pub fn list_all(repository: &Repository) -> Result<Vec<String>> {
+        let Some(objects_dir) = repository.get_path_to_dir_if_exists(&["objects"]) else {
+            return Ok(Vec::new());
+        };
+
+        let mut shas = Vec::new();
+        for dir in fs::read_dir(objects_dir)? {
+            let dir = dir?;
+            let prefix = dir.file_name().to_string_lossy().to_string();
+
+            if prefix == "pack" {
+                let has_packs = fs::read_dir(dir.path())?
+                    .filter_map(Result::ok)
+                    .any(|file| file.path().extension().is_some_and(|ext| ext == "pack"));
+                if has_packs {
+                    return Err(anyhow!(
+                        "Can't list objects: packfiles in {} are not supported",
+                        dir.path().display()
+                    ));
+                }
+                continue;
+            }
+            if prefix.len() != 2 || !prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+                continue;
+            }
+
+            for file in fs::read_dir(dir.path())? {
+                let name = file?.file_name().to_string_lossy().to_string();
+                if name.len() == 38 && name.chars().all(|c| c.is_ascii_hexdigit()) {
+                    shas.push(format!("{}{}", prefix, name));
+                }
             }
-            print!("{:02x} ", byte);
         }
-        println!();
+
+        shas.sort();
+        Ok(shas)
     }
 }
@@ -1,25 +1,32 @@
 // Represents a blob object type. This is used to store user files being tracked by gitrs.
 
-use crate::object::Object;
+use std::borrow::Cow;
+
+use crate::object::{Object, error::ObjectError};
 
 pub struct Blob {
     data: Vec<u8>,
 }
 
 impl Object for Blob {
-    fn serialize(&mut self) -> Vec<u8> {
-        self.data.clone()
+    fn serialize(&mut self) -> Result<Cow<'_, [u8]>, ObjectError> {
+        Ok(Cow::Borrowed(&self.data))
     }
 
-    fn deserialize(data: &[u8]) -> Self {
-        Self {
+    fn deserialize(data: &[u8]) -> Result<Self, ObjectError> {
+        Ok(Self {
             data: data.to_vec(),
-        }
+        })
     }
 }
 
 impl Blob {
-    pub fn get_data(&self) -> Vec<u8> {
-        self.data.clone()
+    pub fn get_data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Takes the content out of the blob without copying it.
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
     }
 }
@@ -1,35 +1,437 @@
-use crate::{kvlm::Kvlm, object::Object};
+use std::{borrow::Cow, str};
 
+use typed_builder::TypedBuilder;
+
+use crate::{
+    kvlm::Kvlm,
+    object::{
+        GitrsObject, Object, ObjectType,
+        error::ObjectError,
+        is_valid_hash,
+        signature::{Signature, SignatureRole},
+        with_trailing_newline,
+    },
+    repository::Repository,
+};
+
+/// Headers with a dedicated accessor; everything else is reported by `extra_headers`.
+const KNOWN_HEADERS: [&str; 7] = [
+    "tree",
+    "parent",
+    "author",
+    "committer",
+    "encoding",
+    "gpgsig",
+    "mergetag",
+];
+
+/// A commit object.
+///
+/// The headers are kept in their original order (including any this type doesn't know about),
+/// so a parsed commit serializes back to the same object. Accessors never panic on malformed
+/// data; they return `None` or an empty value instead.
 pub struct Commit {
     kvlm: Kvlm,
 }
 
+/// The parts of a new commit, see `Commit::builder`.
+#[derive(TypedBuilder)]
+#[builder(build_method(into = Commit))]
+pub struct CommitFields {
+    #[builder(setter(into))]
+    pub tree: String,
+    #[builder(default)]
+    pub parents: Vec<String>,
+    pub author: Signature,
+    pub committer: Signature,
+    #[builder(default, setter(strip_option, into))]
+    pub encoding: Option<String>,
+    /// Additional `(key, value)` headers, written after the standard ones
+    #[builder(default)]
+    pub extra_headers: Vec<(String, String)>,
+    #[builder(setter(into))]
+    pub message: String,
+}
+
+impl From<CommitFields> for Commit {
+    fn from(fields: CommitFields) -> Self {
+        let mut kvlm = Kvlm::init();
+
+        kvlm.insert("tree", &fields.tree);
+        for parent in &fields.parents {
+            kvlm.append("parent", parent);
+        }
+        kvlm.insert("author", fields.author.to_string());
+        kvlm.insert("committer", fields.committer.to_string());
+        if let Some(encoding) = &fields.encoding {
+            kvlm.insert("encoding", encoding);
+        }
+        for (key, value) in &fields.extra_headers {
+            kvlm.append(key, value);
+        }
+        kvlm.set_message(&fields.message);
+
+        Self { kvlm }
+    }
+}
+
 impl Object for Commit {
-    fn serialize(&mut self) -> Vec<u8> {
-        self.kvlm.serialize()
+    fn serialize(&mut self) -> Result<Cow<'_, [u8]>, ObjectError> {
+        Ok(Cow::Owned(self.kvlm.serialize()))
     }
 
-    fn deserialize(data: &[u8]) -> Self {
-        Self {
-            kvlm: Kvlm::new(data),
-        }
+    fn deserialize(data: &[u8]) -> Result<Self, ObjectError> {
+        Ok(Self {
+            kvlm: Kvlm::parse(data)?,
+        })
     }
 }
 
 impl Commit {
+    /// Starts building a new commit from its parts.
+    pub fn builder() -> CommitFieldsBuilder {
+        CommitFields::builder()
+    }
+
+    /// Writes a commit of `tree` on top of `parents`, returning its hash.
+    ///
+    /// The author and committer are looked up with `Signature::for_role`. No ref is updated;
+    /// that is left to the caller (see `Ref::update_head`).
+    pub fn create(
+        repository: &Repository,
+        tree: &str,
+        parents: Vec<String>,
+        message: &str,
+    ) -> anyhow::Result<String> {
+        let mut commit = GitrsObject::CommitObject(
+            Self::builder()
+                .tree(tree)
+                .parents(parents)
+                .author(Signature::for_role(repository, SignatureRole::Author)?)
+                .committer(Signature::for_role(repository, SignatureRole::Committer)?)
+                .message(with_trailing_newline(message))
+                .build(),
+        );
+
+        commit.write(repository)
+    }
+
+    /// Abbreviates a hash to its first 7 characters, or returns it whole if shorter.
     pub fn short(sha: &str) -> &str {
-        &sha[0..7]
+        sha.get(..7).unwrap_or(sha)
+    }
+
+    /// Checks that the commit has a valid tree, parents, author and committer.
+    pub fn validate(&self) -> Result<(), ObjectError> {
+        let invalid = |reason: &str| ObjectError::Invalid {
+            object_type: ObjectType::Commit,
+            reason: reason.to_string(),
+        };
+
+        if !self.tree().is_some_and(is_valid_hash) {
+            return Err(invalid("missing or malformed tree"));
+        }
+        if self.kvlm.get_all("parent").len() != self.parents().len()
+            || !self.parents().into_iter().all(is_valid_hash)
+        {
+            return Err(invalid("malformed parent"));
+        }
+        if self.author().is_none() {
+            return Err(invalid("missing or malformed author"));
+        }
+        if self.committer().is_none() {
+            return Err(invalid("missing or malformed committer"));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the hash of the commit's root tree, if present.
+    pub fn tree(&self) -> Option<&str> {
+        self.header("tree")
+    }
+
+    /// Returns the parent commit hashes in order (empty for a root commit).
+    pub fn parents(&self) -> Vec<&str> {
+        self.headers("parent")
+    }
+
+    /// Returns the parsed `author` line, if present and well-formed.
+    pub fn author(&self) -> Option<Signature> {
+        self.signature("author")
+    }
+
+    /// Returns the parsed `committer` line, if present and well-formed.
+    pub fn committer(&self) -> Option<Signature> {
+        self.signature("committer")
+    }
+
+    /// Returns the message encoding, if it isn't the default UTF-8.
+    pub fn encoding(&self) -> Option<&str> {
+        self.header("encoding")
+    }
+
+    /// Returns the ASCII-armored signature, if the commit is signed.
+    pub fn gpgsig(&self) -> Option<&str> {
+        self.header("gpgsig")
+    }
+
+    /// Returns the tag objects embedded by merging signed tags.
+    pub fn mergetags(&self) -> Vec<&str> {
+        self.headers("mergetag")
+    }
+
+    /// Returns the headers without a dedicated accessor, in order, as raw bytes.
+    pub fn extra_headers(&self) -> Vec<(&[u8], &[u8])> {
+        self.kvlm
+            .headers()
+            .filter(|(key, _)| !KNOWN_HEADERS.iter().any(|known| known.as_bytes() == *key))
+            .collect()
+    }
+
+    /// Returns the message decoded as UTF-8 (or Latin-1 if that is the commit's `encoding`),
+    /// replacing any invalid bytes.
+    pub fn message(&self) -> Cow<'_, str> {
+        let raw = self.raw_message();
+
+        match self.encoding() {
+            Some(encoding)
+                if ["iso-8859-1", "iso8859-1", "latin-1", "latin1"]
+                    .contains(&encoding.to_ascii_lowercase().as_str()) =>
+            {
+                Cow::Owned(raw.iter().map(|&byte| char::from(byte)).collect())
+            }
+            _ => String::from_utf8_lossy(raw),
+        }
     }
 
-    pub fn message(&self) -> &str {
+    /// Returns the message exactly as stored, in the commit's `encoding`.
+    pub fn raw_message(&self) -> &[u8] {
         self.kvlm.get_message()
     }
 
-    pub fn get_tree_hash(&self) -> &String {
+    /// Returns the first paragraph of the message, joined onto a single line (like `%s`).
+    pub fn summary(&self) -> String {
+        let message = self.message();
+        paragraphs(&message)
+            .0
+            .iter()
+            .map(|line| line.trim())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Returns the message after the summary paragraph (like `%b`).
+    pub fn body(&self) -> String {
+        paragraphs(&self.message()).1.to_string()
+    }
+
+    /// Returns the first value of a header, if present and valid UTF-8.
+    fn header(&self, key: &str) -> Option<&str> {
+        self.kvlm
+            .get_first(key)
+            .and_then(|value| str::from_utf8(value).ok())
+    }
+
+    /// Returns every value of a header that is valid UTF-8.
+    fn headers(&self, key: &str) -> Vec<&str> {
         self.kvlm
-            .get_key("tree")
-            .expect("Each commit must provide a tree")
-            .first()
-            .expect("Tree cannot be assigned to empty value")
+            .get_all(key)
+            .into_iter()
+            .filter_map(|value| str::from_utf8(value).ok())
+            .collect()
+    }
+
+    fn signature(&self, key: &str) -> Option<Signature> {
+        self.header(key)
+            .and_then(|value| Signature::parse(value).ok())
+    }
+}
+
+/// Splits a message into the lines of its first paragraph and the remaining text.
+fn paragraphs(message: &str) -> (Vec<&str>, &str) {
+    let message = message.trim_start_matches('\n');
+    let mut summary = Vec::new();
+    let mut rest = message;
+
+    while !rest.is_empty() {
+        let (line, tail) = rest.split_once('\n').unwrap_or((rest, ""));
+        if line.trim().is_empty() {
+            break;
+        }
+        summary.push(line);
+        rest = tail;
+    }
+
+    // Skip the blank lines separating the summary from the body
+    while let Some((line, tail)) = rest.split_once('\n')
+        && line.trim().is_empty()
+    {
+        rest = tail;
+    }
+
+    (summary, rest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{kvlm::KvlmError, testing::TempRepo};
+
+    const SIGNED: &[u8] = b"tree 29ff16c9c14e2652b22f8b78bb08a5a07930c147
+parent 206941306e8a8af65b66eaaaea388a7ae24d49a0
+author A U Thor <author@example.com> 1527025023 +0200
+committer C O Mitter <committer@example.com> 1527025044 -0130
+x-custom first
+gpgsig -----BEGIN PGP SIGNATURE-----
+ 
+ iQIzBAABCAAdFiEExwXquOM8bWb4Q2zVGxM2FxoLkGQFAlsEjZQACgkQGxM2FxoL
+ -----END PGP SIGNATURE-----
+x-custom second
+
+Create first draft
+  wrapped
+
+Body paragraph
+";
+
+    #[test]
+    fn parses_and_reserializes_a_signed_commit() {
+        let mut commit = Commit::deserialize(SIGNED).unwrap();
+        commit.validate().unwrap();
+
+        assert_eq!(
+            commit.tree(),
+            Some("29ff16c9c14e2652b22f8b78bb08a5a07930c147")
+        );
+        assert_eq!(
+            commit.parents(),
+            ["206941306e8a8af65b66eaaaea388a7ae24d49a0"]
+        );
+        let committer = commit.committer().unwrap();
+        assert_eq!(committer.name, "C O Mitter");
+        assert_eq!(committer.tz_offset, -90);
+        assert_eq!(
+            commit.gpgsig(),
+            Some(
+                "-----BEGIN PGP SIGNATURE-----\n\n\
+                 iQIzBAABCAAdFiEExwXquOM8bWb4Q2zVGxM2FxoLkGQFAlsEjZQACgkQGxM2FxoL\n\
+                 -----END PGP SIGNATURE-----"
+            )
+        );
+        assert_eq!(
+            commit.extra_headers(),
+            [
+                (&b"x-custom"[..], &b"first"[..]),
+                (&b"x-custom"[..], &b"second"[..]),
+            ]
+        );
+        assert_eq!(commit.summary(), "Create first draft wrapped");
+        assert_eq!(commit.body(), "Body paragraph\n");
+        assert_eq!(commit.serialize().unwrap(), SIGNED);
+    }
+
+    #[test]
+    fn builder_output_parses_back() {
+        let author = Signature::parse("A U Thor <author@example.com> 1527025023 +0200").unwrap();
+        let mut built = Commit::builder()
+            .tree("29ff16c9c14e2652b22f8b78bb08a5a07930c147")
+            .parents(vec![
+                "206941306e8a8af65b66eaaaea388a7ae24d49a0".to_string(),
+                "4a5e2b9d6a1c1f2ad38e0c6c5cb3bd21c2a0f1e7".to_string(),
+            ])
+            .author(author.clone())
+            .committer(author.clone())
+            .extra_headers(vec![("x-note".to_string(), "two\nlines".to_string())])
+            .message("Merge\n")
+            .build();
+        let raw = built.serialize().unwrap();
+
+        let mut parsed = Commit::deserialize(&raw).unwrap();
+        parsed.validate().unwrap();
+        assert_eq!(parsed.parents().len(), 2);
+        assert_eq!(parsed.author(), Some(author));
+        assert_eq!(
+            parsed.extra_headers(),
+            [(&b"x-note"[..], &b"two\nlines"[..])]
+        );
+        assert_eq!(parsed.raw_message(), b"Merge\n");
+        assert_eq!(parsed.serialize().unwrap(), raw);
+    }
+
+    #[test]
+    fn commit_without_a_message() {
+        let raw = b"tree 29ff16c9c14e2652b22f8b78bb08a5a07930c147\n\
+                    author A <a@b> 0 +0000\n\
+                    committer A <a@b> 0 +0000\n";
+        let mut commit = Commit::deserialize(raw).unwrap();
+        commit.validate().unwrap();
+        assert_eq!(commit.raw_message(), b"");
+        assert_eq!(commit.summary(), "");
+        assert_eq!(commit.serialize().unwrap(), &raw[..]);
+    }
+
+    #[test]
+    fn malformed_headers_are_rejected() {
+        let cases: [(&[u8], usize); 3] = [
+            (b"tree abc\nparent\n", 9),
+            (b" tree abc\n", 0),
+            (b"tree abc\nauthor A <a@b> 0 +0000", 9),
+        ];
+
+        for (raw, expected) in cases {
+            let offset = match Commit::deserialize(raw) {
+                Err(ObjectError::Malformed(
+                    KvlmError::MissingValue { offset }
+                    | KvlmError::EmptyKey { offset }
+                    | KvlmError::UnterminatedHeader { offset },
+                )) => offset,
+                _ => panic!("{:?} should be malformed", String::from_utf8_lossy(raw)),
+            };
+            assert_eq!(offset, expected);
+        }
+
+        // Well-formed headers with invalid values fail validation instead
+        let commit = Commit::deserialize(b"tree nothex\n\nmessage\n").unwrap();
+        assert!(commit.validate().is_err());
+    }
+
+    #[test]
+    fn short_never_slices_past_the_end() {
+        assert_eq!(
+            Commit::short("e69de29bb2d1d6434b8b29ae775ad8c2e48c5391"),
+            "e69de29"
+        );
+        assert_eq!(Commit::short("e69de"), "e69de");
+        assert_eq!(Commit::short(""), "");
+    }
+
+    #[test]
+    fn create_writes_a_readable_commit() {
+        let repo = TempRepo::new();
+        std::fs::write(
+            repo.gitdir.join("config"),
+            "[user]\n\tname = Test\n\temail = test@example.com\n",
+        )
+        .unwrap();
+        let tree = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+
+        let root = Commit::create(&repo, tree, vec![], "root").unwrap();
+        let child = Commit::create(&repo, tree, vec![root.clone()], "child\n\nbody\n").unwrap();
+
+        let GitrsObject::CommitObject(commit) = GitrsObject::read(&repo, &child).unwrap() else {
+            panic!("not a commit");
+        };
+        commit.validate().unwrap();
+        assert_eq!(commit.tree(), Some(tree));
+        assert_eq!(commit.parents(), [root.as_str()]);
+        assert_eq!(commit.message(), "child\n\nbody\n");
+        assert!(commit.author().is_some() && commit.committer().is_some());
+
+        let GitrsObject::CommitObject(root) = GitrsObject::read(&repo, &root).unwrap() else {
+            panic!("not a commit");
+        };
+        assert!(root.parents().is_empty());
+        assert_eq!(root.message(), "root\n");
     }
 }
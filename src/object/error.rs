@@ -1,5 +1,14 @@
+use crate::{kvlm::KvlmError, object::ObjectType};
+
 #[derive(Debug, thiserror::Error)]
 pub enum ObjectError {
     #[error("Unrecognized Object type: {0}")]
     UnrecognizedObjectType(String),
+    #[error(transparent)]
+    Malformed(#[from] KvlmError),
+    #[error("Invalid {object_type} object: {reason}")]
+    Invalid {
+        object_type: ObjectType,
+        reason: String,
+    },
 }
@@ -0,0 +1,470 @@
+// Identities (name, email and timestamp) recorded in commit and tag objects
+use std::{
+    env, fmt,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, anyhow, bail, ensure};
+
+use crate::{config::Config, repository::Repository};
+
+/// An `author`, `committer` or `tagger` line: `Name <email> <unix seconds> <+hhmm>`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Signature {
+    pub name: String,
+    pub email: String,
+    /// Seconds since the Unix epoch
+    pub timestamp: i64,
+    /// Offset from UTC in minutes
+    pub tz_offset: i32,
+}
+
+/// Which identity to look up from the environment and configuration.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SignatureRole {
+    Author,
+    /// Also used for taggers, as git does
+    Committer,
+}
+
+impl SignatureRole {
+    fn env_prefix(&self) -> &'static str {
+        match self {
+            SignatureRole::Author => "GIT_AUTHOR",
+            SignatureRole::Committer => "GIT_COMMITTER",
+        }
+    }
+
+    fn config_section(&self) -> &'static str {
+        match self {
+            SignatureRole::Author => "author",
+            SignatureRole::Committer => "committer",
+        }
+    }
+}
+
+impl Signature {
+    /// Builds the signature for a role at the current time.
+    ///
+    /// The name and email come from `GIT_<ROLE>_NAME`/`GIT_<ROLE>_EMAIL`, then `<role>.name`/
+    /// `<role>.email`, then `user.name`/`user.email`. `GIT_<ROLE>_DATE` overrides the
+    /// timestamp, which makes objects reproducible; otherwise the local timezone (honouring
+    /// `TZ`) supplies the offset.
+    pub fn for_role(repository: &Repository, role: SignatureRole) -> anyhow::Result<Self> {
+        let config = Config::read(Some(repository))?;
+        Self::from_sources(&config, role, |name| env::var(name).ok())
+    }
+
+    /// Builds the signature for a role from `config` and the environment variables `env`
+    /// looks up, as described for `for_role`.
+    fn from_sources(
+        config: &Config,
+        role: SignatureRole,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> anyhow::Result<Self> {
+        let prefix = role.env_prefix();
+        let section = role.config_section();
+
+        let lookup = |field: &str| -> Option<String> {
+            env(&format!("{}_{}", prefix, field.to_ascii_uppercase()))
+                .or_else(|| {
+                    config
+                        .get(&format!("{}.{}", section, field))
+                        .map(str::to_string)
+                })
+                .or_else(|| config.get(&format!("user.{}", field)).map(str::to_string))
+                .filter(|value| !value.trim().is_empty())
+        };
+
+        let name = lookup("name")
+            .ok_or_else(|| anyhow!("Identity unknown: set user.name (or {}_NAME)", prefix))?;
+        let email = lookup("email")
+            .ok_or_else(|| anyhow!("Identity unknown: set user.email (or {}_EMAIL)", prefix))?;
+
+        let (timestamp, tz_offset) = match env(&format!("{}_DATE", prefix)) {
+            Some(date) => {
+                parse_date(&date).with_context(|| format!("Invalid {}_DATE '{}'", prefix, date))?
+            }
+            None => {
+                let now = now();
+                (now, local_offset(now))
+            }
+        };
+
+        Ok(Self {
+            name: sanitize(&name),
+            email: sanitize(&email),
+            timestamp,
+            tz_offset,
+        })
+    }
+
+    /// Parses the value of an `author`/`committer`/`tagger` header.
+    pub fn parse(line: &str) -> anyhow::Result<Self> {
+        let open = line
+            .find('<')
+            .ok_or_else(|| anyhow!("Malformed signature, missing '<': {}", line))?;
+        let close = line[open..]
+            .find('>')
+            .map(|i| i + open)
+            .ok_or_else(|| anyhow!("Malformed signature, missing '>': {}", line))?;
+
+        let mut date = line[close + 1..].split_whitespace();
+        let timestamp = date
+            .next()
+            .ok_or_else(|| anyhow!("Malformed signature, missing timestamp: {}", line))?
+            .parse()
+            .with_context(|| format!("Malformed signature timestamp: {}", line))?;
+        let tz_offset = parse_offset(
+            date.next()
+                .ok_or_else(|| anyhow!("Malformed signature, missing timezone: {}", line))?,
+        )?;
+
+        Ok(Self {
+            name: line[..open].trim().to_string(),
+            email: line[open + 1..close].to_string(),
+            timestamp,
+            tz_offset,
+        })
+    }
+}
+
+impl fmt::Display for Signature {
+    /// Formats the signature as stored in objects, e.g. `A U Thor <a@b.c> 1527025023 +0200`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} <{}> {} {}",
+            self.name,
+            self.email,
+            self.timestamp,
+            format_offset(self.tz_offset)
+        )
+    }
+}
+
+impl FromStr for Signature {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+/// Formats a UTC offset in minutes as `+hhmm`.
+pub fn format_offset(minutes: i32) -> String {
+    let sign = if minutes < 0 { '-' } else { '+' };
+    let minutes = minutes.abs();
+    format!("{}{:02}{:02}", sign, minutes / 60, minutes % 60)
+}
+
+/// Parses a `+hhmm`, `+hh:mm`, `+hh` or `Z` UTC offset into minutes.
+fn parse_offset(offset: &str) -> anyhow::Result<i32> {
+    if offset.eq_ignore_ascii_case("z") || offset.eq_ignore_ascii_case("utc") {
+        return Ok(0);
+    }
+
+    let (sign, digits) = match offset.as_bytes().first() {
+        Some(b'+') => (1, &offset[1..]),
+        Some(b'-') => (-1, &offset[1..]),
+        _ => bail!("Invalid timezone offset: {}", offset),
+    };
+    let digits = digits.replace(':', "");
+    ensure!(
+        matches!(digits.len(), 2 | 4) && digits.bytes().all(|b| b.is_ascii_digit()),
+        "Invalid timezone offset: {}",
+        offset
+    );
+
+    let hours: i32 = digits[..2].parse()?;
+    let minutes: i32 = if digits.len() == 4 {
+        digits[2..].parse()?
+    } else {
+        0
+    };
+    Ok(sign * (hours * 60 + minutes))
+}
+
+/// Parses a date in one of the formats git accepts for `GIT_*_DATE`, returning the Unix
+/// timestamp and UTC offset in minutes:
+///
+/// - git's internal format: `<unix seconds> <+hhmm>` (or `@<unix seconds>`, or the bare
+///   `<unix seconds>`)
+/// - RFC 2822: `Thu, 07 Apr 2005 22:13:13 +0200`
+/// - ISO 8601: `2005-04-07T22:13:13+02:00` or `2005-04-07 22:13:13 +0200`
+///
+/// Dates without a timezone are taken to be UTC.
+pub fn parse_date(date: &str) -> anyhow::Result<(i64, i32)> {
+    let date = date.trim();
+
+    if let Some(seconds) = date.strip_prefix('@') {
+        let mut parts = seconds.split_whitespace();
+        let timestamp = parts.next().unwrap_or_default().parse()?;
+        let offset = parts.next().map(parse_offset).transpose()?.unwrap_or(0);
+        return Ok((timestamp, offset));
+    }
+
+    // A bare timestamp, with or without an offset
+    let parts: Vec<&str> = date.split_whitespace().collect();
+    if let [seconds, offset @ ..] = &parts[..]
+        && offset.len() <= 1
+        && seconds.bytes().all(|b| b.is_ascii_digit())
+    {
+        let offset = offset.first().map(|o| parse_offset(o)).transpose()?;
+        return Ok((seconds.parse()?, offset.unwrap_or(0)));
+    }
+
+    // `yyyy-mm-dd`, unlike RFC 2822's `dd Mon yyyy` (whose offset may contain a `-`)
+    let first_word = date.split(['T', ' ']).next().unwrap_or_default();
+    if first_word
+        .as_bytes()
+        .first()
+        .is_some_and(u8::is_ascii_digit)
+        && first_word.contains('-')
+    {
+        return parse_iso8601(date);
+    }
+
+    parse_rfc2822(date)
+}
+
+fn parse_iso8601(date: &str) -> anyhow::Result<(i64, i32)> {
+    let (day, rest) = date
+        .split_once(['T', ' '])
+        .ok_or_else(|| anyhow!("Missing time in date: {}", date))?;
+    let rest = rest.trim();
+
+    // Split the time from a trailing offset: `Z`, `+hh:mm`, `-hhmm` or a separate word
+    let offset_start = rest.find(['+', '-', 'Z', 'z', ' ']).unwrap_or(rest.len());
+    let (time, offset) = rest.split_at(offset_start);
+    let offset = match offset.trim() {
+        "" => 0,
+        offset => parse_offset(offset)?,
+    };
+
+    let mut ymd = day.split('-').map(str::parse::<i64>);
+    let (year, month, day) = match (ymd.next(), ymd.next(), ymd.next()) {
+        (Some(Ok(y)), Some(Ok(m)), Some(Ok(d))) => (y, m, d),
+        _ => bail!("Invalid date: {}", date),
+    };
+
+    let seconds = parse_time(time)?;
+    Ok((
+        to_unix(year, month, day, seconds)? - i64::from(offset) * 60,
+        offset,
+    ))
+}
+
+fn parse_rfc2822(date: &str) -> anyhow::Result<(i64, i32)> {
+    const MONTHS: [&str; 12] = [
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ];
+
+    // Drop the optional day of week ("Thu,")
+    let date = match date.split_once(',') {
+        Some((_, rest)) => rest,
+        None => date,
+    };
+    let parts: Vec<&str> = date.split_whitespace().collect();
+    let [day, month, year, time, rest @ ..] = &parts[..] else {
+        bail!("Unrecognized date format: {}", date);
+    };
+
+    let month = MONTHS
+        .iter()
+        .position(|m| month.to_ascii_lowercase().starts_with(m))
+        .ok_or_else(|| anyhow!("Invalid month in date: {}", date))? as i64
+        + 1;
+    let offset = rest
+        .first()
+        .map(|o| parse_offset(o))
+        .transpose()?
+        .unwrap_or(0);
+
+    Ok((
+        to_unix(year.parse()?, month, day.parse()?, parse_time(time)?)? - i64::from(offset) * 60,
+        offset,
+    ))
+}
+
+/// Parses `hh:mm[:ss]` into seconds since midnight.
+fn parse_time(time: &str) -> anyhow::Result<i64> {
+    let fields: Vec<i64> = time
+        .split(':')
+        .map(|field| field.split('.').next().unwrap_or(field).parse())
+        .collect::<Result<_, _>>()
+        .with_context(|| format!("Invalid time: {}", time))?;
+
+    match fields[..] {
+        [h, m] if h < 24 && m < 60 => Ok(h * 3600 + m * 60),
+        [h, m, s] if h < 24 && m < 60 && s <= 60 => Ok(h * 3600 + m * 60 + s),
+        _ => bail!("Invalid time: {}", time),
+    }
+}
+
+/// Converts a UTC civil date and time of day to a Unix timestamp.
+fn to_unix(year: i64, month: i64, day: i64, seconds: i64) -> anyhow::Result<i64> {
+    ensure!(
+        (1..=12).contains(&month) && (1..=31).contains(&day),
+        "Invalid date: {}-{}-{}",
+        year,
+        month,
+        day
+    );
+
+    // Days since the epoch, from Howard Hinnant's `days_from_civil`
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    Ok(days * 86400 + seconds)
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Returns the local timezone's UTC offset in minutes at the given time, as `localtime` sees
+/// it (so `TZ` is honoured). Falls back to UTC if the conversion fails.
+fn local_offset(timestamp: i64) -> i32 {
+    let time = timestamp as libc::time_t;
+
+    // SAFETY: `tm` is plain old data that `localtime_r` fills in; it only reads `time`.
+    unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&time, &mut tm).is_null() {
+            return 0;
+        }
+        (tm.tm_gmtoff / 60) as i32
+    }
+}
+
+/// Strips characters that would corrupt a signature line.
+fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .filter(|c| !matches!(c, '<' | '>' | '\n'))
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::{config::ConfigScope, testing::TempRepo};
+
+    #[test]
+    fn parse_date_matches_git() {
+        // Each date and what `git var GIT_AUTHOR_IDENT` reports for it as GIT_AUTHOR_DATE
+        let cases = [
+            ("1527025023", (1527025023, 0)),
+            ("1527025023 -0700", (1527025023, -420)),
+            ("@1527025023", (1527025023, 0)),
+            ("@1527025023 +0200", (1527025023, 120)),
+            ("Tue, 22 May 2018 23:37:03 +0200", (1527025023, 120)),
+            ("Tue, 22 May 2018 14:37:03 -0700", (1527025023, -420)),
+            ("22 May 2018 21:37:03 -0000", (1527025023, 0)),
+            ("2018-05-22T21:37:03Z", (1527025023, 0)),
+            ("2018-05-22T23:37:03+02:00", (1527025023, 120)),
+            ("2018-05-22T14:37:03-07:00", (1527025023, -420)),
+            ("2018-05-22 16:37:03 -0500", (1527025023, -300)),
+            ("2018-05-22 21:37:03", (1527025023, 0)),
+            ("2000-02-29T12:00:00+0530", (951805800, 330)),
+            ("1970-01-01T00:00:00Z", (0, 0)),
+        ];
+        for (date, expected) in cases {
+            assert_eq!(parse_date(date).unwrap(), expected, "{date:?}");
+        }
+
+        for date in [
+            "",
+            "yesterday",
+            "2018-05-22",
+            "2018-13-01T00:00:00Z",
+            "1527025023 +2",
+        ] {
+            assert!(parse_date(date).is_err(), "{date:?}");
+        }
+    }
+
+    #[test]
+    fn parse_offset_forms() {
+        let cases = [
+            ("+0000", Some(0)),
+            ("-0000", Some(0)),
+            ("Z", Some(0)),
+            ("UTC", Some(0)),
+            ("+0530", Some(330)),
+            ("+05:30", Some(330)),
+            ("-07", Some(-420)),
+            ("0200", None),
+            ("+5", None),
+            ("+05:3x", None),
+        ];
+        for (offset, expected) in cases {
+            assert_eq!(parse_offset(offset).ok(), expected, "{offset:?}");
+        }
+        assert_eq!(format_offset(-570), "-0930");
+    }
+
+    #[test]
+    fn to_unix_handles_leap_years_and_dates_before_the_epoch() {
+        assert_eq!(to_unix(1970, 1, 1, 0).unwrap(), 0);
+        assert_eq!(to_unix(2000, 3, 1, 0).unwrap(), 951868800);
+        assert_eq!(to_unix(2024, 2, 29, 86399).unwrap(), 1709251199);
+        assert_eq!(to_unix(1969, 12, 31, 0).unwrap(), -86400);
+        assert_eq!(to_unix(1900, 1, 1, 0).unwrap(), -2208988800);
+        assert!(to_unix(2018, 0, 1, 0).is_err());
+        assert!(to_unix(2018, 1, 32, 0).is_err());
+    }
+
+    #[test]
+    fn identity_comes_from_env_then_role_then_user_config() {
+        let repo = TempRepo::new();
+        let path = repo.write(
+            "test.cfg",
+            "[user]\n\tname = User\n\temail = user@example.com\n[committer]\n\tname = Committer\n",
+        );
+        let config = Config::read_file(&path, ConfigScope::File, None).unwrap();
+        let signature = |role, env: &[(&str, &str)]| {
+            let env: HashMap<String, String> = env
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect();
+            Signature::from_sources(&config, role, |name| env.get(name).cloned())
+        };
+
+        let dated = [("GIT_COMMITTER_DATE", "1527025023 +0200")];
+        let committer = signature(SignatureRole::Committer, &dated).unwrap();
+        assert_eq!(
+            committer.to_string(),
+            "Committer <user@example.com> 1527025023 +0200"
+        );
+
+        let author = signature(
+            SignatureRole::Author,
+            &[
+                ("GIT_AUTHOR_NAME", "Env"),
+                ("GIT_AUTHOR_DATE", "@0"),
+                ("GIT_COMMITTER_EMAIL", "ignored@example.com"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(author.to_string(), "Env <user@example.com> 0 +0000");
+
+        let empty = Config::default();
+        assert!(Signature::from_sources(&empty, SignatureRole::Author, |_| None).is_err());
+        assert!(signature(SignatureRole::Author, &[("GIT_AUTHOR_DATE", "soon")]).is_err());
+    }
+}
@@ -0,0 +1,351 @@
+// Streaming reads and writes of stored objects, for content too large to hold in memory
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, Read, Write},
+    path::Path,
+    process,
+    str::from_utf8,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use anyhow::{Context, Result, anyhow};
+use flate2::{Compression, bufread::ZlibDecoder, write::ZlibEncoder};
+use sha1::{Digest, Sha1};
+
+use crate::{
+    error::GitrsError,
+    object::{ObjectType, is_valid_hash},
+    repository::Repository,
+};
+
+/// Longest header field accepted when parsing `<type> <size>\0`.
+const MAX_HEADER_FIELD: usize = 32;
+
+/// Size of the chunks content is copied in.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Distinguishes the temporary files of writes running at the same time in this process.
+static NEXT_TEMP_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// A stored object opened for reading.
+///
+/// The `<type> <size>` header is parsed when the object is opened; the content is decompressed
+/// as it is read, and reading fails if it turns out shorter or longer than the header says.
+pub struct ObjectReader {
+    /// Type of the object, from its header
+    pub object_type: ObjectType,
+    /// Size of the content in bytes, from its header
+    pub size: u64,
+    sha: String,
+    decoder: ZlibDecoder<BufReader<File>>,
+    remaining: u64,
+}
+
+impl ObjectReader {
+    /// Opens the object with the given hash and parses its header.
+    pub fn open(repository: &Repository, sha: &str) -> Result<Self> {
+        let path = is_valid_hash(sha)
+            .then(|| repository.get_path_to_file_if_exists(&["objects", &sha[..2], &sha[2..]]))
+            .flatten()
+            .ok_or_else(|| GitrsError::ObjectNotFound(sha.to_string()))?;
+        let file = File::open(&path)
+            .with_context(|| format!("Could not open object file {}", path.display()))?;
+        let mut decoder = ZlibDecoder::new(BufReader::new(file));
+
+        let corrupt = |reason: &str| GitrsError::CorruptObject {
+            sha: sha.to_string(),
+            reason: reason.to_string(),
+        };
+        let object_type = read_header_field(&mut decoder, b' ')
+            .ok_or_else(|| corrupt("missing space in header"))?;
+        let size = read_header_field(&mut decoder, 0)
+            .ok_or_else(|| corrupt("missing null byte in header"))?;
+
+        let object_type = from_utf8(&object_type)
+            .ok()
+            .and_then(|name| ObjectType::try_from(name).ok())
+            .ok_or_else(|| corrupt("unknown object type in header"))?;
+        let size: u64 = from_utf8(&size)
+            .ok()
+            .and_then(|size| size.parse().ok())
+            .ok_or_else(|| corrupt("invalid size in header"))?;
+
+        Ok(Self {
+            object_type,
+            size,
+            sha: sha.to_string(),
+            decoder,
+            remaining: size,
+        })
+    }
+
+    /// Reports the object as corrupt from inside `read`.
+    fn corrupt(&self, reason: String) -> io::Error {
+        GitrsError::CorruptObject {
+            sha: self.sha.clone(),
+            reason,
+        }
+        .into_io()
+    }
+
+    /// Reports content that turned out `found` (less or more) than the header says.
+    fn size_mismatch(&self, found: &str) -> io::Error {
+        self.corrupt(format!(
+            "size mismatch (expected {} bytes, found {})",
+            self.size, found
+        ))
+    }
+}
+
+impl Read for ObjectReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 {
+            // Make sure nothing follows the content the header announced
+            let trailing = self.decoder.read(&mut [0; 1]);
+            return match trailing.map_err(|e| self.corrupt(e.to_string()))? {
+                0 => Ok(0),
+                _ => Err(self.size_mismatch("more")),
+            };
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let limit = buf
+            .len()
+            .min(usize::try_from(self.remaining).unwrap_or(usize::MAX));
+        let read = self
+            .decoder
+            .read(&mut buf[..limit])
+            .map_err(|e| self.corrupt(e.to_string()))?;
+        if read == 0 {
+            return Err(self.size_mismatch("less"));
+        }
+
+        self.remaining -= read as u64;
+        Ok(read)
+    }
+}
+
+/// Reads header bytes up to `delimiter`, which is consumed. Returns `None` if the stream ends
+/// or the field is implausibly long first.
+fn read_header_field<R: Read>(reader: &mut R, delimiter: u8) -> Option<Vec<u8>> {
+    let mut field = Vec::new();
+    let mut byte = [0; 1];
+
+    while field.len() <= MAX_HEADER_FIELD {
+        if reader.read_exact(&mut byte).is_err() {
+            return None;
+        }
+        if byte[0] == delimiter {
+            return Some(field);
+        }
+        field.push(byte[0]);
+    }
+
+    None
+}
+
+/// Stores `size` bytes read from `reader` as an object of the given type, returning its hash.
+///
+/// The content is hashed and compressed as it is read into a temporary file in the object
+/// store, which is then renamed into place (or dropped if the object is already stored).
+/// Fails if the reader yields a different number of bytes.
+pub fn write<R: Read>(
+    repository: &Repository,
+    reader: R,
+    size: u64,
+    object_type: &ObjectType,
+) -> Result<String> {
+    let objects = repository.gitdir.join("objects");
+    fs::create_dir_all(&objects)
+        .with_context(|| format!("Could not create {}", objects.display()))?;
+
+    let temp = objects.join(format!(
+        "tmp_obj_{}_{}",
+        process::id(),
+        NEXT_TEMP_ID.fetch_add(1, Ordering::Relaxed)
+    ));
+    let result = write_compressed(&temp, reader, size, object_type).and_then(|sha| {
+        let target = objects.join(&sha[..2]).join(&sha[2..]);
+        if !target.exists() {
+            fs::create_dir_all(objects.join(&sha[..2]))?;
+            fs::rename(&temp, &target)
+                .with_context(|| format!("Could not write object file {}", target.display()))?;
+        }
+        Ok(sha)
+    });
+
+    // Left behind if the object was already stored or the write failed
+    let _ = fs::remove_file(&temp);
+    result
+}
+
+/// Writes the compressed object to `path`, returning its hash.
+fn write_compressed<R: Read>(
+    path: &Path,
+    reader: R,
+    size: u64,
+    object_type: &ObjectType,
+) -> Result<String> {
+    let file =
+        File::create(path).with_context(|| format!("Could not create {}", path.display()))?;
+    let mut encoder = ZlibEncoder::new(file, Compression::default());
+    let mut hasher = Sha1::new();
+
+    let header = format!("{} {}\x00", object_type, size);
+    hasher.update(header.as_bytes());
+    encoder.write_all(header.as_bytes())?;
+
+    let mut reader = reader.take(size + 1);
+    let mut chunk = vec![0; CHUNK_SIZE];
+    let mut written = 0;
+    loop {
+        let read = match reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        hasher.update(&chunk[..read]);
+        encoder.write_all(&chunk[..read])?;
+        written += read as u64;
+    }
+    if written != size {
+        return Err(anyhow!(
+            "Expected {} bytes of content, read {}",
+            size,
+            written
+        ));
+    }
+
+    encoder.finish()?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{object::GitrsObject, testing::TempRepo};
+
+    const SHA: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    /// Stores `raw` (header included) compressed under `SHA`, without checking it.
+    fn store(repo: &TempRepo, raw: &[u8]) {
+        let dir = repo.gitdir.join("objects").join(&SHA[..2]);
+        fs::create_dir_all(&dir).unwrap();
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(raw).unwrap();
+        fs::write(dir.join(&SHA[2..]), encoder.finish().unwrap()).unwrap();
+    }
+
+    fn read_all(repo: &TempRepo) -> Result<Vec<u8>> {
+        let mut content = Vec::new();
+        ObjectReader::open(repo, SHA)?.read_to_end(&mut content)?;
+        Ok(content)
+    }
+
+    #[test]
+    fn written_objects_read_back() {
+        let repo = TempRepo::new();
+        // Spans several chunks
+        let content: Vec<u8> = (0..3 * CHUNK_SIZE + 17).map(|i| (i % 251) as u8).collect();
+
+        let sha = write(&repo, &content[..], content.len() as u64, &ObjectType::Blob).unwrap();
+        assert_eq!(
+            sha,
+            GitrsObject::hash_raw(&content, &ObjectType::Blob, None).unwrap()
+        );
+        // Writing it again is a no-op
+        assert_eq!(
+            write(&repo, &content[..], content.len() as u64, &ObjectType::Blob).unwrap(),
+            sha
+        );
+
+        let mut reader = ObjectReader::open(&repo, &sha).unwrap();
+        assert_eq!(reader.object_type, ObjectType::Blob);
+        assert_eq!(reader.size, content.len() as u64);
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(read, content);
+
+        let empty = write(&repo, io::empty(), 0, &ObjectType::Blob).unwrap();
+        assert_eq!(empty, "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391");
+    }
+
+    #[test]
+    fn writes_with_the_wrong_size_are_refused() {
+        let repo = TempRepo::new();
+        assert!(write(&repo, &b"abc"[..], 4, &ObjectType::Blob).is_err());
+        assert!(write(&repo, &b"abcde"[..], 4, &ObjectType::Blob).is_err());
+
+        // Nothing is left in the object store
+        let objects: Vec<_> = fs::read_dir(repo.gitdir.join("objects"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .filter(|name| name != "info" && name != "pack")
+            .collect();
+        assert!(objects.is_empty(), "{objects:?}");
+    }
+
+    #[test]
+    fn truncated_content_is_corrupt() {
+        let repo = TempRepo::new();
+        store(&repo, b"blob 10\0abc");
+        let err = read_all(&repo).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("size mismatch (expected 10 bytes, found less)"),
+            "{err}"
+        );
+
+        // A compressed stream cut short
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"blob 3\0abc").unwrap();
+        let compressed = encoder.finish().unwrap();
+        let path = repo.gitdir.join("objects").join(&SHA[..2]).join(&SHA[2..]);
+        fs::write(&path, &compressed[..compressed.len() - 6]).unwrap();
+        assert!(read_all(&repo).is_err());
+    }
+
+    #[test]
+    fn content_longer_than_the_header_is_corrupt() {
+        let repo = TempRepo::new();
+        store(&repo, b"blob 2\0abcdef");
+        let err = read_all(&repo).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("size mismatch (expected 2 bytes, found more)"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn malformed_headers_are_corrupt() {
+        let repo = TempRepo::new();
+        for raw in [
+            &b"blob"[..],
+            b"blob 3",
+            b"blob x\0abc",
+            b"bolb 3\0abc",
+            b"blob 123456789012345678901234567890123456\0",
+        ] {
+            store(&repo, raw);
+            let err = ObjectReader::open(&repo, SHA).err().unwrap();
+            assert!(
+                matches!(
+                    err.downcast_ref::<GitrsError>(),
+                    Some(GitrsError::CorruptObject { .. })
+                ),
+                "{err}"
+            );
+        }
+        assert!(matches!(
+            ObjectReader::open(&repo, "ffffffffffffffffffffffffffffffffffffffff")
+                .err()
+                .unwrap()
+                .downcast_ref::<GitrsError>(),
+            Some(GitrsError::ObjectNotFound(_))
+        ));
+    }
+}
@@ -1,4 +1,19 @@
-use crate::{kvlm::Kvlm, object::Object, refs::Ref, repository::Repository};
+use std::borrow::Cow;
+
+use anyhow::Context;
+
+use crate::{
+    kvlm::Kvlm,
+    object::{
+        GitrsObject, Object, ObjectType,
+        error::ObjectError,
+        is_valid_hash,
+        signature::{Signature, SignatureRole},
+        with_trailing_newline,
+    },
+    refs::Ref,
+    repository::Repository,
+};
 
 pub struct Tag {
     kvlm: Kvlm,
@@ -6,19 +21,22 @@ pub struct Tag {
 
 pub enum TagType {
     Lightweight,
-    Object,
+    /// An annotated tag object carrying a tagger and message
+    Object {
+        message: String,
+    },
 }
 
 // Tag objects are essentially identical to commit objects
 impl Object for Tag {
-    fn serialize(&mut self) -> Vec<u8> {
-        self.kvlm.serialize()
+    fn serialize(&mut self) -> Result<Cow<'_, [u8]>, ObjectError> {
+        Ok(Cow::Owned(self.kvlm.serialize()))
     }
 
-    fn deserialize(data: &[u8]) -> Self {
-        Self {
-            kvlm: Kvlm::new(data),
-        }
+    fn deserialize(data: &[u8]) -> Result<Self, ObjectError> {
+        Ok(Self {
+            kvlm: Kvlm::parse(data)?,
+        })
     }
 }
 
@@ -36,23 +54,57 @@ impl Tag {
     ) -> anyhow::Result<()> {
         match tag_type {
             TagType::Lightweight => Ref::create_at(repository, hash, &["refs", "tags", name]),
-            TagType::Object => {
-                let mut kvlm = Kvlm::init();
+            TagType::Object { message } => {
+                let target = GitrsObject::read(repository, hash)
+                    .with_context(|| format!("Couldn't read tagged object {}", hash))?;
+                let tagger = Signature::for_role(repository, SignatureRole::Committer)?;
 
+                let mut kvlm = Kvlm::init();
                 kvlm.insert("object", hash);
+                kvlm.insert("type", target.get_type().to_string());
                 kvlm.insert("tag", name);
+                kvlm.insert("tagger", tagger.to_string());
+                kvlm.set_message(with_trailing_newline(&message));
 
-                // TODO: complete
-                Ok(())
+                let sha = GitrsObject::TagObject(Self::new(kvlm)).write(repository)?;
+                Ref::create_at(repository, &sha, &["refs", "tags", name])
             }
         }
     }
 
-    pub fn get_object_hash(&self) -> &String {
+    /// Checks that the tag names a valid object, type and tag name, and that its tagger (if
+    /// any) is well-formed.
+    pub fn validate(&self) -> Result<(), ObjectError> {
+        let invalid = |reason: &str| ObjectError::Invalid {
+            object_type: ObjectType::Tag,
+            reason: reason.to_string(),
+        };
+        let header = |key: &str| {
+            self.kvlm
+                .get_first(key)
+                .and_then(|value| std::str::from_utf8(value).ok())
+        };
+
+        if !self.object_hash().is_some_and(is_valid_hash) {
+            return Err(invalid("missing or malformed object"));
+        }
+        if header("type").is_none_or(|t| ObjectType::try_from(t).is_err()) {
+            return Err(invalid("missing or unknown type"));
+        }
+        if header("tag").is_none_or(str::is_empty) {
+            return Err(invalid("missing tag name"));
+        }
+        if header("tagger").is_some_and(|tagger| Signature::parse(tagger).is_err()) {
+            return Err(invalid("malformed tagger"));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the hash of the tagged object, if present.
+    pub fn object_hash(&self) -> Option<&str> {
         self.kvlm
-            .get_key("object")
-            .expect("Each tag object must provide a target object hash")
-            .first()
-            .expect("Object cannot be assigned to empty value")
+            .get_first("object")
+            .and_then(|value| std::str::from_utf8(value).ok())
     }
 }
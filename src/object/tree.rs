@@ -1,20 +1,48 @@
-use anyhow::anyhow;
-use log::info;
+use anyhow::{Context, anyhow, bail};
+use log::debug;
 
-use crate::{object::Object, repository::Repository};
+use crate::{config::Config, object::Object, repository::Repository, worktree};
 use std::{
-    fs,
-    io::{Cursor, Read, Write},
+    borrow::Cow,
+    fmt, fs,
+    io::{Cursor, Read},
     path::{Path, PathBuf},
-    str::FromStr,
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
 };
 
-use super::{GitrsObject, ObjectType};
+use super::{GitrsObject, ObjectType, error::ObjectError};
+
+/// Modes git accepts in tree entries, as normalized to six digits.
+const VALID_MODES: [&str; 5] = ["100644", "100755", "120000", "040000", "160000"];
 
 pub struct Tree {
     pub records: Vec<Leaf>,
 }
 
+/// Options to control how `Tree::checkout` writes files.
+#[derive(Default)]
+pub struct CheckoutOptions<'a> {
+    /// Number of threads reading and writing blobs; files are written in order when 0 or 1
+    pub jobs: usize,
+    /// Called with the number of files written so far and the total after each file
+    pub progress: Option<&'a (dyn Fn(usize, usize) + Sync)>,
+}
+
+/// Options to control which entries `Tree::list` returns (see `ls-tree`).
+pub struct ListOptions {
+    /// Recurse into subtrees
+    pub recursive: bool,
+    /// Only show trees
+    pub trees_only: bool,
+    /// Show trees even when recursing into them
+    pub show_trees: bool,
+    /// Only show entries at or under these paths (relative to the tree root)
+    pub paths: Vec<PathBuf>,
+}
+
+#[derive(Clone)]
 pub struct Leaf {
     pub file_mode: String,
     pub path: PathBuf, // relative to worktree
@@ -22,10 +50,10 @@ pub struct Leaf {
 }
 
 impl Object for Tree {
-    fn serialize(&mut self) -> Vec<u8> {
+    fn serialize(&mut self) -> Result<Cow<'_, [u8]>, ObjectError> {
         // Sort leaf nodes
         self.records.sort_by_key(|leaf| {
-            let is_dir = !leaf.file_mode.starts_with("10");
+            let is_dir = Leaf::get_type_from_mode(&leaf.file_mode) == ObjectType::Tree;
             let mut file_path_str = leaf.path.to_string_lossy().to_string();
             if is_dir {
                 file_path_str.push('/');
@@ -35,92 +63,376 @@ impl Object for Tree {
         });
 
         let mut output = Vec::new();
-        self.records.iter().for_each(|leaf| {
+        for leaf in &self.records {
             output.extend_from_slice(
+                // Modes are stored without the leading zero we pad them to (e.g. `40000`)
                 format!(
-                    "{}\x20{}\x00{}",
-                    leaf.file_mode,
-                    leaf.path.to_string_lossy().to_string(),
-                    leaf.hash
+                    "{}\x20{}\x00",
+                    leaf.file_mode.trim_start_matches('0'),
+                    leaf.path.to_string_lossy()
                 )
                 .as_bytes(),
             );
-        });
+            // Hashes are stored as 20 raw bytes
+            let raw_hash = hex::decode(&leaf.hash)
+                .ok()
+                .filter(|raw| raw.len() == 20)
+                .ok_or_else(|| {
+                    invalid(format!(
+                        "bad hash '{}' for '{}'",
+                        leaf.hash,
+                        leaf.path.display()
+                    ))
+                })?;
+            output.extend_from_slice(&raw_hash);
+        }
 
-        output
+        Ok(Cow::Owned(output))
     }
 
-    fn deserialize(data: &[u8]) -> Self {
+    fn deserialize(data: &[u8]) -> Result<Self, ObjectError> {
         let mut cursor = Cursor::new(data);
         let len = cursor.get_ref().len();
 
         let mut records = Vec::new();
         while (cursor.position() as usize) < len {
-            let leaf = Leaf::parse(&mut cursor, data);
+            let leaf = Leaf::parse(&mut cursor, data)?;
             records.push(leaf);
         }
 
-        Self { records }
+        Ok(Self { records })
     }
 }
 
 impl Tree {
-    // TODO: clean up partially created tree in case of failure
-    pub fn checkout(&self, repository: &Repository, path: &Path) -> anyhow::Result<()> {
+    /// Checks that every entry has a known mode and a plain, non-empty name.
+    pub fn validate(&self) -> Result<(), ObjectError> {
+        for leaf in &self.records {
+            let name = leaf.path.to_string_lossy();
+
+            if !VALID_MODES.contains(&leaf.file_mode.as_str()) {
+                return Err(invalid(format!(
+                    "bad mode {} for '{}'",
+                    leaf.file_mode, name
+                )));
+            }
+            if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+                return Err(invalid(format!("bad entry name '{}'", name)));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks that every entry, in this tree and its subtrees, is safe to create in a worktree
+    /// (like git's `verify_path`): besides what `validate` rejects, no entry may be named
+    /// `.gitrs` in any case, as checking it out would write into a repository's gitdir.
+    pub fn verify_paths(&self, repository: &Repository) -> anyhow::Result<()> {
+        self.validate()?;
+
         for record in &self.records {
-            let obj = GitrsObject::read(repository, &record.hash)?;
-            let dest = path.join(record.path.as_path());
-            info!("Final path is: {}", dest.display());
-
-            match obj {
-                GitrsObject::TreeObject(tree_obj) => {
-                    fs::create_dir(&dest)?;
-                    tree_obj.checkout(repository, &dest)?
-                }
-                GitrsObject::BlobObject(mut blob_obj) => {
-                    // TODO: support symlinks
-                    let mut file = fs::File::create(&dest)?;
-                    file.write_all(&blob_obj.serialize())?
+            if record.path.to_string_lossy().eq_ignore_ascii_case(".gitrs") {
+                bail!("Invalid path '{}' in tree", record.path.display());
+            }
+            if Leaf::get_type_from_mode(&record.file_mode) != ObjectType::Tree {
+                continue;
+            }
+            match GitrsObject::read(repository, &record.hash)? {
+                GitrsObject::TreeObject(tree) => tree
+                    .verify_paths(repository)
+                    .with_context(|| format!("In '{}'", record.path.display()))?,
+                other => bail!(
+                    "Expected tree at {}, found {}",
+                    record.path.display(),
+                    other.get_type()
+                ),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Lists the entries of this tree in tree order, as `ls-tree` would show them.
+    ///
+    /// The returned leaves have their `path` set to the path from this tree's root.
+    pub fn list(
+        &self,
+        repository: &Repository,
+        options: &ListOptions,
+    ) -> anyhow::Result<Vec<Leaf>> {
+        let mut leaves = Vec::new();
+        self.list_into(repository, options, Path::new(""), &mut leaves)?;
+        Ok(leaves)
+    }
+
+    fn list_into(
+        &self,
+        repository: &Repository,
+        options: &ListOptions,
+        prefix: &Path,
+        leaves: &mut Vec<Leaf>,
+    ) -> anyhow::Result<()> {
+        for record in &self.records {
+            let path = prefix.join(&record.path);
+            let is_tree = Leaf::get_type_from_mode(&record.file_mode) == ObjectType::Tree;
+
+            // Entries at or below a filter are listed, and trees leading to one are entered
+            // (a filter ending in `/` names the tree's contents rather than the tree itself)
+            let matched = options.paths.is_empty()
+                || options.paths.iter().any(|filter| path.starts_with(filter));
+            let leads_to_filter = options.paths.iter().any(|filter| {
+                filter.starts_with(&path)
+                    && (filter != &path || filter.to_string_lossy().ends_with('/'))
+            });
+            if !matched && !leads_to_filter {
+                continue;
+            }
+
+            let descend = is_tree && (options.recursive || leads_to_filter);
+            let show = if is_tree {
+                !descend || options.show_trees || (options.trees_only && matched)
+            } else {
+                !options.trees_only
+            };
+
+            if show {
+                leaves.push(Leaf {
+                    path: path.clone(),
+                    ..record.clone()
+                });
+            }
+
+            if descend {
+                match GitrsObject::read(repository, &record.hash)? {
+                    GitrsObject::TreeObject(tree) => {
+                        tree.list_into(repository, options, &path, leaves)?
+                    }
+                    other => {
+                        return Err(anyhow!(
+                            "Expected tree at {}, found {}",
+                            path.display(),
+                            other.get_type()
+                        ));
+                    }
                 }
-                _ => {
-                    return Err(anyhow!(
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks out the tree into `path`, returning the number of files written.
+    ///
+    /// Files are first written to a temporary directory next to `path` and then moved into
+    /// place, so a failed checkout leaves nothing behind. `path` is created if it doesn't exist;
+    /// if it does, it must be a directory without any of the tree's top-level entries. Gitlinks
+    /// are checked out as empty directories, as the commits they point to live in the
+    /// submodule's repository (see `submodule update`). Trees with entries that aren't safe to
+    /// create (see `verify_paths`) are refused before anything is written.
+    pub fn checkout(
+        &self,
+        repository: &Repository,
+        path: &Path,
+        options: &CheckoutOptions,
+    ) -> anyhow::Result<usize> {
+        let symlinks = Config::read(Some(repository))?
+            .get_bool("core.symlinks")?
+            .unwrap_or(true);
+        self.verify_paths(repository)
+            .context("Refusing to check out tree")?;
+
+        // List everything up front, so progress can be reported against a total
+        let (dirs, files): (Vec<Leaf>, Vec<Leaf>) = self
+            .list(
+                repository,
+                &ListOptions {
+                    recursive: true,
+                    trees_only: false,
+                    show_trees: true,
+                    paths: Vec::new(),
+                },
+            )?
+            .into_iter()
+            .partition(|leaf| Leaf::get_type_from_mode(&leaf.file_mode) != ObjectType::Blob);
+
+        let name = path
+            .file_name()
+            .ok_or_else(|| anyhow!("Invalid checkout path: {}", path.display()))?;
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+
+        if fs::symlink_metadata(path).is_ok() {
+            if !path.is_dir() {
+                bail!("Checkout path {} is not a directory", path.display());
+            }
+            if let Some(record) = self
+                .records
+                .iter()
+                .find(|record| fs::symlink_metadata(path.join(&record.path)).is_ok())
+            {
+                bail!("{} already exists", path.join(&record.path).display());
+            }
+        }
+
+        let staging = parent.join(format!(
+            ".{}.checkout-{}",
+            name.to_string_lossy(),
+            process::id()
+        ));
+        // Left behind by an earlier process that had the same pid and was killed mid-checkout
+        if fs::symlink_metadata(&staging).is_ok() {
+            fs::remove_dir_all(&staging)
+                .with_context(|| format!("Failed to remove stale {}", staging.display()))?;
+        }
+        fs::create_dir(&staging)
+            .with_context(|| format!("Failed to create {}", staging.display()))?;
+
+        let result = write_checkout(repository, &staging, &dirs, &files, symlinks, options)
+            .and_then(|()| move_into_place(&staging, path, &self.records));
+        if result.is_err() {
+            let _ = fs::remove_dir_all(&staging);
+        }
+
+        result.map(|()| files.len())
+    }
+}
+
+/// Creates the directories and writes the files of a checkout under `root`.
+fn write_checkout(
+    repository: &Repository,
+    root: &Path,
+    dirs: &[Leaf],
+    files: &[Leaf],
+    symlinks: bool,
+    options: &CheckoutOptions,
+) -> anyhow::Result<()> {
+    for dir in dirs {
+        fs::create_dir(root.join(&dir.path))
+            .with_context(|| format!("Failed to create {}", dir.path.display()))?;
+    }
+
+    let next = AtomicUsize::new(0);
+    let written = AtomicUsize::new(0);
+
+    // Workers claim files by index until they run out (or one of them fails)
+    let write_files = || -> anyhow::Result<()> {
+        while let Some(leaf) = files.get(next.fetch_add(1, Ordering::Relaxed)) {
+            let dest = root.join(&leaf.path);
+            debug!("Writing {}", dest.display());
+
+            let result = GitrsObject::open(repository, &leaf.hash).and_then(|reader| {
+                if reader.object_type != ObjectType::Blob {
+                    bail!(
                         "Unexpected object in tree: <type: {} hash: {}>",
-                        obj.get_type(),
-                        record.hash
-                    ));
+                        reader.object_type,
+                        leaf.hash
+                    );
                 }
+                let mode = u32::from_str_radix(&leaf.file_mode, 8)?;
+                worktree::write_file(&dest, mode, reader, symlinks)
+            });
+            if result.is_err() {
+                next.store(files.len(), Ordering::Relaxed);
+                return result;
+            }
+
+            let done = written.fetch_add(1, Ordering::Relaxed) + 1;
+            if let Some(progress) = options.progress {
+                progress(done, files.len());
             }
         }
 
         Ok(())
+    };
+
+    if options.jobs <= 1 {
+        return write_files();
+    }
+
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..options.jobs.min(files.len()))
+            .map(|_| scope.spawn(write_files))
+            .collect();
+
+        workers.into_iter().try_for_each(|worker| {
+            worker
+                .join()
+                .unwrap_or_else(|_| Err(anyhow!("Checkout worker panicked")))
+        })
+    })
+}
+
+/// Moves a finished checkout from `staging` to `path`.
+///
+/// The staging directory is renamed to `path` if it doesn't exist; otherwise its top-level
+/// entries are moved into `path` one by one, moving any already moved back if one fails.
+pub(crate) fn move_into_place(staging: &Path, path: &Path, records: &[Leaf]) -> anyhow::Result<()> {
+    if fs::symlink_metadata(path).is_err() {
+        return fs::rename(staging, path)
+            .with_context(|| format!("Failed to move checkout into {}", path.display()));
+    }
+
+    let mut moved = Vec::new();
+    for record in records {
+        let (from, to) = (staging.join(&record.path), path.join(&record.path));
+        if let Err(e) = fs::rename(&from, &to) {
+            for (from, to) in moved.iter().rev() {
+                let _ = fs::rename(to, from);
+            }
+            return Err(e).with_context(|| format!("Failed to move {} into place", to.display()));
+        }
+        moved.push((from, to));
+    }
+
+    fs::remove_dir(staging).with_context(|| format!("Failed to remove {}", staging.display()))
+}
+
+impl fmt::Display for Leaf {
+    /// Formats the leaf as `<mode> <type> <hash>\t<path>`, as listed by `cat-file -p`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {}\t{}",
+            self.file_mode,
+            Self::get_type_from_mode(&self.file_mode),
+            self.hash,
+            self.path.display()
+        )
     }
 }
 
 impl Leaf {
-    fn parse(cursor: &mut Cursor<&[u8]>, data: &[u8]) -> Self {
+    fn parse(cursor: &mut Cursor<&[u8]>, data: &[u8]) -> Result<Self, ObjectError> {
         let curr_pos = cursor.position() as usize;
 
         // Extract the file mode
         let space_idx = data[curr_pos..]
             .iter()
             .position(|&b| b == b' ')
-            .ok_or("Malformed leaf record: Missing space")
-            .unwrap()
+            .ok_or_else(|| invalid(format!("missing space in entry at byte {}", curr_pos)))?
             + curr_pos;
         let mut mode = String::from_utf8_lossy(&data[curr_pos..space_idx]).into_owned();
 
-        info!("Size for mode: {}", space_idx - curr_pos);
         // Normalize to 6 bytes
         if space_idx - curr_pos == 5 {
             mode.insert(0, '0');
         }
+        if Self::type_of_mode(&mode).is_none() {
+            return Err(invalid(format!(
+                "bad mode {} in entry at byte {}",
+                mode, curr_pos
+            )));
+        }
 
         // Extract the file path
         let null_idx = data[space_idx..]
             .iter()
             .position(|&b| b == 0)
-            .ok_or("Mallformed leaf record: Expected null byte")
-            .unwrap()
+            .ok_or_else(|| invalid(format!("missing null byte in entry at byte {}", curr_pos)))?
             + space_idx;
         let path = String::from_utf8_lossy(&data[space_idx + 1..null_idx]).into_owned();
 
@@ -129,30 +441,124 @@ impl Leaf {
         let mut hash_buf = vec![0; 20];
         cursor
             .read_exact(&mut hash_buf)
-            .expect("Couldn't read SHA-1 hash from leaf record");
+            .map_err(|_| invalid(format!("truncated hash in entry at byte {}", curr_pos)))?;
 
-        let hash = String::from_utf8_lossy(&hash_buf).into_owned();
+        let hash = hex::encode(&hash_buf);
 
-        Self {
-            file_mode: mode.to_string().to_owned(),
-            path: PathBuf::from_str(&path).expect("Couldn't create PathBuf"),
+        Ok(Self {
+            file_mode: mode,
+            path: PathBuf::from(path),
             hash,
-        }
+        })
     }
 
+    /// Returns the type of object an entry with this (normalized) mode points at.
+    ///
+    /// Modes are checked when trees are parsed, so any other mode is treated as a blob.
     pub fn get_type_from_mode(file_mode: &str) -> ObjectType {
-        let file_type;
-        if file_mode.len() == 5 {
-            file_type = &file_mode[..1]
+        Self::type_of_mode(file_mode).unwrap_or(ObjectType::Blob)
+    }
+
+    fn type_of_mode(file_mode: &str) -> Option<ObjectType> {
+        let file_type = if file_mode.len() == 5 {
+            file_mode.get(..1)
         } else {
-            file_type = &file_mode[..2]
+            file_mode.get(..2)
+        };
+
+        match file_type? {
+            "4" | "04" => Some(ObjectType::Tree),
+            "10" | "12" => Some(ObjectType::Blob),
+            "16" => Some(ObjectType::Commit),
+            _ => None,
+        }
+    }
+}
+
+fn invalid(reason: String) -> ObjectError {
+    ObjectError::Invalid {
+        object_type: ObjectType::Tree,
+        reason,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::GitrsError, testing::TempRepo};
+
+    /// Writes a tree with the given `(mode, name, hash)` entries, without validating them.
+    fn raw_tree(repo: &TempRepo, entries: &[(&str, &str, &str)]) -> (String, Tree) {
+        let mut data = Vec::new();
+        for (mode, name, hash) in entries {
+            data.extend_from_slice(format!("{} {}\0", mode, name).as_bytes());
+            data.extend_from_slice(&hex::decode(hash).unwrap());
         }
+        let sha = GitrsObject::hash_raw(&data, &ObjectType::Tree, Some(repo)).unwrap();
+        match GitrsObject::read(repo, &sha).unwrap() {
+            GitrsObject::TreeObject(tree) => (sha, tree),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn checkout_refuses_unsafe_names() {
+        let repo = TempRepo::new();
+        let out = repo.path("out");
+        let blob = GitrsObject::hash_raw(b"x\n", &ObjectType::Blob, Some(&repo)).unwrap();
+        let (safe, tree) = raw_tree(&repo, &[("100644", "file", &blob)]);
+        assert!(tree.verify_paths(&repo).is_ok());
 
-        match file_type {
-            "4" | "04" => ObjectType::Tree,
-            "10" | "12" => ObjectType::Blob,
-            "16" => ObjectType::Commit,
-            _ => panic!("Weird leaf mode: {}", file_mode),
+        for name in ["..", ".", "", "a/b", ".gitrs", ".GitRS"] {
+            let (_, tree) = raw_tree(&repo, &[("100644", "ok", &blob), ("100644", name, &blob)]);
+            assert!(
+                tree.checkout(&repo, &out, &CheckoutOptions::default())
+                    .is_err()
+            );
+            assert!(!out.exists(), "{name:?} was checked out");
         }
+
+        // Names in subtrees are checked too
+        let (nested, _) = raw_tree(&repo, &[("100644", ".gitrs", &blob)]);
+        let (_, tree) = raw_tree(&repo, &[("40000", "a", &safe), ("40000", "b", &nested)]);
+        assert!(
+            tree.checkout(&repo, &out, &CheckoutOptions::default())
+                .is_err()
+        );
+        assert!(!out.exists());
+    }
+
+    #[test]
+    fn writing_a_bad_hash_is_a_corrupt_object_error() {
+        let repo = TempRepo::new();
+        let mut tree = GitrsObject::TreeObject(Tree {
+            records: vec![Leaf {
+                file_mode: "100644".to_string(),
+                path: PathBuf::from("file"),
+                hash: "not hex".to_string(),
+            }],
+        });
+
+        let error = tree.write(&repo).unwrap_err();
+        assert!(matches!(
+            GitrsError::find(&error),
+            Some(GitrsError::CorruptObject { .. })
+        ));
+    }
+
+    #[test]
+    fn checkout_replaces_a_stale_staging_directory() {
+        let repo = TempRepo::new();
+        let out = repo.path("out");
+        let staging = format!(".out.checkout-{}", process::id());
+        let blob = GitrsObject::hash_raw(b"x\n", &ObjectType::Blob, Some(&repo)).unwrap();
+        let (_, tree) = raw_tree(&repo, &[("100644", "file", &blob)]);
+        repo.write(&format!("{}/junk", staging), "old");
+
+        let written = tree.checkout(&repo, &out, &CheckoutOptions::default());
+        assert_eq!(written.unwrap(), 1);
+        assert_eq!(fs::read(out.join("file")).unwrap(), b"x\n");
+        assert!(!out.join("junk").exists());
+        assert!(!repo.path(&staging).exists());
     }
 }
@@ -0,0 +1,406 @@
+// Interactive hunk selection for `add --patch`
+use std::{
+    env, fs,
+    io::{BufRead, Write},
+    path::{Path, PathBuf},
+    process,
+};
+
+use anyhow::{Context, anyhow};
+
+use crate::{
+    diff::{self, CONTEXT_LINES, DiffLine, Hunk},
+    index::{Index, IndexEntry},
+    object::{GitrsObject, ObjectType},
+    repository::{Repository, resolve_path},
+    worktree,
+};
+
+const EDIT_FILE: &str = "ADD_EDIT.hunk";
+
+const HELP: &str = "y - stage this hunk
+n - do not stage this hunk
+a - stage this hunk and all later hunks in the file
+d - do not stage this hunk or any of the later hunks in the file
+s - split the current hunk into smaller hunks
+e - manually edit the current hunk
+q - quit; do not stage this hunk or any of the remaining ones
+? - print help";
+
+/// Walks the hunks between the staged and worktree versions of each tracked file under `paths`
+/// (or the whole worktree if empty), asking which ones to stage.
+///
+/// Answers are read line by line from `input` so the selection can be scripted; running out of
+/// input behaves like `q`. Only the accepted hunks are applied to the staged blob, which is then
+/// written to the index. Returns the number of files whose staged content changed.
+pub fn add_patch<R: BufRead, W: Write>(
+    repository: &Repository,
+    index: &mut Index,
+    paths: &[PathBuf],
+    input: &mut R,
+    output: &mut W,
+) -> anyhow::Result<usize> {
+    let roots = if paths.is_empty() {
+        vec![repository.worktree.clone()]
+    } else {
+        paths.iter().map(|path| resolve_path(path)).collect()
+    };
+
+    let tracked: Vec<(PathBuf, String)> = index
+        .entries
+        .iter()
+        .filter(|entry| roots.iter().any(|root| entry.path.starts_with(root)))
+        .filter(|entry| fs::symlink_metadata(&entry.path).is_ok_and(|m| m.is_file()))
+        .map(|entry| (entry.path.clone(), entry.sha.clone()))
+        .collect();
+
+    let mut staged = 0;
+
+    for (path, sha) in tracked {
+        let old = match GitrsObject::read(repository, &sha)? {
+            GitrsObject::BlobObject(blob) => blob.into_data(),
+            other => {
+                return Err(anyhow!(
+                    "Expected blob for {}, found {}",
+                    path.display(),
+                    other.get_type()
+                ));
+            }
+        };
+        let (mode, new) = worktree::read_file(&path)?;
+        let name = repository.relative_path(&path).display().to_string();
+
+        if old == new {
+            continue;
+        }
+        if old.contains(&0) || new.contains(&0) {
+            writeln!(output, "Skipping binary file {}", name)?;
+            continue;
+        }
+
+        writeln!(output, "diff --gitrs a/{0} b/{0}", name)?;
+        writeln!(output, "--- a/{}\n+++ b/{}", name, name)?;
+
+        let (selected, quit) = select_hunks(
+            repository,
+            diff::diff(&old, &new, CONTEXT_LINES),
+            input,
+            output,
+        )?;
+
+        if !selected.is_empty() {
+            let content = diff::apply(&old, &selected);
+            let sha = GitrsObject::deserialize(&content, ObjectType::Blob)?.write(repository)?;
+            let mtime = fs::symlink_metadata(&path)?.modified()?;
+
+            index.upsert(
+                IndexEntry::builder()
+                    .mtime(mtime)
+                    .sha(sha)
+                    .size_in_bytes(content.len() as u64)
+                    .mode(mode)
+                    .path(path)
+                    .build(),
+            );
+            staged += 1;
+        }
+
+        if quit {
+            break;
+        }
+    }
+
+    if staged > 0 {
+        index.write(repository)?;
+    }
+
+    Ok(staged)
+}
+
+/// Prompts for each hunk of a single file, returning the accepted hunks (in order) and whether
+/// the user asked to quit.
+fn select_hunks<R: BufRead, W: Write>(
+    repository: &Repository,
+    mut hunks: Vec<Hunk>,
+    input: &mut R,
+    output: &mut W,
+) -> anyhow::Result<(Vec<Hunk>, bool)> {
+    let mut accepted = vec![false; hunks.len()];
+    let mut quit = false;
+    let mut i = 0;
+
+    while i < hunks.len() {
+        let can_split = hunks[i].split().is_some();
+        write!(output, "{}", hunks[i])?;
+        write!(
+            output,
+            "({}/{}) Stage this hunk [y,n,a,d{},e,q,?]? ",
+            i + 1,
+            hunks.len(),
+            if can_split { ",s" } else { "" }
+        )?;
+        output.flush()?;
+
+        let mut answer = String::new();
+        if input.read_line(&mut answer)? == 0 {
+            writeln!(output)?;
+            quit = true;
+            break;
+        }
+
+        match answer.trim() {
+            "y" => {
+                accepted[i] = true;
+                i += 1;
+            }
+            "n" => i += 1,
+            "a" => {
+                accepted[i..].fill(true);
+                break;
+            }
+            "d" => break,
+            "q" => {
+                quit = true;
+                break;
+            }
+            "s" if can_split => {
+                let parts = hunks[i].split().unwrap_or_default();
+                writeln!(output, "Split into {} hunks.", parts.len())?;
+                let count = parts.len();
+                hunks.splice(i..=i, parts);
+                accepted.splice(i..=i, vec![false; count]);
+            }
+            "e" => match edit_hunk(repository, &hunks[i])? {
+                Some(edited) => {
+                    hunks[i] = edited;
+                    accepted[i] = true;
+                    i += 1;
+                }
+                None => writeln!(output, "Your edited hunk does not apply.")?,
+            },
+            _ => writeln!(output, "{}", HELP)?,
+        }
+    }
+
+    let selected = hunks
+        .into_iter()
+        .zip(accepted)
+        .filter_map(|(hunk, accepted)| accepted.then_some(hunk))
+        .collect();
+
+    Ok((selected, quit))
+}
+
+/// Opens the hunk in the user's editor and parses the result back.
+///
+/// Returns `None` if the edited hunk no longer applies to the staged content (i.e. its context
+/// and removed lines were changed).
+fn edit_hunk(repository: &Repository, hunk: &Hunk) -> anyhow::Result<Option<Hunk>> {
+    let path = repository.gitdir.join(EDIT_FILE);
+
+    let mut content = b"# Manual hunk edit mode -- lines starting with '#' are ignored.\n\
+        # To remove '-' lines, make them ' ' lines (context).\n\
+        # To remove '+' lines, delete them.\n"
+        .to_vec();
+    for line in &hunk.lines {
+        let (prefix, data) = match line {
+            DiffLine::Context(data) => (b' ', data),
+            DiffLine::Delete(data) => (b'-', data),
+            DiffLine::Insert(data) => (b'+', data),
+        };
+        content.push(prefix);
+        content.extend_from_slice(data);
+        if data.last() != Some(&b'\n') {
+            content.extend_from_slice(b"\n\\ No newline at end of file\n");
+        }
+    }
+    fs::write(&path, content)?;
+
+    run_editor(&path)?;
+
+    let edited = fs::read(&path)?;
+    fs::remove_file(&path)?;
+
+    Ok(parse_edited_hunk(hunk, &edited))
+}
+
+/// Runs the configured editor (`GITRS_EDITOR`, `VISUAL`, `EDITOR`, falling back to `vi`).
+fn run_editor(path: &Path) -> anyhow::Result<()> {
+    let editor = ["GITRS_EDITOR", "VISUAL", "EDITOR"]
+        .iter()
+        .find_map(|var| env::var(var).ok().filter(|value| !value.is_empty()))
+        .unwrap_or_else(|| "vi".to_string());
+
+    let status = process::Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$@\"", editor))
+        .arg(&editor)
+        .arg(path)
+        .status()
+        .with_context(|| format!("Couldn't launch editor '{}'", editor))?;
+
+    if !status.success() {
+        return Err(anyhow!("Editor '{}' exited with {}", editor, status));
+    }
+
+    Ok(())
+}
+
+fn parse_edited_hunk(original: &Hunk, edited: &[u8]) -> Option<Hunk> {
+    let mut lines: Vec<DiffLine> = Vec::new();
+
+    for raw in diff::split_lines(edited) {
+        match raw.first() {
+            Some(b'#') => {}
+            Some(b'@') if raw.starts_with(b"@@") => {}
+            Some(b'\\') => match lines.last_mut()? {
+                DiffLine::Context(data) | DiffLine::Delete(data) | DiffLine::Insert(data) => {
+                    if data.last() == Some(&b'\n') {
+                        data.pop();
+                    }
+                }
+            },
+            Some(b' ') => lines.push(DiffLine::Context(raw[1..].to_vec())),
+            Some(b'-') => lines.push(DiffLine::Delete(raw[1..].to_vec())),
+            Some(b'+') => lines.push(DiffLine::Insert(raw[1..].to_vec())),
+            // Editors commonly strip the trailing space off empty context lines
+            Some(b'\n') => lines.push(DiffLine::Context(b"\n".to_vec())),
+            _ => return None,
+        }
+    }
+
+    let hunk = Hunk {
+        old_start: original.old_start,
+        new_start: original.new_start,
+        lines,
+    };
+
+    (hunk.old_side() == original.old_side()).then_some(hunk)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{index::AddOptions, testing::TempRepo};
+
+    fn hunk(lines: &[DiffLine]) -> Hunk {
+        Hunk {
+            old_start: 4,
+            new_start: 4,
+            lines: lines.to_vec(),
+        }
+    }
+
+    fn context(text: &str) -> DiffLine {
+        DiffLine::Context(text.as_bytes().to_vec())
+    }
+
+    fn delete(text: &str) -> DiffLine {
+        DiffLine::Delete(text.as_bytes().to_vec())
+    }
+
+    fn insert(text: &str) -> DiffLine {
+        DiffLine::Insert(text.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn parses_edited_hunk() {
+        let original = hunk(&[context("a\n"), delete("b\n"), insert("c\n"), insert("d\n")]);
+        let edited = b"# comment\n@@ -5,2 +5,3 @@\n a\n-b\n+c\n+e\n";
+
+        let parsed = parse_edited_hunk(&original, edited).expect("edited hunk applies");
+        assert_eq!(parsed.old_start, 4);
+        assert_eq!(
+            parsed.lines,
+            [context("a\n"), delete("b\n"), insert("c\n"), insert("e\n")]
+        );
+    }
+
+    #[test]
+    fn accepts_empty_context_lines_stripped_by_editors() {
+        let original = hunk(&[context("\n"), delete("b\n")]);
+        let parsed = parse_edited_hunk(&original, b"\n-b\n").expect("edited hunk applies");
+        assert_eq!(parsed.lines, [context("\n"), delete("b\n")]);
+    }
+
+    #[test]
+    fn honours_missing_newline_marker() {
+        let original = hunk(&[delete("b")]);
+        let parsed = parse_edited_hunk(&original, b"-b\n\\ No newline at end of file\n+c\n")
+            .expect("edited hunk applies");
+        assert_eq!(parsed.lines, [delete("b"), insert("c\n")]);
+    }
+
+    #[test]
+    fn rejects_edits_to_the_old_side() {
+        let original = hunk(&[context("a\n"), delete("b\n")]);
+        assert!(parse_edited_hunk(&original, b" a\n-x\n").is_none());
+        assert!(parse_edited_hunk(&original, b" a\n").is_none());
+        assert!(parse_edited_hunk(&original, b" a\n-b\nstray\n").is_none());
+    }
+
+    #[test]
+    fn stages_only_selected_hunks() {
+        let repo = TempRepo::new();
+        let numbers = |first: &str, last: &str| {
+            let middle: String = (2..12).map(|i| format!("{}\n", i)).collect();
+            format!("{}\n{}{}\n", first, middle, last)
+        };
+        let path = repo.write("file.txt", &numbers("1", "12"));
+
+        let mut index = Index::default();
+        index
+            .add(&repo, std::slice::from_ref(&path), &AddOptions::default())
+            .unwrap();
+
+        fs::write(&path, numbers("one", "twelve")).unwrap();
+
+        // Skip the first hunk and stage the second
+        let mut output = Vec::new();
+        let staged = add_patch(&repo, &mut index, &[], &mut &b"n\ny\n"[..], &mut output).unwrap();
+        assert_eq!(staged, 1);
+
+        let prompts = String::from_utf8(output).unwrap();
+        assert!(prompts.contains("(1/2) Stage this hunk [y,n,a,d,e,q,?]? "));
+        assert!(prompts.contains("(2/2) Stage this hunk"));
+
+        let entry = index.entries.iter().find(|e| e.path == path).unwrap();
+        let GitrsObject::BlobObject(blob) = GitrsObject::read(&repo, &entry.sha).unwrap() else {
+            panic!("staged object is not a blob");
+        };
+        assert_eq!(blob.get_data(), numbers("1", "twelve").as_bytes());
+
+        // The index on disk has the same content
+        let on_disk = Index::read(&repo).unwrap();
+        assert_eq!(on_disk.entries[0].sha, entry.sha);
+    }
+
+    #[test]
+    fn split_and_quit_are_scriptable() {
+        let repo = TempRepo::new();
+        let path = repo.write("file.txt", "1\n2\n3\n4\n5\n6\n");
+
+        let mut index = Index::default();
+        index
+            .add(&repo, std::slice::from_ref(&path), &AddOptions::default())
+            .unwrap();
+        fs::write(&path, "one\n2\n3\n4\n5\nsix\n").unwrap();
+
+        // Split the single hunk, stage its first part, then run out of input
+        let mut output = Vec::new();
+        let staged = add_patch(&repo, &mut index, &[], &mut &b"s\ny\n"[..], &mut output).unwrap();
+        assert_eq!(staged, 1);
+        assert!(
+            String::from_utf8(output)
+                .unwrap()
+                .contains("Split into 2 hunks.")
+        );
+
+        let GitrsObject::BlobObject(blob) =
+            GitrsObject::read(&repo, &index.entries[0].sha).unwrap()
+        else {
+            panic!("staged object is not a blob");
+        };
+        assert_eq!(blob.get_data(), b"one\n2\n3\n4\n5\n6\n");
+    }
+}
@@ -1,12 +1,16 @@
 /// Manages git references (refs), providing utilities
 /// to resolve, list, and create references in a repository.
 use core::str;
-use std::{fs, io, path::Path};
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+};
 
 use anyhow::Context;
 use indexmap::IndexMap;
 
-use crate::repository::Repository;
+use crate::repository::{self, Repository};
 
 pub struct Ref;
 
@@ -40,22 +44,61 @@ impl Ref {
 
     /// Lists all references at the given directory path inside the repository.
     ///
-    /// Returns an ordered map of ref names (relative paths) to their resolved SHA-1 hashes.
+    /// Returns an ordered map of ref names (paths relative to the gitdir, e.g.
+    /// `refs/heads/master`) to their resolved SHA-1 hashes.
     pub fn list_at(
         repository: &Repository,
         path: &Path,
     ) -> anyhow::Result<IndexMap<String, String>> {
-        Ok(Self::list_at_dir(repository, path)?.into_iter().collect())
+        Ok(Self::list_at_dir(repository, path)?
+            .into_iter()
+            .map(|(name, hash)| {
+                let name = Path::new(&name)
+                    .strip_prefix(&repository.gitdir)
+                    .map_or(name.clone(), |relative| relative.display().to_string());
+                (name, hash)
+            })
+            .collect())
     }
 
-    /// Creates a new reference file at the specified path with the given SHA-1 hash content.
+    /// Creates or updates the reference file at the specified path with the given SHA-1 hash.
+    ///
+    /// Like git, the new content is written to `<ref>.lock` and renamed over the ref, so
+    /// concurrent updates fail with `GitrsError::RefLockHeld` instead of clobbering each other.
     pub fn create_at(repository: &Repository, hash: &str, paths: &[&str]) -> anyhow::Result<()> {
+        let path = paths
+            .iter()
+            .fold(repository.gitdir.clone(), |path, part| path.join(part));
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Couldn't create directory {}", parent.display()))?;
+        }
+
+        repository::write_locked(&path, |file| writeln!(file, "{}", hash))
+    }
+
+    /// Returns the ref HEAD refers to (e.g. `refs/heads/master`), or `None` if it is detached.
+    pub fn head_branch(repository: &Repository) -> anyhow::Result<Option<String>> {
         let path = repository
-            .create_file(paths)
-            .with_context(|| format!("Couldn't create file at {:?}", paths))?;
+            .get_path_to_file_if_exists(&["HEAD"])
+            .context("Repository has no HEAD")?;
+        let data = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read file: {}", path.display()))?;
+
+        Ok(data
+            .trim_end()
+            .strip_prefix("ref:")
+            .map(|target| target.trim().to_string()))
+    }
+
+    /// Points the branch HEAD refers to at `hash`, or HEAD itself when it is detached.
+    ///
+    /// Returns the name of the ref that was updated, e.g. `refs/heads/master`.
+    pub fn update_head(repository: &Repository, hash: &str) -> anyhow::Result<String> {
+        let target = Self::head_branch(repository)?.unwrap_or_else(|| "HEAD".to_string());
+        Self::create_at(repository, hash, &target.split('/').collect::<Vec<_>>())?;
 
-        fs::write(path, format!("{}\n", hash))?;
-        Ok(())
+        Ok(target)
     }
 
     /// Recursively lists references inside a directory, returning a vector of
@@ -67,10 +110,10 @@ impl Ref {
             .map(|comp| {
                 comp.as_os_str()
                     .to_str()
-                    .expect("Could not convert path component to string")
-                    .to_string()
+                    .map(str::to_string)
+                    .with_context(|| format!("Ref path is not valid UTF-8: {}", path.display()))
             })
-            .collect();
+            .collect::<anyhow::Result<_>>()?;
 
         // Read directory entries and sort by filename
         let mut entries: Vec<_> = fs::read_dir(path)
@@ -87,6 +130,8 @@ impl Ref {
                 if file_type.is_dir() {
                     // Recurse into subdirectory
                     acc.extend(Self::list_at_dir(repository, &entry.path())?);
+                } else if entry.file_name().to_string_lossy().ends_with(".lock") {
+                    // A ref being updated by `create_at`, not a ref itself
                 } else {
                     // Resolve the ref file to its SHA
                     let file_name = entry.file_name().to_string_lossy().into_owned();
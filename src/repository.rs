@@ -1,17 +1,29 @@
 // Definitions and methods for the gitrs "repository"
 
-use core::panic;
 use std::{
+    collections::{BinaryHeap, HashSet},
     env,
-    fs::{self, File, canonicalize},
-    io::Write,
-    path::{Path, PathBuf},
+    fs::{self, File, OpenOptions, canonicalize},
+    io::{self, Write},
+    path::{Component, Path, PathBuf},
 };
 
 use anyhow::{Context, Result, anyhow, ensure};
-use flate2::{Compression, write::ZlibEncoder};
 use log::error;
 
+use crate::{
+    error::GitrsError,
+    index::Index,
+    object::{
+        GitrsObject::{self, CommitObject},
+        ObjectFindOptions, ObjectType,
+        commit::Commit,
+    },
+    refs::Ref,
+    status::Status,
+};
+
+/// A repository on disk: a worktree with the `.gitrs` directory holding its objects and refs.
 pub struct Repository {
     pub worktree: PathBuf, // canonicalized
     pub gitdir: PathBuf,
@@ -35,15 +47,17 @@ impl Repository {
     ];
 
     /////////////////////////////////////
-    /// Repository Initialization
+    // Repository Initialization
     /////////////////////////////////////
 
     /// Constructs an in-memory handle to an existing repository
-    pub fn new(worktree: &Path) -> Self {
-        Self {
-            worktree: fs::canonicalize(worktree).expect("Failed to canonicalize worktree path"),
+    pub fn new(worktree: &Path) -> Result<Self> {
+        let worktree = fs::canonicalize(worktree)
+            .with_context(|| format!("Invalid worktree: {}", worktree.display()))?;
+        Ok(Self {
             gitdir: worktree.join(".gitrs"),
-        }
+            worktree,
+        })
     }
 
     /// Initializes a new gitrs repository
@@ -65,7 +79,7 @@ impl Repository {
         fs::create_dir_all(&gitdir)
             .with_context(|| format!("Failed to create directory {}", gitdir.display()))?;
 
-        let repo = Self::new(worktree);
+        let repo = Self::new(worktree)?;
 
         for segments in Self::REQUIRED_DIRS {
             repo.compute_or_create_repo_dir(segments, true)
@@ -86,20 +100,77 @@ impl Repository {
     pub fn find_repository_at(current_path: &Path) -> Option<Self> {
         let path = canonicalize(current_path).ok()?;
         if path.join(".gitrs").exists() {
-            Some(Self::new(current_path))
+            Self::new(&path).ok()
         } else {
             path.parent().and_then(Self::find_repository_at)
         }
     }
 
     /// Finds the closest repository to the current working directory
-    pub fn find_repository() -> Self {
-        Self::find_repository_at(&env::current_dir().unwrap())
-            .expect("Expected a repository at current dir")
+    pub fn find_repository() -> Result<Self> {
+        let cwd = env::current_dir().context("Couldn't determine the current directory")?;
+        Self::find_repository_at(&cwd).ok_or_else(|| GitrsError::NotARepository.into())
     }
 
     /////////////////////////////////////
-    /// Repository File Management
+    // High-level Operations
+    /////////////////////////////////////
+
+    /// Records the index as a commit on top of `HEAD` and moves the current branch (or a
+    /// detached `HEAD`) to it, like `commit -m`.
+    ///
+    /// Returns `None`, changing nothing, if the index has the same tree as `HEAD`.
+    pub fn commit(&self, message: &str) -> Result<Option<NewCommit>> {
+        // 1. Convert the index into a tree object
+        let tree = Index::read(self)?.write_tree(self, None)?;
+
+        // HEAD doesn't resolve yet on an unborn branch, making this a root commit
+        let parent = Ref::resolve(self, &["HEAD"]).ok();
+        let parent_tree = parent
+            .as_ref()
+            .and_then(|parent| GitrsObject::read(self, parent).ok())
+            .and_then(|parent| match parent {
+                CommitObject(commit) => commit.tree().map(str::to_string),
+                _ => None,
+            });
+        if parent_tree.as_deref() == Some(tree.as_str()) {
+            return Ok(None);
+        }
+
+        // 2. Generate and store the corresponding commit object
+        let hash = Commit::create(self, &tree, parent.into_iter().collect(), message)?;
+
+        // 3. Update the HEAD branch to the new commit
+        let target = Ref::update_head(self, &hash).context("Couldn't update HEAD")?;
+
+        Ok(Some(NewCommit { hash, target }))
+    }
+
+    /// Compares `HEAD`, the index and the worktree, like `status`.
+    pub fn status(&self) -> Result<Status> {
+        Status::read(self, &Index::read(self)?)
+    }
+
+    /// Walks the history reachable from the commit named `start`, like `log`.
+    pub fn log(&self, start: &str) -> Result<Log<'_>> {
+        let hash = GitrsObject::find(
+            self,
+            start,
+            Some(ObjectFindOptions {
+                object_type: ObjectType::Commit,
+                should_follow: false,
+            }),
+        )?;
+
+        Ok(Log {
+            repository: self,
+            pending: BinaryHeap::from([(0, hash)]),
+            seen: HashSet::new(),
+        })
+    }
+
+    /////////////////////////////////////
+    // Repository File Management
     /////////////////////////////////////
 
     pub fn get_path_to_file_if_exists(&self, paths: &[&str]) -> Option<PathBuf> {
@@ -116,19 +187,19 @@ impl Repository {
             .and_then(|(_, path)| path.exists().then_some(path))
     }
 
+    /// Returns true if the path (which need not exist) lies inside the worktree
     pub fn contains(&self, path: &Path) -> bool {
-        let canonicalized_path = fs::canonicalize(path).expect("Failed to canonicalize path");
-        canonicalized_path.starts_with(&self.worktree)
+        resolve_path(path).starts_with(&self.worktree)
+    }
+
+    /// Returns true if the path (which need not exist) lies inside the `.gitrs` directory
+    pub fn is_gitdir_path(&self, path: &Path) -> bool {
+        resolve_path(path).starts_with(self.worktree.join(".gitrs"))
     }
 
-    /// Compresses and writes to a file (upserts if exists)
-    pub fn upsert_file(&self, paths: &[&str], data: &Vec<u8>) -> Option<PathBuf> {
-        let (file, path) = self.compute_or_create_repo_file(paths, true)?;
-        ZlibEncoder::new(file, Compression::default())
-            .write_all(data)
-            .map_err(|e| error!("Could not compress file at {}: {}", path.display(), e))
-            .ok()?;
-        Some(path)
+    /// Returns the path relative to the worktree root, for display purposes
+    pub fn relative_path<'a>(&self, path: &'a Path) -> &'a Path {
+        path.strip_prefix(&self.worktree).unwrap_or(path)
     }
 
     /// Computes a full path under `.gitrs` directory
@@ -170,11 +241,15 @@ impl Repository {
     ) -> Option<PathBuf> {
         let path = self.compute_repo_path(paths);
         if path.exists() {
-            assert!(path.is_dir(), "Expected a directory at {}", path.display());
+            if !path.is_dir() {
+                error!("Expected a directory at {}", path.display());
+                return None;
+            }
             Some(path)
         } else if create_if_missing {
             fs::create_dir_all(&path)
-                .unwrap_or_else(|e| panic!("Failed to create directory {}: {}", path.display(), e));
+                .map_err(|e| error!("Failed to create directory {}: {}", path.display(), e))
+                .ok()?;
             Some(path)
         } else {
             None
@@ -190,7 +265,147 @@ impl Repository {
     }
 }
 
+/// A commit made by `Repository::commit`.
+pub struct NewCommit {
+    /// Hash of the commit
+    pub hash: String,
+    /// The ref moved to the commit, e.g. `refs/heads/master` (or `HEAD` when detached)
+    pub target: String,
+}
+
+/// Iterator over the `(hash, commit)` pairs of a history, from `Repository::log`.
+///
+/// Commits are visited once each, newest first by committer date. Parents that can't be read
+/// (e.g. in a shallow copy) end the walk along their line of history.
+pub struct Log<'a> {
+    repository: &'a Repository,
+    pending: BinaryHeap<(i64, String)>,
+    seen: HashSet<String>,
+}
+
+impl Iterator for Log<'_> {
+    type Item = Result<(String, Commit)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let hash = loop {
+            let (_, hash) = self.pending.pop()?;
+            if self.seen.insert(hash.clone()) {
+                break hash;
+            }
+        };
+
+        let commit = match GitrsObject::read(self.repository, &hash) {
+            Ok(CommitObject(commit)) => commit,
+            Ok(_) => return Some(Err(anyhow!("Expected commit object for hash {}", hash))),
+            Err(e) => return Some(Err(e)),
+        };
+
+        for parent in commit.parents() {
+            if let Ok(CommitObject(parent_obj)) = GitrsObject::read(self.repository, parent) {
+                let date = parent_obj.committer().map_or(0, |c| c.timestamp);
+                self.pending.push((date, parent.to_string()));
+            }
+        }
+
+        Some(Ok((hash, commit)))
+    }
+}
+
+/// Resolves a path that may not exist yet into an absolute path, handling `.` and `..`
+/// lexically and canonicalizing the longest existing ancestor of its parent.
+///
+/// The final component is kept as is, so a path naming a symlink resolves to the link itself
+/// rather than its target (as git does for the paths given to `add`, `rm` and `mv`).
+pub fn resolve_path(path: &Path) -> PathBuf {
+    let absolute = env::current_dir()
+        .map(|cwd| cwd.join(path))
+        .unwrap_or_else(|_| path.to_path_buf());
+
+    let normalized = absolute
+        .components()
+        .fold(PathBuf::new(), |mut acc, component| {
+            match component {
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    acc.pop();
+                }
+                other => acc.push(other),
+            }
+            acc
+        });
+
+    match (normalized.parent(), normalized.file_name()) {
+        (Some(parent), Some(name)) => resolve_existing_prefix(parent).join(name),
+        _ => resolve_existing_prefix(&normalized),
+    }
+}
+
+/// Canonicalizes the longest existing ancestor of `path` and appends the remaining components.
+fn resolve_existing_prefix(path: &Path) -> PathBuf {
+    path.ancestors()
+        .find_map(|ancestor| {
+            let canonical = fs::canonicalize(ancestor).ok()?;
+            match path.strip_prefix(ancestor).ok()? {
+                rest if rest.as_os_str().is_empty() => Some(canonical),
+                rest => Some(canonical.join(rest)),
+            }
+        })
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+/// Returns true if the path is a directory itself, not a symlink to one
+pub fn is_dir_no_follow(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|metadata| metadata.is_dir())
+}
+
+/// Expresses an absolute `path` relative to the absolute directory `base`, using `..` to climb
+/// out of `base` when needed (e.g. for printing paths relative to the current directory)
+pub fn relative_to(path: &Path, base: &Path) -> PathBuf {
+    let common = path
+        .components()
+        .zip(base.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut relative: PathBuf = base.components().skip(common).map(|_| "..").collect();
+    relative.extend(path.components().skip(common));
+
+    if relative.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        relative
+    }
+}
+
 /// Returns true if a directory exists and is empty
 pub fn is_empty_dir(path: &Path) -> bool {
-    path.is_dir() && fs::read_dir(path).map_or(false, |mut entries| entries.next().is_none())
+    path.is_dir() && fs::read_dir(path).is_ok_and(|mut entries| entries.next().is_none())
+}
+
+/// Replaces the file at `path` with what `write` produces, like git does: the content goes to
+/// `<path>.lock`, which is renamed over `path` once complete, so readers never see a partial
+/// file and concurrent writers fail with `GitrsError::RefLockHeld` instead of clobbering each
+/// other.
+pub fn write_locked(path: &Path, write: impl FnOnce(&mut File) -> io::Result<()>) -> Result<()> {
+    let mut lock_name = path.as_os_str().to_os_string();
+    lock_name.push(".lock");
+    let lock = PathBuf::from(lock_name);
+
+    let mut file = match OpenOptions::new().write(true).create_new(true).open(&lock) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            return Err(GitrsError::RefLockHeld { lock }.into());
+        }
+        Err(e) => {
+            return Err(e).with_context(|| format!("Couldn't create {}", lock.display()));
+        }
+    };
+
+    let result = write(&mut file)
+        .and_then(|_| fs::rename(&lock, path))
+        .with_context(|| format!("Couldn't update {}", path.display()));
+    if result.is_err() {
+        let _ = fs::remove_file(&lock);
+    }
+    result
 }
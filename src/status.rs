@@ -0,0 +1,344 @@
+// Comparing HEAD, the index and the worktree, for `status` and `diff`
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::bail;
+
+use crate::{
+    diff::{self, CONTEXT_LINES},
+    ignore::IgnoreRules,
+    index::{Content, Index, MODE_GITLINK, tree_blobs},
+    object::{GitrsObject, ObjectFindOptions, ObjectType},
+    refs::Ref,
+    repository::Repository,
+    worktree,
+};
+
+/// A path whose mode or content differs between two of HEAD, the index and the worktree.
+pub struct Change {
+    /// Absolute path of the file
+    pub path: PathBuf,
+    /// Mode and hash on the older side (HEAD or the index), `None` if the path was added
+    pub old: Option<Content>,
+    /// Mode and hash on the newer side (the index or the worktree), `None` if it was deleted
+    pub new: Option<Content>,
+}
+
+pub enum ChangeKind {
+    Added,
+    Modified,
+    Deleted,
+}
+
+/// The state of the worktree and index relative to HEAD.
+pub struct Status {
+    /// The branch HEAD is on (e.g. `master`), or `None` if it is detached
+    pub branch: Option<String>,
+    /// Changes from HEAD to the index
+    pub staged: Vec<Change>,
+    /// Paths with unresolved merge conflicts
+    pub unmerged: Vec<PathBuf>,
+    /// Changes from the index to the worktree
+    pub unstaged: Vec<Change>,
+    /// Files in the worktree that are neither tracked nor ignored, and directories holding
+    /// only such files
+    pub untracked: Vec<PathBuf>,
+}
+
+impl Change {
+    pub fn kind(&self) -> ChangeKind {
+        match (&self.old, &self.new) {
+            (None, _) => ChangeKind::Added,
+            (_, None) => ChangeKind::Deleted,
+            _ => ChangeKind::Modified,
+        }
+    }
+
+    /// Returns true if the path is a submodule on either side.
+    pub fn is_submodule(&self) -> bool {
+        [&self.old, &self.new]
+            .into_iter()
+            .flatten()
+            .any(|(mode, _)| *mode == MODE_GITLINK)
+    }
+}
+
+impl Status {
+    /// Compares HEAD's tree with the index, and the index with the worktree.
+    ///
+    /// On an unborn branch every staged path counts as added. Submodules are compared by the
+    /// commit they have checked out.
+    pub fn read(repository: &Repository, index: &Index) -> anyhow::Result<Self> {
+        let branch = Ref::head_branch(repository)?.map(|name| {
+            name.strip_prefix("refs/heads/")
+                .unwrap_or(&name)
+                .to_string()
+        });
+
+        // HEAD doesn't resolve on an unborn branch, but any other failure is an error
+        let head_tree = match Ref::resolve(repository, &["HEAD"]) {
+            Ok(_) => Some(GitrsObject::find(
+                repository,
+                "HEAD",
+                Some(ObjectFindOptions {
+                    object_type: ObjectType::Tree,
+                    should_follow: true,
+                }),
+            )?),
+            Err(_) => None,
+        };
+        let head: BTreeMap<PathBuf, Content> = match head_tree {
+            Some(tree) => tree_blobs(repository, &tree)?
+                .into_iter()
+                .map(|(path, content)| (repository.worktree.join(path), content))
+                .collect(),
+            None => BTreeMap::new(),
+        };
+
+        let unmerged: BTreeSet<PathBuf> = index
+            .entries
+            .iter()
+            .filter(|entry| entry.stage > 0)
+            .map(|entry| entry.path.clone())
+            .collect();
+        let staged: BTreeMap<PathBuf, Content> = index
+            .entries
+            .iter()
+            .filter(|entry| entry.stage == 0)
+            .map(|entry| (entry.path.clone(), (entry.mode, entry.sha.clone())))
+            .collect();
+
+        let paths: BTreeSet<&PathBuf> = head
+            .keys()
+            .chain(staged.keys())
+            .filter(|path| !unmerged.contains(*path))
+            .collect();
+        let staged_changes = paths
+            .into_iter()
+            .filter(|path| head.get(*path) != staged.get(*path))
+            .map(|path| Change {
+                path: path.clone(),
+                old: head.get(path).cloned(),
+                new: staged.get(path).cloned(),
+            })
+            .collect();
+
+        let mut unstaged = Vec::new();
+        for entry in index.entries.iter().filter(|entry| entry.stage == 0) {
+            let content = entry.worktree_content()?;
+            if content.as_ref() != Some(&(entry.mode, entry.sha.clone())) {
+                unstaged.push(Change {
+                    path: entry.path.clone(),
+                    old: Some((entry.mode, entry.sha.clone())),
+                    new: content,
+                });
+            }
+        }
+
+        // Like git, a directory without any tracked files is listed once instead of by file
+        let tracked_dirs: BTreeSet<&Path> = index
+            .entries
+            .iter()
+            .flat_map(|entry| entry.path.ancestors().skip(1))
+            .collect();
+        let rules = IgnoreRules::read(repository);
+        let untracked: BTreeSet<PathBuf> =
+            worktree::walk(repository, &repository.worktree, Some(&rules))?
+                .into_iter()
+                .filter(|path| !staged.contains_key(path) && !unmerged.contains(path))
+                .map(|path| {
+                    path.ancestors()
+                        .skip(1)
+                        .take_while(|dir| {
+                            *dir != repository.worktree && !tracked_dirs.contains(dir)
+                        })
+                        .last()
+                        .map_or(path.clone(), Path::to_path_buf)
+                })
+                .collect();
+
+        Ok(Self {
+            branch,
+            staged: staged_changes,
+            unmerged: unmerged.into_iter().collect(),
+            unstaged,
+            untracked: untracked.into_iter().collect(),
+        })
+    }
+}
+
+/// Writes changes as a unified diff.
+///
+/// The old side is read from the object store, as is the new side unless `from_worktree` is
+/// set. Submodules are shown as a change to a single `Subproject commit <hash>` line.
+pub fn write_diff<W: Write>(
+    repository: &Repository,
+    changes: &[Change],
+    from_worktree: bool,
+    output: &mut W,
+) -> anyhow::Result<()> {
+    for change in changes {
+        let name = repository.relative_path(&change.path).display().to_string();
+        let old = side_content(repository, change.old.as_ref(), None)?;
+        let new = side_content(
+            repository,
+            change.new.as_ref(),
+            from_worktree.then_some(change.path.as_path()),
+        )?;
+
+        writeln!(output, "diff --gitrs a/{0} b/{0}", name)?;
+        match (&change.old, &change.new) {
+            (None, Some((mode, _))) => writeln!(output, "new file mode {:06o}", mode)?,
+            (Some((mode, _)), None) => writeln!(output, "deleted file mode {:06o}", mode)?,
+            (Some((old_mode, _)), Some((new_mode, _))) if old_mode != new_mode => writeln!(
+                output,
+                "old mode {:06o}\nnew mode {:06o}",
+                old_mode, new_mode
+            )?,
+            _ => {}
+        }
+
+        if old.contains(&0) || new.contains(&0) {
+            writeln!(output, "Binary files differ")?;
+            continue;
+        }
+
+        let label = |prefix: &str, side: &Option<Content>| match side {
+            Some(_) => format!("{}/{}", prefix, name),
+            None => "/dev/null".to_string(),
+        };
+        writeln!(output, "--- {}", label("a", &change.old))?;
+        writeln!(output, "+++ {}", label("b", &change.new))?;
+
+        for hunk in diff::diff(&old, &new, CONTEXT_LINES) {
+            write!(output, "{}", hunk)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the content to diff for one side of a change, reading it from `worktree_path` if
+/// given and from the object store otherwise.
+fn side_content(
+    repository: &Repository,
+    side: Option<&Content>,
+    worktree_path: Option<&Path>,
+) -> anyhow::Result<Vec<u8>> {
+    let Some((mode, sha)) = side else {
+        return Ok(Vec::new());
+    };
+
+    if *mode == MODE_GITLINK {
+        return Ok(format!("Subproject commit {}\n", sha).into_bytes());
+    }
+    if let Some(path) = worktree_path {
+        return Ok(worktree::read_file(path)?.1);
+    }
+
+    match GitrsObject::read(repository, sha)? {
+        GitrsObject::BlobObject(blob) => Ok(blob.into_data()),
+        other => bail!("Expected blob at {}, found {}", sha, other.get_type()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{index::AddOptions, testing::TempRepo};
+
+    fn stage(repo: &TempRepo, paths: &[&str]) -> Index {
+        let mut index = Index::read(repo).unwrap();
+        let paths: Vec<PathBuf> = paths.iter().map(|path| repo.path(path)).collect();
+        index.add(repo, &paths, &AddOptions::default()).unwrap();
+        index
+    }
+
+    fn commit(repo: &TempRepo) {
+        fs::write(
+            repo.gitdir.join("config"),
+            "[user]\n\tname = Test\n\temail = test@example.com\n",
+        )
+        .unwrap();
+        repo.commit("test\n").unwrap().unwrap();
+    }
+
+    fn paths(changes: &[Change]) -> Vec<PathBuf> {
+        changes.iter().map(|change| change.path.clone()).collect()
+    }
+
+    #[test]
+    fn unborn_branch_counts_everything_staged_as_added() {
+        let repo = TempRepo::new();
+        repo.write("a", "a\n");
+        let status = Status::read(&repo, &stage(&repo, &["a"])).unwrap();
+
+        assert_eq!(status.branch.as_deref(), Some("master"));
+        assert_eq!(paths(&status.staged), [repo.path("a")]);
+        assert!(matches!(status.staged[0].kind(), ChangeKind::Added));
+        assert!(status.unstaged.is_empty() && status.untracked.is_empty());
+    }
+
+    #[test]
+    fn changes_are_split_into_staged_and_unstaged() {
+        let repo = TempRepo::new();
+        repo.write("a", "a\n");
+        repo.write("b", "b\n");
+        repo.write("c", "c\n");
+        stage(&repo, &[]);
+        commit(&repo);
+
+        repo.write("a", "staged\n");
+        let index = stage(&repo, &["a"]);
+        repo.write("b", "unstaged\n");
+        fs::remove_file(repo.path("c")).unwrap();
+        let status = Status::read(&repo, &index).unwrap();
+
+        assert_eq!(paths(&status.staged), [repo.path("a")]);
+        assert!(matches!(status.staged[0].kind(), ChangeKind::Modified));
+        assert_eq!(paths(&status.unstaged), [repo.path("b"), repo.path("c")]);
+        assert!(matches!(status.unstaged[1].kind(), ChangeKind::Deleted));
+    }
+
+    #[test]
+    fn untracked_directories_are_listed_once() {
+        let repo = TempRepo::new();
+        repo.write("dir/tracked", "t\n");
+        let index = stage(&repo, &["dir/tracked"]);
+        repo.write("dir/new", "n\n");
+        repo.write("dir/sub/x", "x\n");
+        repo.write("dir/sub/deeper/y", "y\n");
+        repo.write("other/z", "z\n");
+        repo.write("top", "top\n");
+
+        let status = Status::read(&repo, &index).unwrap();
+        assert_eq!(
+            status.untracked,
+            [
+                repo.path("dir/new"),
+                repo.path("dir/sub"),
+                repo.path("other"),
+                repo.path("top")
+            ]
+        );
+    }
+
+    #[test]
+    fn a_broken_head_is_an_error() {
+        let repo = TempRepo::new();
+        repo.write("a", "a\n");
+        let index = stage(&repo, &["a"]);
+        fs::write(
+            repo.gitdir.join("refs/heads/master"),
+            "0123456789012345678901234567890123456789\n",
+        )
+        .unwrap();
+
+        assert!(Status::read(&repo, &index).is_err());
+    }
+}
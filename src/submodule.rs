@@ -0,0 +1,588 @@
+// Submodules: nested repositories recorded in the superproject's trees as gitlinks
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    process,
+};
+
+use anyhow::{Context, anyhow, bail};
+
+use crate::{
+    config::{Config, ConfigFile, ConfigScope},
+    index::{Index, MODE_GITLINK, RmOptions, tree_blobs},
+    object::{
+        GitrsObject, ObjectFindOptions, ObjectType,
+        tree::{self, CheckoutOptions},
+    },
+    refs::Ref,
+    repository::{self, Repository},
+    worktree,
+};
+
+/// A submodule declared in `.gitmodules`.
+pub struct Submodule {
+    /// The `<name>` in its `[submodule "<name>"]` section
+    pub name: String,
+    /// Path of the submodule's worktree, relative to the superproject's worktree
+    pub path: PathBuf,
+    /// Where to fetch it from, as written in `.gitmodules`
+    pub url: String,
+}
+
+/// The outcome of `Submodule::update`.
+pub enum SubmoduleUpdate {
+    /// The commit with this hash was checked out
+    CheckedOut(String),
+    /// The recorded commit was already checked out
+    UpToDate,
+}
+
+impl Submodule {
+    /// Reads the submodules declared in `.gitmodules` at the root of the worktree, in order.
+    ///
+    /// Declarations missing a `path` or `url` are skipped.
+    pub fn list(repository: &Repository) -> anyhow::Result<Vec<Self>> {
+        let config = Config::read_file(
+            &repository.worktree.join(".gitmodules"),
+            ConfigScope::File,
+            None,
+        )?;
+
+        let mut names: Vec<&str> = Vec::new();
+        for entry in config.entries() {
+            if entry.section == "submodule"
+                && let Some(name) = &entry.subsection
+                && !names.contains(&name.as_str())
+            {
+                names.push(name);
+            }
+        }
+
+        Ok(names
+            .into_iter()
+            .filter_map(|name| {
+                Some(Self {
+                    name: name.to_string(),
+                    path: PathBuf::from(config.get(&format!("submodule.{}.path", name))?),
+                    url: config.get(&format!("submodule.{}.url", name))?.to_string(),
+                })
+            })
+            .collect())
+    }
+
+    /// Registers the submodule's URL in the repository's config (`submodule.<name>.url`),
+    /// resolving a relative URL against the superproject's worktree.
+    ///
+    /// A URL that is already registered is kept. Returns the URL if it was newly registered.
+    pub fn init(&self, repository: &Repository) -> anyhow::Result<Option<String>> {
+        let key = format!("submodule.{}.url", self.name);
+        if Config::read(Some(repository))?.get(&key).is_some() {
+            return Ok(None);
+        }
+
+        let url = if self.url.starts_with("./") || self.url.starts_with("../") {
+            repository::resolve_path(&repository.worktree.join(&self.url))
+                .display()
+                .to_string()
+        } else {
+            self.url.clone()
+        };
+
+        let mut file = ConfigFile::read(&repository.gitdir.join("config"))?;
+        file.set(&key, &url, false)?;
+        file.save()?;
+
+        Ok(Some(url))
+    }
+
+    /// Checks out the commit the index records for the submodule, cloning it from its
+    /// registered URL (which must be a local gitrs repository) if needed.
+    ///
+    /// Objects the submodule is missing are copied over from the URL's repository. Its tracked
+    /// files are then replaced by the commit's tree and its HEAD is detached at the commit.
+    ///
+    /// The commit is checked out next to the submodule first, so nothing in it is touched
+    /// unless the checkout succeeds. It refuses to replace tracked files with local
+    /// modifications, or to overwrite untracked files.
+    pub fn update(
+        &self,
+        repository: &Repository,
+        index: &Index,
+    ) -> anyhow::Result<SubmoduleUpdate> {
+        let url = Config::read(Some(repository))?
+            .get(&format!("submodule.{}.url", self.name))
+            .map(str::to_string)
+            .ok_or_else(|| {
+                anyhow!(
+                    "Submodule '{}' is not initialized, run `submodule init` first",
+                    self.name
+                )
+            })?;
+
+        let worktree = repository.worktree.join(&self.path);
+        let commit = index
+            .entries
+            .iter()
+            .find(|entry| entry.path == worktree && entry.stage == 0 && entry.mode == MODE_GITLINK)
+            .map(|entry| entry.sha.clone())
+            .ok_or_else(|| {
+                anyhow!(
+                    "No commit recorded for submodule path '{}'",
+                    self.path.display()
+                )
+            })?;
+
+        if worktree::submodule_head(&worktree).as_deref() == Some(commit.as_str()) {
+            return Ok(SubmoduleUpdate::UpToDate);
+        }
+
+        if !Path::new(&url).join(".gitrs").is_dir() {
+            bail!(
+                "Can't fetch submodule '{}' from '{}': only local gitrs repositories are supported",
+                self.name,
+                url
+            );
+        }
+        let source = Repository::new(Path::new(&url))?;
+
+        let created_dir = fs::symlink_metadata(&worktree).is_err();
+        fs::create_dir_all(&worktree)
+            .with_context(|| format!("Failed to create {}", worktree.display()))?;
+
+        // A repository created here is removed again if the update fails, leaving the
+        // submodule as it was
+        let fresh = !worktree.join(".gitrs").is_dir();
+        let result = if fresh {
+            Repository::init(&worktree)
+        } else {
+            Repository::new(&worktree)
+        }
+        .and_then(|submodule| self.check_out(&submodule, &source, &url, &commit));
+        if result.is_err() && fresh {
+            let _ = fs::remove_dir_all(worktree.join(".gitrs"));
+            if created_dir {
+                let _ = fs::remove_dir(&worktree);
+            }
+        }
+        result?;
+
+        Ok(SubmoduleUpdate::CheckedOut(commit))
+    }
+
+    /// Checks out `commit` in the submodule's repository, copying over the objects it needs
+    /// from `source` (found at `url`).
+    fn check_out(
+        &self,
+        submodule: &Repository,
+        source: &Repository,
+        url: &str,
+        commit: &str,
+    ) -> anyhow::Result<()> {
+        copy_objects(source, submodule)?;
+
+        let tree = GitrsObject::find(
+            submodule,
+            commit,
+            Some(ObjectFindOptions {
+                object_type: ObjectType::Tree,
+                should_follow: true,
+            }),
+        )
+        .with_context(|| format!("Commit {} not found in '{}'", commit, url))?;
+        let GitrsObject::TreeObject(tree_obj) = GitrsObject::read(submodule, &tree)? else {
+            bail!("Expected a tree at {}", tree);
+        };
+
+        let mut submodule_index = Index::read(submodule)
+            .with_context(|| format!("Couldn't read the index of submodule '{}'", self.name))?;
+
+        let staging = submodule.worktree.with_file_name(format!(
+            ".{}.update-{}",
+            submodule
+                .worktree
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy(),
+            process::id()
+        ));
+        for dir in [&staging, &aside_dir(&staging)] {
+            if fs::symlink_metadata(dir).is_ok() {
+                fs::remove_dir_all(dir)
+                    .with_context(|| format!("Failed to remove stale {}", dir.display()))?;
+            }
+        }
+        tree_obj.checkout(submodule, &staging, &CheckoutOptions::default())?;
+
+        let result = self.replace_files(submodule, &mut submodule_index, &staging, &tree);
+        if result.is_err() {
+            let _ = fs::remove_dir_all(&staging);
+        }
+        result?;
+        submodule_index.read_tree(submodule, &tree, None)?;
+        Ref::create_at(submodule, commit, &["HEAD"])
+    }
+
+    /// Swaps the submodule's tracked files for the checkout of `tree` in `staging`.
+    ///
+    /// The checkout is moved in first, setting aside the tracked files in its way so they can
+    /// be put back if that fails; tracked files the tree doesn't have are removed last.
+    fn replace_files(
+        &self,
+        submodule: &Repository,
+        index: &mut Index,
+        staging: &Path,
+        tree: &str,
+    ) -> anyhow::Result<()> {
+        let GitrsObject::TreeObject(tree_obj) = GitrsObject::read(submodule, tree)? else {
+            bail!("Expected a tree at {}", tree);
+        };
+        let tracked: Vec<PathBuf> = index
+            .entries
+            .iter()
+            .map(|entry| entry.path.clone())
+            .collect();
+        let tracked_set: HashSet<&Path> = tracked.iter().map(PathBuf::as_path).collect();
+
+        for record in &tree_obj.records {
+            let target = submodule.worktree.join(&record.path);
+            if !cleared_by_rm(&target, &tracked_set) {
+                bail!(
+                    "Untracked file '{}' in submodule '{}' would be overwritten by checkout",
+                    submodule.relative_path(&target).display(),
+                    self.name
+                );
+            }
+        }
+        for entry in &index.entries {
+            if !entry.matches_worktree()? {
+                bail!(
+                    "'{}' in submodule '{}' has local modifications",
+                    submodule.relative_path(&entry.path).display(),
+                    self.name
+                );
+            }
+        }
+
+        let aside = aside_dir(staging);
+        fs::create_dir(&aside).with_context(|| format!("Failed to create {}", aside.display()))?;
+        let mut set_aside = Vec::new();
+        let mut result = Ok(());
+        for record in &tree_obj.records {
+            let (target, moved) = (
+                submodule.worktree.join(&record.path),
+                aside.join(&record.path),
+            );
+            if fs::symlink_metadata(&target).is_err() {
+                continue;
+            }
+            if let Err(e) = fs::rename(&target, &moved) {
+                result = Err(e).with_context(|| format!("Failed to move {}", target.display()));
+                break;
+            }
+            set_aside.push((target, moved));
+        }
+
+        let result = result
+            .and_then(|_| tree::move_into_place(staging, &submodule.worktree, &tree_obj.records));
+        if result.is_err() {
+            for (target, moved) in set_aside.iter().rev() {
+                let _ = fs::rename(moved, target);
+            }
+        }
+        let _ = fs::remove_dir_all(&aside);
+        result?;
+
+        let checked_out = tree_blobs(submodule, tree)?;
+        let stale: Vec<PathBuf> = tracked
+            .into_iter()
+            .filter(|path| !checked_out.contains_key(submodule.relative_path(path)))
+            .collect();
+        if !stale.is_empty() {
+            index.rm(
+                submodule,
+                &stale,
+                &RmOptions {
+                    cached: false,
+                    force: true,
+                    recursive: true,
+                },
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Where `replace_files` sets aside the files a checkout staged at `staging` replaces.
+fn aside_dir(staging: &Path) -> PathBuf {
+    let mut name = staging.as_os_str().to_os_string();
+    name.push(".old");
+    PathBuf::from(name)
+}
+
+/// Returns true if removing the tracked files would leave nothing at `path`.
+fn cleared_by_rm(path: &Path, tracked: &HashSet<&Path>) -> bool {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return true;
+    };
+
+    if !metadata.is_dir() {
+        return tracked.contains(path);
+    }
+    // A gitlink's directory is only removed if it is empty
+    if tracked.contains(path) {
+        return repository::is_empty_dir(path);
+    }
+
+    fs::read_dir(path).is_ok_and(|entries| {
+        entries
+            .flatten()
+            .all(|entry| cleared_by_rm(&entry.path(), tracked))
+    })
+}
+
+/// Copies the loose objects `destination` is missing from `source`, returning how many.
+fn copy_objects(source: &Repository, destination: &Repository) -> anyhow::Result<usize> {
+    let mut copied = 0;
+
+    for sha in GitrsObject::list_all(source)? {
+        let target = destination
+            .gitdir
+            .join("objects")
+            .join(&sha[..2])
+            .join(&sha[2..]);
+        if target.exists() {
+            continue;
+        }
+
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(
+            source
+                .gitdir
+                .join("objects")
+                .join(&sha[..2])
+                .join(&sha[2..]),
+            &target,
+        )
+        .with_context(|| format!("Failed to copy object {}", sha))?;
+        copied += 1;
+    }
+
+    Ok(copied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        index::{AddOptions, IndexEntry},
+        status::{self, Status},
+        testing::TempRepo,
+    };
+
+    /// Commits `files` (name and content pairs) on top of the repository's HEAD, replacing
+    /// whatever was tracked, and returns the commit's hash.
+    fn commit(repo: &TempRepo, files: &[(&str, &str)]) -> String {
+        fs::write(
+            repo.gitdir.join("config"),
+            "[user]\n\tname = Test\n\temail = test@example.com\n",
+        )
+        .unwrap();
+        let mut index = Index::read(repo).unwrap();
+        let tracked: Vec<PathBuf> = index.entries.iter().map(|e| e.path.clone()).collect();
+        if !tracked.is_empty() {
+            let options = RmOptions {
+                cached: false,
+                force: true,
+                recursive: true,
+            };
+            index.rm(repo, &tracked, &options).unwrap();
+        }
+        for (name, content) in files {
+            repo.write(name, content);
+        }
+        index.add(repo, &[], &AddOptions::default()).unwrap();
+        repo.commit("commit\n").unwrap().unwrap().hash
+    }
+
+    /// A superproject recording `commit` of `source` as the submodule at `sub`, initialized.
+    fn superproject(source: &TempRepo, commit: &str) -> (TempRepo, Submodule, Index) {
+        let repo = TempRepo::new();
+        repo.write(
+            ".gitmodules",
+            &format!(
+                "[submodule \"sub\"]\n\tpath = sub\n\turl = {}\n",
+                source.worktree.display()
+            ),
+        );
+        let mut index = Index::default();
+        index.upsert(
+            IndexEntry::builder()
+                .mtime(std::time::UNIX_EPOCH)
+                .sha(commit)
+                .size_in_bytes(0)
+                .mode(MODE_GITLINK)
+                .path(repo.path("sub"))
+                .build(),
+        );
+        index.write(&repo).unwrap();
+
+        let submodule = Submodule::list(&repo).unwrap().remove(0);
+        submodule.init(&repo).unwrap();
+        (repo, submodule, index)
+    }
+
+    fn read(path: PathBuf) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn init_registers_urls_from_gitmodules() {
+        let repo = TempRepo::new();
+        repo.write(
+            ".gitmodules",
+            "[submodule \"lib\"]\n\tpath = vendor/lib\n\turl = ../lib\n\
+             [submodule \"abs\"]\n\tpath = abs\n\turl = /srv/abs\n\
+             [submodule \"no-url\"]\n\tpath = nowhere\n",
+        );
+
+        let submodules = Submodule::list(&repo).unwrap();
+        let names: Vec<&str> = submodules.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["lib", "abs"]);
+        assert_eq!(submodules[0].path, Path::new("vendor/lib"));
+
+        let expected = repo.worktree.parent().unwrap().join("lib");
+        assert_eq!(
+            submodules[0].init(&repo).unwrap(),
+            Some(expected.display().to_string())
+        );
+        assert_eq!(
+            submodules[1].init(&repo).unwrap().as_deref(),
+            Some("/srv/abs")
+        );
+        // Already registered URLs are kept
+        assert_eq!(submodules[0].init(&repo).unwrap(), None);
+
+        let config =
+            Config::read_file(&repo.gitdir.join("config"), ConfigScope::Local, None).unwrap();
+        assert_eq!(
+            config.get("submodule.lib.url"),
+            Some(expected.display().to_string().as_str())
+        );
+    }
+
+    #[test]
+    fn update_checks_out_the_recorded_commit() {
+        let source = TempRepo::new();
+        let first = commit(&source, &[("a", "one\n"), ("old/x", "x\n")]);
+        let second = commit(&source, &[("a", "two\n"), ("new/y", "y\n")]);
+
+        let (repo, submodule, mut index) = superproject(&source, &first);
+        assert!(matches!(
+            submodule.update(&repo, &index).unwrap(),
+            SubmoduleUpdate::CheckedOut(hash) if hash == first
+        ));
+        assert_eq!(read(repo.path("sub/a")), "one\n");
+        assert_eq!(
+            worktree::submodule_head(&repo.path("sub")),
+            Some(first.clone())
+        );
+        assert!(matches!(
+            submodule.update(&repo, &index).unwrap(),
+            SubmoduleUpdate::UpToDate
+        ));
+
+        // Moving the gitlink on replaces the files, dropping those the commit doesn't have
+        index.entries[0].sha = second.clone();
+        submodule.update(&repo, &index).unwrap();
+        assert_eq!(read(repo.path("sub/a")), "two\n");
+        assert_eq!(read(repo.path("sub/new/y")), "y\n");
+        assert!(!repo.path("sub/old").exists());
+        assert_eq!(worktree::submodule_head(&repo.path("sub")), Some(second));
+
+        let leftovers: Vec<_> = fs::read_dir(&repo.worktree)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .filter(|name| name.to_string_lossy().starts_with(".sub."))
+            .collect();
+        assert!(leftovers.is_empty(), "{leftovers:?}");
+    }
+
+    #[test]
+    fn update_refuses_to_overwrite_local_changes() {
+        let source = TempRepo::new();
+        let first = commit(&source, &[("a", "one\n"), ("b", "b\n")]);
+        let second = commit(&source, &[("a", "two\n"), ("b", "b\n"), ("c", "c\n")]);
+        let (repo, submodule, mut index) = superproject(&source, &first);
+        submodule.update(&repo, &index).unwrap();
+        index.entries[0].sha = second;
+
+        // A modified tracked file
+        repo.write("sub/b", "mine\n");
+        assert!(submodule.update(&repo, &index).is_err());
+        assert_eq!(read(repo.path("sub/a")), "one\n");
+        assert_eq!(read(repo.path("sub/b")), "mine\n");
+        repo.write("sub/b", "b\n");
+
+        // An untracked file in the way
+        repo.write("sub/c", "untracked\n");
+        assert!(submodule.update(&repo, &index).is_err());
+        assert_eq!(read(repo.path("sub/a")), "one\n");
+        assert_eq!(read(repo.path("sub/c")), "untracked\n");
+        assert_eq!(worktree::submodule_head(&repo.path("sub")), Some(first));
+    }
+
+    #[test]
+    fn failed_first_update_leaves_no_repository_behind() {
+        let source = TempRepo::new();
+        commit(&source, &[("a", "one\n")]);
+        let missing = "0123456789012345678901234567890123456789";
+        let (repo, submodule, index) = superproject(&source, missing);
+
+        assert!(submodule.update(&repo, &index).is_err());
+        assert!(!repo.path("sub").exists());
+    }
+
+    #[test]
+    fn status_and_diff_show_a_moved_gitlink() {
+        let source = TempRepo::new();
+        let first = commit(&source, &[("a", "one\n")]);
+        let (repo, submodule, index) = superproject(&source, &first);
+        submodule.update(&repo, &index).unwrap();
+        assert!(Status::read(&repo, &index).unwrap().unstaged.is_empty());
+
+        // New commits in the submodule show as a change to the gitlink
+        let submodule_repo = Repository::new(&repo.path("sub")).unwrap();
+        fs::write(
+            submodule_repo.gitdir.join("config"),
+            "[user]\n\tname = Test\n\temail = test@example.com\n",
+        )
+        .unwrap();
+        repo.write("sub/a", "two\n");
+        let mut sub_index = Index::read(&submodule_repo).unwrap();
+        sub_index
+            .add(&submodule_repo, &[], &AddOptions::default())
+            .unwrap();
+        let second = submodule_repo.commit("two\n").unwrap().unwrap().hash;
+
+        let status = Status::read(&repo, &index).unwrap();
+        assert_eq!(status.unstaged.len(), 1);
+        assert!(status.unstaged[0].is_submodule());
+        assert_eq!(status.unstaged[0].new, Some((MODE_GITLINK, second.clone())));
+
+        let mut diff = Vec::new();
+        status::write_diff(&repo, &status.unstaged, true, &mut diff).unwrap();
+        let diff = String::from_utf8(diff).unwrap();
+        assert!(
+            diff.contains(&format!("-Subproject commit {first}\n")),
+            "{diff}"
+        );
+        assert!(
+            diff.contains(&format!("+Subproject commit {second}\n")),
+            "{diff}"
+        );
+    }
+}
@@ -0,0 +1,58 @@
+// Helpers shared by the unit tests
+use std::{
+    fs,
+    ops::Deref,
+    path::PathBuf,
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::repository::Repository;
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// A repository initialized in a fresh temporary directory, removed again when dropped.
+pub struct TempRepo {
+    repository: Repository,
+    root: PathBuf,
+}
+
+impl TempRepo {
+    pub fn new() -> Self {
+        let root = std::env::temp_dir().join(format!(
+            "gitrs-test-{}-{}",
+            process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&root).unwrap();
+        let repository = Repository::init(&root).unwrap();
+        Self { repository, root }
+    }
+
+    /// Absolute path of `name` in the worktree.
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.repository.worktree.join(name)
+    }
+
+    /// Writes a worktree file, creating its parent directories.
+    pub fn write(&self, name: &str, content: &str) -> PathBuf {
+        let path = self.path(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, content).unwrap();
+        path
+    }
+}
+
+impl Deref for TempRepo {
+    type Target = Repository;
+
+    fn deref(&self) -> &Repository {
+        &self.repository
+    }
+}
+
+impl Drop for TempRepo {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
@@ -0,0 +1,130 @@
+// Utilities for walking the files checked out in a repository's worktree
+use std::{
+    ffi::OsStr,
+    fs::{self, File, Metadata, Permissions},
+    io::{self, Read},
+    os::unix::{
+        ffi::{OsStrExt, OsStringExt},
+        fs::{PermissionsExt, symlink},
+    },
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+
+use crate::{
+    ignore::IgnoreRules,
+    index::{MODE_EXECUTABLE, MODE_FILE, MODE_SYMLINK},
+    refs::Ref,
+    repository::Repository,
+};
+
+/// Recursively collects the files under `path` (or `path` itself if it is a file).
+///
+/// The `.gitrs` directory is always skipped, and nested repositories (submodules) are returned
+/// as a single directory entry rather than walked. When `rules` is provided, files and
+/// directories excluded by them are skipped as well. Returned paths are absolute and sorted.
+pub fn walk(
+    repository: &Repository,
+    path: &Path,
+    rules: Option<&IgnoreRules>,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    walk_into(repository, path, rules, &mut files)?;
+    files.sort();
+    Ok(files)
+}
+
+fn walk_into(
+    repository: &Repository,
+    path: &Path,
+    rules: Option<&IgnoreRules>,
+    files: &mut Vec<PathBuf>,
+) -> anyhow::Result<()> {
+    if path == repository.gitdir || path.file_name().is_some_and(|name| name == ".gitrs") {
+        return Ok(());
+    }
+
+    let metadata =
+        fs::symlink_metadata(path).with_context(|| format!("Failed to stat {}", path.display()))?;
+
+    if !metadata.is_dir() || (path != repository.worktree && path.join(".gitrs").is_dir()) {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+
+    for entry in
+        fs::read_dir(path).with_context(|| format!("Failed to read dir: {}", path.display()))?
+    {
+        let entry_path = entry?.path();
+        if rules.is_some_and(|rules| rules.is_ignored(&entry_path)) {
+            continue;
+        }
+        walk_into(repository, &entry_path, rules, files)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the commit checked out in the submodule at `path`, if it is a repository with one.
+pub fn submodule_head(path: &Path) -> Option<String> {
+    if !path.join(".gitrs").is_dir() {
+        return None;
+    }
+    Ref::resolve(&Repository::new(path).ok()?, &["HEAD"]).ok()
+}
+
+/// Reads a worktree file as it would be staged, returning its mode and blob content.
+///
+/// Symlinks are not followed: their content is the link target. Files with any executable bit
+/// set get mode `100755`.
+pub fn read_file(path: &Path) -> anyhow::Result<(u32, Vec<u8>)> {
+    let metadata =
+        fs::symlink_metadata(path).with_context(|| format!("Failed to stat {}", path.display()))?;
+
+    if metadata.file_type().is_symlink() {
+        let target =
+            fs::read_link(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        return Ok((MODE_SYMLINK, target.into_os_string().into_vec()));
+    }
+
+    let data = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok((file_mode(&metadata), data))
+}
+
+/// Returns the mode a regular file is staged with: `100755` if any executable bit is set.
+pub fn file_mode(metadata: &Metadata) -> u32 {
+    if metadata.permissions().mode() & 0o111 != 0 {
+        MODE_EXECUTABLE
+    } else {
+        MODE_FILE
+    }
+}
+
+/// Writes blob content to the worktree as a file of the given mode, the reverse of `read_file`.
+///
+/// The content is copied from `content` as it is read. Symlinks are created pointing at the
+/// content, or written as a plain file holding it when `symlinks` is false (`core.symlinks`).
+pub fn write_file<R: Read>(
+    path: &Path,
+    mode: u32,
+    mut content: R,
+    symlinks: bool,
+) -> anyhow::Result<()> {
+    if mode == MODE_SYMLINK && symlinks {
+        let mut target = Vec::new();
+        content.read_to_end(&mut target)?;
+        return symlink(OsStr::from_bytes(&target), path)
+            .with_context(|| format!("Failed to create symlink {}", path.display()));
+    }
+
+    File::create(path)
+        .and_then(|mut file| io::copy(&mut content, &mut file))
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    if mode == MODE_EXECUTABLE {
+        fs::set_permissions(path, Permissions::from_mode(0o755))
+            .with_context(|| format!("Failed to make {} executable", path.display()))?;
+    }
+
+    Ok(())
+}
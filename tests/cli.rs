@@ -0,0 +1,320 @@
+// Runs the `git-rs` binary against throwaway repositories
+use std::{
+    fs,
+    io::Write,
+    path::PathBuf,
+    process::{self, Command, Output, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+const EMPTY_BLOB: &str = "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391";
+const HELLO_BLOB: &str = "ce013625030ba8dba906f756967f9e9ca394464a";
+const EMPTY_TREE: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// A temporary directory holding a fresh repository, removed again when dropped.
+struct Sandbox {
+    root: PathBuf,
+}
+
+impl Sandbox {
+    fn new() -> Self {
+        let root = std::env::temp_dir().join(format!(
+            "gitrs-cli-{}-{}",
+            process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&root).unwrap();
+        let sandbox = Self { root };
+        assert!(sandbox.run(&["init", "."], "").status.success());
+        sandbox
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.root.join(name)
+    }
+
+    fn write(&self, name: &str, content: &str) {
+        let path = self.path(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    fn run(&self, args: &[&str], stdin: &str) -> Output {
+        self.run_in("", args, stdin)
+    }
+
+    /// Runs the binary in a directory of the sandbox with `stdin` as its input, isolated from
+    /// the user's configuration and with a fixed identity and date.
+    fn run_in(&self, dir: &str, args: &[&str], stdin: &str) -> Output {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_git-rs"))
+            .args(args)
+            .current_dir(self.root.join(dir))
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env("GIT_CONFIG_GLOBAL", self.root.join("global.cfg"))
+            .env_remove("RUST_LOG")
+            .envs(["AUTHOR", "COMMITTER"].into_iter().flat_map(|role| {
+                [
+                    (format!("GIT_{role}_NAME"), "Test"),
+                    (format!("GIT_{role}_EMAIL"), "test@example.com"),
+                    (format!("GIT_{role}_DATE"), "1527025023 +0000"),
+                ]
+            }))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(stdin.as_bytes())
+            .unwrap();
+        child.wait_with_output().unwrap()
+    }
+
+    fn stdout(&self, args: &[&str], stdin: &str) -> String {
+        self.stdout_in("", args, stdin)
+    }
+
+    /// Runs the binary in a directory of the sandbox, expecting it to succeed, and returns its
+    /// standard output.
+    fn stdout_in(&self, dir: &str, args: &[&str], stdin: &str) -> String {
+        let output = self.run_in(dir, args, stdin);
+        assert!(
+            output.status.success(),
+            "{args:?} failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    fn has_object(&self, sha: &str) -> bool {
+        self.path(".gitrs/objects")
+            .join(&sha[..2])
+            .join(&sha[2..])
+            .is_file()
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+#[test]
+fn hash_object_matches_git() {
+    let sandbox = Sandbox::new();
+    sandbox.write("empty", "");
+    sandbox.write("hello", "hello\n");
+
+    let output = sandbox.stdout(&["hash-object", "empty", "hello"], "");
+    assert_eq!(output, format!("{EMPTY_BLOB}\n{HELLO_BLOB}\n"));
+    assert!(!sandbox.has_object(EMPTY_BLOB));
+
+    let output = sandbox.stdout(&["hash-object", "--stdin"], "hello\n");
+    assert_eq!(output, format!("{HELLO_BLOB}\n"));
+}
+
+#[test]
+fn hash_object_writes_with_w() {
+    let sandbox = Sandbox::new();
+    sandbox.write("hello", "hello\n");
+
+    let output = sandbox.stdout(&["hash-object", "-w", "hello"], "");
+    assert_eq!(output, format!("{HELLO_BLOB}\n"));
+    assert!(sandbox.has_object(HELLO_BLOB));
+    assert_eq!(
+        sandbox.stdout(&["cat-file", "-p", HELLO_BLOB], ""),
+        "hello\n"
+    );
+
+    sandbox.stdout(&["hash-object", "-w", "--stdin"], "");
+    assert!(sandbox.has_object(EMPTY_BLOB));
+}
+
+#[test]
+fn hash_object_checks_other_types() {
+    let sandbox = Sandbox::new();
+    let commit = "tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+                  author A <a@b> 0 +0000\n\
+                  committer A <a@b> 0 +0000\n\
+                  \n\
+                  m\n";
+
+    let output = sandbox.stdout(&["hash-object", "-t", "tree", "--stdin"], "");
+    assert_eq!(output, format!("{EMPTY_TREE}\n"));
+    let output = sandbox.stdout(&["hash-object", "-t", "commit", "--stdin"], commit);
+    assert_eq!(output, "09c9dfe00ddfa1db2b5ba1953e2536a5e84b21da\n");
+
+    sandbox.write("bad", "not a commit\n");
+    assert!(
+        !sandbox
+            .run(&["hash-object", "-t", "commit", "bad"], "")
+            .status
+            .success()
+    );
+    let output = sandbox.stdout(&["hash-object", "-t", "commit", "--literally", "bad"], "");
+    assert_eq!(output, "fcd4989c0b35a94fc0ab7a3c52a38a4edcf9b41a\n");
+}
+
+/// A sandbox with `top`, `sub/x`, `sub/deep/y` and `other/z` committed.
+fn committed_tree() -> Sandbox {
+    let sandbox = Sandbox::new();
+    sandbox.write("top", "a\n");
+    sandbox.write("sub/x", "b\n");
+    sandbox.write("sub/deep/y", "c\n");
+    sandbox.write("other/z", "d\n");
+    sandbox.stdout(&["add", "."], "");
+    sandbox.stdout(&["commit", "-m", "files"], "");
+    sandbox
+}
+
+#[test]
+fn ls_tree_listing_modes() {
+    let sandbox = committed_tree();
+    let ls = |args: &[&str]| {
+        let mut args = [&["ls-tree", "--name-only"], args].concat();
+        args.push("HEAD");
+        sandbox.stdout(&args, "")
+    };
+
+    assert_eq!(ls(&[]), "other\nsub\ntop\n");
+    assert_eq!(ls(&["-r"]), "other/z\nsub/deep/y\nsub/x\ntop\n");
+    assert_eq!(
+        ls(&["-r", "-t"]),
+        "other\nother/z\nsub\nsub/deep\nsub/deep/y\nsub/x\ntop\n"
+    );
+    assert_eq!(ls(&["-d"]), "other\nsub\n");
+    assert_eq!(ls(&["-r", "-d"]), "other\nsub\nsub/deep\n");
+
+    assert_eq!(
+        sandbox.stdout(&["ls-tree", "HEAD", "top"], ""),
+        "100644 blob 78981922613b2afb6025042ff6bd878ac1994e85\ttop\n"
+    );
+}
+
+#[test]
+fn ls_tree_paths_are_relative_to_the_current_directory() {
+    let sandbox = committed_tree();
+    let ls = |args: &[&str]| {
+        sandbox.stdout_in(
+            "sub",
+            &[&["ls-tree", "--name-only", "HEAD"], args].concat(),
+            "",
+        )
+    };
+
+    assert_eq!(ls(&[]), "deep\nx\n");
+    assert_eq!(ls(&["x"]), "x\n");
+    assert_eq!(ls(&["deep"]), "deep\n");
+    assert_eq!(ls(&["deep/"]), "deep/y\n");
+    assert_eq!(ls(&["../top", "deep/y"]), "deep/y\n../top\n");
+    assert_eq!(ls(&["../other/"]), "../other/z\n");
+    assert!(
+        !sandbox
+            .run_in("sub", &["ls-tree", "HEAD", "../../x"], "")
+            .status
+            .success()
+    );
+
+    let json = sandbox.stdout_in("sub", &["--json", "ls-tree", "HEAD", "x"], "");
+    assert!(json.contains("\"path\":\"sub/x\""), "{json}");
+}
+
+#[test]
+fn write_tree_and_commit_tree_match_git() {
+    let sandbox = Sandbox::new();
+    sandbox.write("a", "a\n");
+    sandbox.write("dir/b", "b\n");
+    sandbox.write("dir/c", "c\n");
+    sandbox.stdout(&["add", "."], "");
+
+    let tree = "5133e6bc499e3fcd41adeb014d20097055b6211e";
+    assert_eq!(sandbox.stdout(&["write-tree"], ""), format!("{tree}\n"));
+
+    let first = "1c4576f27a74c49e19a533a000235bbe0af09d6d";
+    let output = sandbox.stdout(&["commit-tree", tree, "-m", "first"], "");
+    assert_eq!(output, format!("{first}\n"));
+    let output = sandbox.stdout(
+        &[
+            "commit-tree",
+            tree,
+            "-p",
+            first,
+            "-m",
+            "second",
+            "-m",
+            "body",
+        ],
+        "",
+    );
+    assert_eq!(output, "9f7161840b6872e31a04cf73581cdcc9ea39f8c5\n");
+    // The message is read from stdin without -m
+    let output = sandbox.stdout(&["commit-tree", tree], "msg\n");
+    assert_eq!(output, "10c66579d4b375127ebe0ae48ccf2f098c41da75\n");
+
+    assert!(
+        !sandbox
+            .run(&["commit-tree", "nosuch", "-m", "x"], "")
+            .status
+            .success()
+    );
+}
+
+#[test]
+fn check_ignore_verbose_shows_rules_without_logging() {
+    let sandbox = Sandbox::new();
+    sandbox.write(".gitrsignore", "*.log\n!keep.log\n");
+    let expected = ".gitrsignore:1:*.log\ta.log\n\
+                    .gitrsignore:2:!keep.log\tkeep.log\n\
+                    ::\tb.txt\n";
+
+    for args in [
+        &["check-ignore", "-v", "-n", "a.log", "keep.log", "b.txt"][..],
+        &[
+            "-q",
+            "check-ignore",
+            "-v",
+            "-n",
+            "a.log",
+            "keep.log",
+            "b.txt",
+        ],
+        &[
+            "check-ignore",
+            "--verbose",
+            "--non-matching",
+            "a.log",
+            "keep.log",
+            "b.txt",
+        ],
+    ] {
+        let output = sandbox.run(args, "");
+        assert!(output.status.success(), "{args:?}");
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+        assert_eq!(String::from_utf8(output.stderr).unwrap(), "", "{args:?}");
+    }
+
+    // -n needs the check-ignore -v, which the global one isn't
+    assert!(
+        !sandbox
+            .run(&["check-ignore", "-n", "a.log"], "")
+            .status
+            .success()
+    );
+    assert!(
+        !sandbox
+            .run(&["-v", "check-ignore", "-n", "a.log"], "")
+            .status
+            .success()
+    );
+
+    // The global -v still turns on logging, and only lists ignored paths
+    let output = sandbox.run(&["-vv", "check-ignore", "a.log", "keep.log"], "");
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "a.log\n");
+    assert!(!output.stderr.is_empty());
+}
@@ -0,0 +1,160 @@
+// Drives the library API end to end, as a tool depending on the crate would
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process,
+};
+
+use git_rs::{
+    GitrsError, GitrsObject, Index, ObjectType, Ref, Repository, index::AddOptions,
+    status::ChangeKind,
+};
+
+/// A temporary directory, removed again when dropped.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("gitrs-lib-{}-{name}", process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Self(fs::canonicalize(path).unwrap())
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn write(root: &Path, name: &str, content: &str) {
+    let path = root.join(name);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, content).unwrap();
+}
+
+/// Reads the content of the blob at `path` in the tree of the given commit.
+fn blob_at(repository: &Repository, commit: &str, path: &str) -> Vec<u8> {
+    let GitrsObject::CommitObject(commit) = GitrsObject::read(repository, commit).unwrap() else {
+        panic!("{commit} isn't a commit");
+    };
+    let mut hash = commit.tree().unwrap().to_string();
+    for name in path.split('/') {
+        let GitrsObject::TreeObject(tree) = GitrsObject::read(repository, &hash).unwrap() else {
+            panic!("{hash} isn't a tree");
+        };
+        let leaf = tree
+            .records
+            .into_iter()
+            .find(|leaf| leaf.path == Path::new(name));
+        hash = leaf.unwrap_or_else(|| panic!("{path} is missing")).hash;
+    }
+    match GitrsObject::read(repository, &hash).unwrap() {
+        GitrsObject::BlobObject(blob) => blob.into_data(),
+        _ => panic!("{path} isn't a blob"),
+    }
+}
+
+#[test]
+fn init_add_commit_and_read_back() {
+    let dir = TempDir::new("round-trip");
+    let repository = Repository::init(&dir.0).unwrap();
+    write(
+        &repository.gitdir,
+        "config",
+        "[user]\n\tname = Test\n\temail = test@example.com\n",
+    );
+
+    write(&dir.0, "README", "hello\n");
+    write(&dir.0, "src/main.rs", "fn main() {}\n");
+    let found = Repository::find_repository_at(&dir.0.join("src")).unwrap();
+    assert_eq!(found.worktree, repository.worktree);
+    let status = repository.status().unwrap();
+    assert_eq!(status.branch.as_deref(), Some("master"));
+    assert_eq!(status.untracked, [dir.0.join("README"), dir.0.join("src")]);
+
+    let mut index = Index::read(&repository).unwrap();
+    index.add(&repository, &[], &AddOptions::default()).unwrap();
+    index.write(&repository).unwrap();
+    let status = repository.status().unwrap();
+    assert_eq!(status.staged.len(), 2);
+    assert!(
+        status
+            .staged
+            .iter()
+            .all(|change| matches!(change.kind(), ChangeKind::Added))
+    );
+
+    let first = repository.commit("Add a README\n").unwrap().unwrap();
+    assert_eq!(first.target, "refs/heads/master");
+    assert_eq!(Ref::resolve(&repository, &["HEAD"]).unwrap(), first.hash);
+    assert!(repository.status().unwrap().staged.is_empty());
+    // Nothing changed since
+    assert!(repository.commit("Again\n").unwrap().is_none());
+
+    write(&dir.0, "README", "hello again\n");
+    let mut index = Index::read(&repository).unwrap();
+    index
+        .add(&repository, &[dir.0.join("README")], &AddOptions::default())
+        .unwrap();
+    index.write(&repository).unwrap();
+    let second = repository.commit("Update the README\n").unwrap().unwrap();
+
+    // History comes back newest first
+    let history: Vec<_> = repository
+        .log("HEAD")
+        .unwrap()
+        .map(|entry| entry.unwrap())
+        .collect();
+    let hashes: Vec<&str> = history.iter().map(|(hash, _)| hash.as_str()).collect();
+    assert_eq!(hashes, [second.hash.as_str(), first.hash.as_str()]);
+    let (_, latest) = &history[0];
+    assert_eq!(latest.summary(), "Update the README");
+    assert_eq!(latest.parents(), [first.hash.as_str()]);
+    assert_eq!(latest.author().unwrap().email, "test@example.com");
+
+    // The content reads back through the object model
+    assert_eq!(blob_at(&repository, &first.hash, "README"), b"hello\n");
+    assert_eq!(
+        blob_at(&repository, &second.hash, "README"),
+        b"hello again\n"
+    );
+    assert_eq!(
+        blob_at(&repository, &second.hash, "src/main.rs"),
+        b"fn main() {}\n"
+    );
+    let GitrsObject::CommitObject(commit) = GitrsObject::read(&repository, &first.hash).unwrap()
+    else {
+        panic!("not a commit");
+    };
+    let tree = commit.tree().unwrap().to_string();
+    let GitrsObject::TreeObject(tree) = GitrsObject::read(&repository, &tree).unwrap() else {
+        panic!("not a tree");
+    };
+    let names: Vec<_> = tree
+        .records
+        .iter()
+        .map(|leaf| (leaf.file_mode.as_str(), leaf.path.to_str().unwrap()))
+        .collect();
+    assert_eq!(names, [("100644", "README"), ("040000", "src")]);
+    assert_eq!(
+        GitrsObject::read(&repository, &tree.records[0].hash)
+            .unwrap()
+            .get_type(),
+        ObjectType::Blob
+    );
+}
+
+#[test]
+fn errors_carry_their_cause() {
+    let dir = TempDir::new("errors");
+    let repository = Repository::init(&dir.0).unwrap();
+
+    let err = GitrsObject::find(&repository, "HEAD", None).unwrap_err();
+    assert!(matches!(
+        GitrsError::find(&err),
+        Some(GitrsError::ObjectNotFound(_))
+    ));
+    assert!(Repository::init(&dir.0).is_err());
+}